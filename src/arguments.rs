@@ -1,24 +1,90 @@
 use clap;
 
 use std::fs;
+use std::fs::File;
 use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
 use std::io::Write;
 use std::path::PathBuf;
 use std::process;
 use std::rc::Rc;
 
+use database::ChunkingMethod;
+use database::DatabaseFormat;
+use hash_algorithm::*;
+use linux::CompressionType;
+use operations::CHUNK_ALIGNMENT;
+use operations::SourcePolicy;
+
 pub enum Command {
 	Dedupe,
+	Watch,
 	PrintExtents,
+	ConvertDatabase,
+	CheckDatabase,
+}
+
+#[ derive (Clone, Copy, Debug, Eq, PartialEq) ]
+pub enum DefragmentMode {
+	Never,
+	Always,
+	IfFragmented,
+}
+
+impl DefragmentMode {
+
+	pub fn names (
+	) -> & 'static [& 'static str] {
+		& [ "never", "always", "if-fragmented" ]
+	}
+
+	pub fn from_name (
+		name: & str,
+	) -> Option <DefragmentMode> {
+
+		match name {
+			"never" => Some (DefragmentMode::Never),
+			"always" => Some (DefragmentMode::Always),
+			"if-fragmented" => Some (DefragmentMode::IfFragmented),
+			_ => None,
+		}
+
+	}
+
 }
 
 pub struct Arguments {
 	pub command: Command,
 	pub database_path: Option <PathBuf>,
+	pub database_format: DatabaseFormat,
+	pub output_database_path: Option <PathBuf>,
+	pub journal_size_limit: u64,
 	pub minimum_file_size: u64,
 	pub content_hash_batch_size: u64,
 	pub extent_hash_batch_size: u64,
 	pub dedupe_batch_size: u64,
+	pub hash_threads: usize,
+	pub content_hash_algorithm: HashAlgorithm,
+	pub include_patterns: Vec <String>,
+	pub exclude_patterns: Vec <String>,
+	pub use_ignore_files: bool,
+	pub incremental: bool,
+	pub cross_subvolumes: bool,
+	pub skip_readonly_subvolumes: bool,
+	pub dry_run: bool,
+	pub verify_before_dedupe: bool,
+	pub defragment_mode: DefragmentMode,
+	pub defragment_compression: CompressionType,
+	pub defragment_extent_threshold: u32,
+	pub source_policy: SourcePolicy,
+	pub preferred_source_paths: Vec <PathBuf>,
+	pub block_dedupe: bool,
+	pub block_dedupe_minimum_file_size: u64,
+	pub chunking_method: ChunkingMethod,
+	pub chunk_size: u64,
+	pub repair: bool,
+	pub debounce_time: u64,
 	pub root_paths: Vec <Rc <PathBuf>>,
 }
 
@@ -37,56 +103,78 @@ pub fn parse_arguments (
 
 		.subcommand (
 
-			clap::SubCommand::with_name ("dedupe")
-				.about ("Automatically runs all deduplication steps (default)")
+			dedupe_arguments (
+				clap::SubCommand::with_name ("dedupe")
+					.about ("Automatically runs all deduplication steps \
+						(default)"))
+
+		)
+
+		.subcommand (
+
+			dedupe_arguments (
+				clap::SubCommand::with_name ("watch")
+					.about ("Keeps running, deduplicating files in small \
+						batches as they change"))
 
 			.arg (
-				clap::Arg::with_name ("database")
-					.long ("database")
-					.value_name ("PATH")
-					.help ("Database path to store metadata and hashes")
+				clap::Arg::with_name ("debounce")
+					.long ("debounce")
+					.value_name ("SECONDS")
+					.default_value ("10")
+					.help ("How long to wait for changes to stop before \
+						processing them")
 			)
 
+		)
+
+		.subcommand (
+
+			clap::SubCommand::with_name ("convert-database")
+				.about ("Converts a database to a different format")
+
 			.arg (
-				clap::Arg::with_name ("minimum-file-size")
-					.long ("minimum-file-size")
-					.value_name ("SIZE")
-					.default_value ("1KiB")
-					.help ("Minimum file size to consider for deduplication")
+				clap::Arg::with_name ("database-format")
+					.long ("database-format")
+					.value_name ("FORMAT")
+					.possible_values (DatabaseFormat::names ())
+					.required (true)
+					.help ("Format to write the new database in")
 			)
 
 			.arg (
-				clap::Arg::with_name ("content-hash-batch-size")
-					.long ("content-hash-batch-size")
-					.value_name ("SIZE")
-					.default_value ("2GiB")
-					.help ("Amount of file contents data to hash before \
-						writing database")
+				clap::Arg::with_name ("input-path")
+					.value_name ("INPUT")
+					.required (true)
+					.help ("Existing database, in either format")
 			)
 
 			.arg (
-				clap::Arg::with_name ("extent-hash-batch-size")
-					.long ("extent-hash-batch-size")
-					.value_name ("SIZE")
-					.default_value ("512GiB")
-					.help ("Amount of file extent data to hash before writing \
-						database")
+				clap::Arg::with_name ("output-path")
+					.value_name ("OUTPUT")
+					.required (true)
+					.help ("Path to write the converted database to")
 			)
 
+		)
+
+		.subcommand (
+
+			clap::SubCommand::with_name ("check-database")
+				.about ("Checks a database for damage, and optionally repairs it")
+
 			.arg (
-				clap::Arg::with_name ("dedupe-batch-size")
-					.long ("dedupe-batch-size")
-					.value_name ("SIZE")
-					.default_value ("64GiB")
-					.help ("Amount of file data to deduplicate before writing \
-						database")
+				clap::Arg::with_name ("repair")
+					.long ("repair")
+					.help ("Remove or invalidate damaged entries, so they are \
+						recalculated on the next run")
 			)
 
 			.arg (
-				clap::Arg::with_name ("root-path")
-					.multiple (true)
-					.value_name ("PATH")
-					.help ("Root path to scan for files")
+				clap::Arg::with_name ("database-path")
+					.value_name ("DATABASE")
+					.required (true)
+					.help ("Database to check, in any format")
 			)
 
 		)
@@ -110,8 +198,26 @@ pub fn parse_arguments (
 	let argument_matches =
 		application.clone ().get_matches ();
 
-	if let Some (dedupe_matches) =
-		argument_matches.subcommand_matches ("dedupe") {
+	let dedupe_matches_option =
+		argument_matches.subcommand_matches (
+			"dedupe",
+		).map (
+			|dedupe_matches|
+			(Command::Dedupe, dedupe_matches)
+		).or_else (
+			||
+
+			argument_matches.subcommand_matches (
+				"watch",
+			).map (
+				|watch_matches|
+				(Command::Watch, watch_matches)
+			)
+
+		);
+
+	if let Some ((command, dedupe_matches)) =
+		dedupe_matches_option {
 
 		let database_path =
 			dedupe_matches.value_of_os (
@@ -124,6 +230,41 @@ pub fn parse_arguments (
 
 			);
 
+		let database_format =
+			DatabaseFormat::from_name (
+				dedupe_matches.value_of (
+					"database-format",
+				).unwrap (),
+			).unwrap ();
+
+		let journal_size_limit = (
+
+			parse_size (
+				dedupe_matches.value_of (
+					"journal-size-limit",
+				).unwrap ()
+			)
+
+		).map_err (
+			|error|
+
+			clap::Error {
+
+				message:
+					format! (
+						"Can't parse --journal-size-limit: {}",
+						error),
+
+				kind:
+					clap::ErrorKind::InvalidValue,
+
+				info:
+					None,
+
+			}.exit ()
+
+		).unwrap ();
+
 		let content_hash_batch_size = (
 
 			parse_size (
@@ -236,6 +377,294 @@ pub fn parse_arguments (
 
 		).unwrap ();
 
+		let hash_threads = (
+
+			dedupe_matches.value_of (
+				"hash-threads",
+			).unwrap ().parse::<usize> (
+			).map_err (
+				|_|
+				"Unable to parse integer value".to_owned ()
+			).and_then (
+				|hash_threads|
+
+				if hash_threads > 0 {
+					Ok (hash_threads)
+				} else {
+					Err ("Must be at least one".to_owned ())
+				}
+
+			)
+
+		).map_err (
+			|error|
+
+			clap::Error {
+
+				message:
+					format! (
+						"Can't parse --hash-threads: {}",
+						error),
+
+				kind:
+					clap::ErrorKind::InvalidValue,
+
+				info:
+					None,
+
+			}.exit ()
+
+		).unwrap ();
+
+		let content_hash_algorithm =
+			HashAlgorithm::from_name (
+				dedupe_matches.value_of (
+					"content-hash-algorithm",
+				).unwrap (),
+			).unwrap ();
+
+		let defragment_mode =
+			DefragmentMode::from_name (
+				dedupe_matches.value_of (
+					"defragment",
+				).unwrap (),
+			).unwrap ();
+
+		let defragment_compression =
+			CompressionType::from_name (
+				dedupe_matches.value_of (
+					"defragment-compression",
+				).unwrap (),
+			).unwrap ();
+
+		let defragment_extent_threshold = (
+
+			parse_size (
+				dedupe_matches.value_of (
+					"defragment-extent-threshold",
+				).unwrap ()
+			).and_then (
+				|size|
+
+				if size <= u32::MAX as u64 {
+					Ok (size as u32)
+				} else {
+					Err ("Must be less than 4GiB".to_owned ())
+				}
+
+			)
+
+		).map_err (
+			|error|
+
+			clap::Error {
+
+				message:
+					format! (
+						"Can't parse --defragment-extent-threshold: {}",
+						error),
+
+				kind:
+					clap::ErrorKind::InvalidValue,
+
+				info:
+					None,
+
+			}.exit ()
+
+		).unwrap ();
+
+		let source_policy =
+			SourcePolicy::from_name (
+				dedupe_matches.value_of (
+					"source-policy",
+				).unwrap (),
+			).unwrap ();
+
+		let preferred_source_paths: Vec <PathBuf> =
+			dedupe_matches.values_of_os (
+				"prefer-source",
+			).map (
+				|os_values|
+
+				os_values.map (
+					|os_value|
+
+					fs::canonicalize (
+						PathBuf::from (
+							os_value),
+					).unwrap ()
+
+				).collect ()
+
+			).unwrap_or (
+				Vec::new (),
+			);
+
+		let block_dedupe_minimum_file_size = (
+
+			parse_size (
+				dedupe_matches.value_of (
+					"block-dedupe-minimum-file-size",
+				).unwrap ()
+			)
+
+		).map_err (
+			|error|
+
+			clap::Error {
+
+				message:
+					format! (
+						"Can't parse --block-dedupe-minimum-file-size: {}",
+						error),
+
+				kind:
+					clap::ErrorKind::InvalidValue,
+
+				info:
+					None,
+
+			}.exit ()
+
+		).unwrap ();
+
+		let chunking_method =
+			ChunkingMethod::from_name (
+				dedupe_matches.value_of (
+					"chunking",
+				).unwrap (),
+			).unwrap ();
+
+		let chunk_size = (
+
+			parse_size (
+				dedupe_matches.value_of (
+					"chunk-size",
+				).unwrap ()
+			).and_then (
+				|size|
+
+				if size > 0 && size % CHUNK_ALIGNMENT == 0 {
+					Ok (size)
+				} else {
+					Err ("Must be a multiple of 4KiB".to_owned ())
+				}
+
+			)
+
+		).map_err (
+			|error|
+
+			clap::Error {
+
+				message:
+					format! (
+						"Can't parse --chunk-size: {}",
+						error),
+
+				kind:
+					clap::ErrorKind::InvalidValue,
+
+				info:
+					None,
+
+			}.exit ()
+
+		).unwrap ();
+
+		// only the watch command waits for changes
+
+		let debounce_time = (
+
+			dedupe_matches.value_of (
+				"debounce",
+			).unwrap_or ("0").parse::<u64> (
+			).map_err (
+				|_|
+				"Unable to parse integer value".to_owned ()
+			)
+
+		).map_err (
+			|error|
+
+			clap::Error {
+
+				message:
+					format! (
+						"Can't parse --debounce: {}",
+						error),
+
+				kind:
+					clap::ErrorKind::InvalidValue,
+
+				info:
+					None,
+
+			}.exit ()
+
+		).unwrap ();
+
+		let include_patterns: Vec <String> =
+			dedupe_matches.values_of (
+				"include",
+			).map (
+				|values|
+
+				values.map (
+					|value|
+					value.to_owned ()
+				).collect ()
+
+			).unwrap_or (
+				Vec::new (),
+			);
+
+		let mut exclude_patterns: Vec <String> =
+			dedupe_matches.values_of (
+				"exclude",
+			).map (
+				|values|
+
+				values.map (
+					|value|
+					value.to_owned ()
+				).collect ()
+
+			).unwrap_or (
+				Vec::new (),
+			);
+
+		for exclude_from_path in dedupe_matches.values_of_os (
+			"exclude-from",
+		).into_iter ().flatten () {
+
+			exclude_patterns.extend (
+				read_patterns_file (
+					& PathBuf::from (
+						exclude_from_path),
+				).map_err (
+					|error|
+
+					clap::Error {
+
+						message:
+							format! (
+								"Can't read --exclude-from: {}",
+								error),
+
+						kind:
+							clap::ErrorKind::InvalidValue,
+
+						info:
+							None,
+
+					}.exit ()
+
+				).unwrap ());
+
+		}
+
 		let mut root_paths = (
 			dedupe_matches.values_of_os (
 				"root-path",
@@ -262,12 +691,38 @@ pub fn parse_arguments (
 		root_paths.sort ();
 
 		Arguments {
-			command: Command::Dedupe,
+			command: command,
 			database_path: database_path,
+			database_format: database_format,
+			output_database_path: None,
+			journal_size_limit: journal_size_limit,
 			minimum_file_size: minimum_file_size,
 			content_hash_batch_size: content_hash_batch_size,
 			extent_hash_batch_size: extent_hash_batch_size,
 			dedupe_batch_size: dedupe_batch_size,
+			hash_threads: hash_threads,
+			content_hash_algorithm: content_hash_algorithm,
+			include_patterns: include_patterns,
+			exclude_patterns: exclude_patterns,
+			use_ignore_files: ! dedupe_matches.is_present ("no-ignore-files"),
+			incremental: dedupe_matches.is_present ("incremental"),
+			cross_subvolumes: dedupe_matches.is_present ("cross-subvolumes"),
+			skip_readonly_subvolumes:
+				dedupe_matches.is_present ("skip-readonly-subvolumes"),
+			dry_run: dedupe_matches.is_present ("dry-run"),
+			verify_before_dedupe:
+				dedupe_matches.is_present ("verify-before-dedupe"),
+			defragment_mode: defragment_mode,
+			defragment_compression: defragment_compression,
+			defragment_extent_threshold: defragment_extent_threshold,
+			source_policy: source_policy,
+			preferred_source_paths: preferred_source_paths,
+			block_dedupe: dedupe_matches.is_present ("block-dedupe"),
+			block_dedupe_minimum_file_size: block_dedupe_minimum_file_size,
+			chunking_method: chunking_method,
+			chunk_size: chunk_size,
+			repair: false,
+			debounce_time: debounce_time,
 			root_paths: root_paths,
 		}
 
@@ -300,13 +755,130 @@ pub fn parse_arguments (
 		Arguments {
 			command: Command::PrintExtents,
 			database_path: None,
+			database_format: DatabaseFormat::Json,
+			output_database_path: None,
+			journal_size_limit: 0,
 			minimum_file_size: 0,
 			content_hash_batch_size: 0,
 			extent_hash_batch_size: 0,
 			dedupe_batch_size: 0,
+			hash_threads: 0,
+			content_hash_algorithm: HashAlgorithm::Sha256,
+			include_patterns: Vec::new (),
+			exclude_patterns: Vec::new (),
+			use_ignore_files: false,
+			incremental: false,
+			cross_subvolumes: false,
+			skip_readonly_subvolumes: false,
+			dry_run: false,
+			verify_before_dedupe: false,
+			defragment_mode: DefragmentMode::Never,
+			defragment_compression: CompressionType::None,
+			defragment_extent_threshold: 0,
+			source_policy: SourcePolicy::First,
+			preferred_source_paths: Vec::new (),
+			block_dedupe: false,
+			block_dedupe_minimum_file_size: 0,
+			chunking_method: ChunkingMethod::Fixed,
+			chunk_size: 0,
+			repair: false,
+			debounce_time: 0,
 			root_paths: paths,
 		}
 
+	} else if let Some (convert_database_matches) =
+		argument_matches.subcommand_matches ("convert-database") {
+
+		let database_format =
+			DatabaseFormat::from_name (
+				convert_database_matches.value_of (
+					"database-format",
+				).unwrap (),
+			).unwrap ();
+
+		Arguments {
+			command: Command::ConvertDatabase,
+			database_path: Some (
+				PathBuf::from (
+					convert_database_matches.value_of_os (
+						"input-path",
+					).unwrap ())),
+			database_format: database_format,
+			output_database_path: Some (
+				PathBuf::from (
+					convert_database_matches.value_of_os (
+						"output-path",
+					).unwrap ())),
+			journal_size_limit: 0,
+			minimum_file_size: 0,
+			content_hash_batch_size: 0,
+			extent_hash_batch_size: 0,
+			dedupe_batch_size: 0,
+			hash_threads: 0,
+			content_hash_algorithm: HashAlgorithm::Sha256,
+			include_patterns: Vec::new (),
+			exclude_patterns: Vec::new (),
+			use_ignore_files: false,
+			incremental: false,
+			cross_subvolumes: false,
+			skip_readonly_subvolumes: false,
+			dry_run: false,
+			verify_before_dedupe: false,
+			defragment_mode: DefragmentMode::Never,
+			defragment_compression: CompressionType::None,
+			defragment_extent_threshold: 0,
+			source_policy: SourcePolicy::First,
+			preferred_source_paths: Vec::new (),
+			block_dedupe: false,
+			block_dedupe_minimum_file_size: 0,
+			chunking_method: ChunkingMethod::Fixed,
+			chunk_size: 0,
+			repair: false,
+			debounce_time: 0,
+			root_paths: Vec::new (),
+		}
+
+	} else if let Some (check_database_matches) =
+		argument_matches.subcommand_matches ("check-database") {
+
+		Arguments {
+			command: Command::CheckDatabase,
+			database_path: Some (
+				PathBuf::from (
+					check_database_matches.value_of_os (
+						"database-path",
+					).unwrap ())),
+			database_format: DatabaseFormat::Json,
+			output_database_path: None,
+			journal_size_limit: 0,
+			minimum_file_size: 0,
+			content_hash_batch_size: 0,
+			extent_hash_batch_size: 0,
+			dedupe_batch_size: 0,
+			hash_threads: 0,
+			content_hash_algorithm: HashAlgorithm::Sha256,
+			include_patterns: Vec::new (),
+			exclude_patterns: Vec::new (),
+			use_ignore_files: false,
+			incremental: false,
+			cross_subvolumes: false,
+			skip_readonly_subvolumes: false,
+			dry_run: false,
+			verify_before_dedupe: false,
+			defragment_mode: DefragmentMode::Never,
+			defragment_compression: CompressionType::None,
+			defragment_extent_threshold: 0,
+			source_policy: SourcePolicy::First,
+			preferred_source_paths: Vec::new (),
+			block_dedupe: false,
+			block_dedupe_minimum_file_size: 0,
+			chunking_method: ChunkingMethod::Fixed,
+			chunk_size: 0,
+			repair: check_database_matches.is_present ("repair"),
+			debounce_time: 0,
+			root_paths: Vec::new (),
+		}
+
 	} else {
 
 		let stderr =
@@ -315,7 +887,7 @@ pub fn parse_arguments (
 		let mut stderr_lock =
 			stderr.lock ();
 
-		stderr_lock.write (
+		stderr_lock.write_all (
 			b"\n",
 		).unwrap ();
 
@@ -323,7 +895,7 @@ pub fn parse_arguments (
 			& mut stderr_lock,
 		).unwrap ();
 
-		stderr_lock.write (
+		stderr_lock.write_all (
 			b"\n\n",
 		).unwrap ();
 
@@ -333,6 +905,319 @@ pub fn parse_arguments (
 
 }
 
+/// The options shared by the dedupe and watch subcommands, which both run all
+/// of the deduplication steps.
+
+fn dedupe_arguments <'a, 'b> (
+	subcommand: clap::App <'a, 'b>,
+) -> clap::App <'a, 'b> {
+
+	subcommand
+
+	.arg (
+		clap::Arg::with_name ("database")
+			.long ("database")
+			.value_name ("PATH")
+			.help ("Database path to store metadata and hashes")
+	)
+
+	.arg (
+		clap::Arg::with_name ("database-format")
+			.long ("database-format")
+			.value_name ("FORMAT")
+			.possible_values (DatabaseFormat::names ())
+			.default_value ("json")
+			.help ("Format to write the database in, any format can \
+				be read")
+	)
+
+	.arg (
+		clap::Arg::with_name ("journal-size-limit")
+			.long ("journal-size-limit")
+			.value_name ("SIZE")
+			.default_value ("256MiB")
+			.help ("Size the journal of database changes can grow to \
+				before the database is rewritten in full")
+	)
+
+	.arg (
+		clap::Arg::with_name ("minimum-file-size")
+			.long ("minimum-file-size")
+			.value_name ("SIZE")
+			.default_value ("1KiB")
+			.help ("Minimum file size to consider for deduplication")
+	)
+
+	.arg (
+		clap::Arg::with_name ("content-hash-batch-size")
+			.long ("content-hash-batch-size")
+			.value_name ("SIZE")
+			.default_value ("2GiB")
+			.help ("Amount of file contents data to hash before \
+				writing database")
+	)
+
+	.arg (
+		clap::Arg::with_name ("extent-hash-batch-size")
+			.long ("extent-hash-batch-size")
+			.value_name ("SIZE")
+			.default_value ("512GiB")
+			.help ("Amount of file extent data to hash before writing \
+				database")
+	)
+
+	.arg (
+		clap::Arg::with_name ("dedupe-batch-size")
+			.long ("dedupe-batch-size")
+			.value_name ("SIZE")
+			.default_value ("64GiB")
+			.help ("Amount of file data to deduplicate before writing \
+				database")
+	)
+
+	.arg (
+		clap::Arg::with_name ("hash-threads")
+			.long ("hash-threads")
+			.value_name ("THREADS")
+			.default_value ("1")
+			.help ("Number of threads to use when hashing file \
+				contents")
+	)
+
+	.arg (
+		clap::Arg::with_name ("content-hash-algorithm")
+			.long ("content-hash-algorithm")
+			.value_name ("ALGORITHM")
+			.possible_values (HashAlgorithm::names ())
+			.default_value ("sha256")
+			.help ("Algorithm used to hash file contents, changing \
+				this will cause all files to be hashed again")
+	)
+
+	.arg (
+		clap::Arg::with_name ("include")
+			.long ("include")
+			.value_name ("PATTERN")
+			.multiple (true)
+			.number_of_values (1)
+			.help ("Only consider files matching this pattern, may be \
+				given more than once")
+	)
+
+	.arg (
+		clap::Arg::with_name ("exclude")
+			.long ("exclude")
+			.value_name ("PATTERN")
+			.multiple (true)
+			.number_of_values (1)
+			.help ("Ignore files and directories matching this \
+				pattern, may be given more than once")
+	)
+
+	.arg (
+		clap::Arg::with_name ("exclude-from")
+			.long ("exclude-from")
+			.value_name ("FILE")
+			.multiple (true)
+			.number_of_values (1)
+			.help ("Read exclude patterns from a file, one per line")
+	)
+
+	.arg (
+		clap::Arg::with_name ("no-ignore-files")
+			.long ("no-ignore-files")
+			.help ("Don't read .btrfs-dedupe-ignore files from scanned \
+				directories")
+	)
+
+	.arg (
+		clap::Arg::with_name ("incremental")
+			.long ("incremental")
+			.help ("Only look in directories where btrfs reports changes \
+				since the last scan, which needs the same privileges \
+				as btrfs subvolume find-new")
+	)
+
+	.arg (
+		clap::Arg::with_name ("cross-subvolumes")
+			.long ("cross-subvolumes")
+			.help ("Descend into subvolumes and snapshots of the same \
+				filesystem found beneath the root paths, rather than \
+				stopping at them")
+	)
+
+	.arg (
+		clap::Arg::with_name ("skip-readonly-subvolumes")
+			.long ("skip-readonly-subvolumes")
+			.help ("Leave out files in read only subvolumes, such as \
+				snapshots, rather than using them as sources to \
+				deduplicate against")
+	)
+
+	.arg (
+		clap::Arg::with_name ("dry-run")
+			.long ("dry-run")
+			.help ("Scan and hash files, then show what would be \
+				deduplicated without changing anything")
+	)
+
+	.arg (
+		clap::Arg::with_name ("verify-before-dedupe")
+			.long ("verify-before-dedupe")
+			.help ("Compare the contents of each file with its source \
+				byte for byte before deduplicating, even when using a \
+				cryptographic hash")
+	)
+
+	.arg (
+		clap::Arg::with_name ("defragment")
+			.long ("defragment")
+			.value_name ("MODE")
+			.possible_values (DefragmentMode::names ())
+			.default_value ("always")
+			.help ("When to defragment the source file before \
+				deduplicating others against it")
+	)
+
+	.arg (
+		clap::Arg::with_name ("defragment-compression")
+			.long ("defragment-compression")
+			.value_name ("TYPE")
+			.possible_values (CompressionType::names ())
+			.default_value ("lzo")
+			.help ("Compression to apply when defragmenting, any \
+				value other than none causes the entire file to be \
+				rewritten")
+	)
+
+	.arg (
+		clap::Arg::with_name ("defragment-extent-threshold")
+			.long ("defragment-extent-threshold")
+			.value_name ("SIZE")
+			.default_value ("32MiB")
+			.help ("Extents smaller than this are rewritten when \
+				defragmenting, and a file with more than one is \
+				considered fragmented")
+	)
+
+	.arg (
+		clap::Arg::with_name ("source-policy")
+			.long ("source-policy")
+			.value_name ("POLICY")
+			.possible_values (SourcePolicy::names ())
+			.default_value ("first")
+			.help ("How to choose the file in each group of identical \
+				files which the others are deduplicated against")
+	)
+
+	.arg (
+		clap::Arg::with_name ("prefer-source")
+			.long ("prefer-source")
+			.value_name ("PATH")
+			.multiple (true)
+			.number_of_values (1)
+			.help ("Prefer files beneath this path, such as a \
+				particular subvolume, as the source for \
+				deduplication, may be given more than once")
+	)
+
+	.arg (
+		clap::Arg::with_name ("block-dedupe")
+			.long ("block-dedupe")
+			.help ("Also deduplicate identical ranges within large \
+				files which are not identical as a whole")
+	)
+
+	.arg (
+		clap::Arg::with_name ("block-dedupe-minimum-file-size")
+			.long ("block-dedupe-minimum-file-size")
+			.value_name ("SIZE")
+			.default_value ("16MiB")
+			.help ("Minimum file size to consider for block \
+				deduplication")
+	)
+
+	.arg (
+		clap::Arg::with_name ("chunking")
+			.long ("chunking")
+			.value_name ("METHOD")
+			.possible_values (ChunkingMethod::names ())
+			.default_value ("fixed")
+			.help ("How to split files into chunks for block \
+				deduplication, content defined chunks also match \
+				data which has been shifted")
+	)
+
+	.arg (
+		clap::Arg::with_name ("chunk-size")
+			.long ("chunk-size")
+			.value_name ("SIZE")
+			.default_value ("128KiB")
+			.help ("Size, or average size for content defined \
+				chunking, of the chunks hashed for block \
+				deduplication, must be a multiple of 4KiB")
+	)
+
+	.arg (
+		clap::Arg::with_name ("root-path")
+			.multiple (true)
+			.value_name ("PATH")
+			.help ("Root path to scan for files")
+	)
+
+}
+
+fn read_patterns_file (
+	path: & PathBuf,
+) -> Result <Vec <String>, String> {
+
+	let file =
+		try! (
+			File::open (
+				path,
+			).map_err (
+				|io_error|
+
+				format! (
+					"{}: {}",
+					path.to_string_lossy (),
+					io_error)
+
+			));
+
+	let mut patterns: Vec <String> =
+		Vec::new ();
+
+	for line_result in BufReader::new (file).lines () {
+
+		let line =
+			try! (
+				line_result.map_err (
+					|io_error|
+
+					format! (
+						"{}: {}",
+						path.to_string_lossy (),
+						io_error)
+
+				));
+
+		let line =
+			line.trim ();
+
+		if line.is_empty () || line.starts_with ('#') {
+			continue;
+		}
+
+		patterns.push (
+			line.to_owned ());
+
+	}
+
+	Ok (patterns)
+
+}
+
 pub fn parse_size (
 	size_string: & str,
 ) -> Result <u64, String> {
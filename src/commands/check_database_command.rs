@@ -0,0 +1,287 @@
+use output::Output;
+
+use time;
+
+use arguments::*;
+use commands::*;
+use database::*;
+use operations::*;
+
+/// Checks every entry in a database, and the journal alongside it, for damage.
+/// With repair, entries which can't be trusted are removed or invalidated, so
+/// that they are worked out again on the next run, and the database is written
+/// back in the format it was already in.
+
+pub fn check_database_command (
+	output: & Output,
+	arguments: & Arguments,
+) -> Result <(), String> {
+
+	let database_path =
+		arguments.database_path.as_ref ().unwrap ();
+
+	let mut recursive_path_database =
+		RecursivePathDatabase::new ();
+
+	let database_format =
+		DatabaseFormat::detect_file (
+			database_path,
+		).map_err (
+			|error_string|
+
+			format! (
+				"Error reading database: {}",
+				error_string)
+
+		) ?;
+
+	let mut database_storage =
+		open_database_storage (
+			database_path,
+			database_format,
+			0);
+
+	output.message_format (
+		format_args! (
+			"Checking {} database {}",
+			database_format.name (),
+			database_path.to_string_lossy ()));
+
+	let stored_database =
+		database_storage.read_stored (
+			& mut recursive_path_database,
+		).map_err (
+			|error_string|
+
+			format! (
+				"Error reading database: {}",
+				error_string)
+
+		) ?;
+
+	let now =
+		time::get_time ().sec;
+
+	let mut subvolume_locator =
+		SubvolumeLocator::new ();
+
+	// check the database itself
+
+	let file_data_problems =
+		check_file_data_list (
+			& stored_database.file_data_list,
+			stored_database.file_data_ordered,
+			& stored_database.database_header,
+			now,
+			& mut recursive_path_database,
+			& mut subvolume_locator);
+
+	let mut num_problems: u64 = 0;
+	let mut num_damaged: u64 = 0;
+
+	for (file_data, problems) in stored_database.file_data_list.iter ().zip (
+		file_data_problems.iter ()) {
+
+		report_problems (
+			output,
+			file_data,
+			problems,
+			"",
+			& mut num_problems,
+			& mut num_damaged);
+
+	}
+
+	// and the journal, where files only appear once they have changed
+
+	let journal_problems: Vec <Vec <DatabaseProblem>> =
+		stored_database.journal_entries.iter ().map (
+			|journal_entry|
+
+			match * journal_entry {
+
+				JournalEntry::Changed (ref file_data) =>
+					check_file_data (
+						file_data,
+						& stored_database.database_header,
+						now,
+						& mut recursive_path_database,
+						& mut subvolume_locator),
+
+				JournalEntry::Removed (_) =>
+					Vec::new (),
+
+			}
+
+		).collect ();
+
+	for (journal_entry, problems) in stored_database.journal_entries.iter ().zip (
+		journal_problems.iter ()) {
+
+		if let JournalEntry::Changed (ref file_data) = * journal_entry {
+
+			report_problems (
+				output,
+				file_data,
+				problems,
+				" (journal)",
+				& mut num_problems,
+				& mut num_damaged);
+
+		}
+
+	}
+
+	if ! stored_database.journal_complete {
+
+		output.message (
+			"Journal ends with an incomplete entry");
+
+		num_problems += 1;
+
+	}
+
+	let num_files =
+		stored_database.file_data_list.len ();
+
+	if num_problems == 0 {
+
+		output.message_format (
+			format_args! (
+				"No problems found in {} files",
+				num_files));
+
+		return Ok (());
+
+	}
+
+	output.message_format (
+		format_args! (
+			"Found {} problems with {} of {} files",
+			num_problems,
+			num_damaged,
+			num_files));
+
+	if ! arguments.repair {
+
+		return Err (
+			"Database is damaged, use --repair to fix it".to_owned ());
+
+	}
+
+	// remove or invalidate anything which can't be trusted
+
+	let database_header =
+		stored_database.database_header.clone ();
+
+	let StoredDatabase {
+		file_data_list,
+		scan_generations,
+		journal_entries,
+		..
+	} = stored_database;
+
+	let file_data_list: Vec <FileData> =
+		file_data_list.into_iter ().zip (
+			file_data_problems.iter (),
+		).filter_map (
+			|(file_data, problems)|
+
+			repair_file_data (
+				file_data,
+				problems)
+
+		).collect ();
+
+	let journal_entries: Vec <JournalEntry> =
+		journal_entries.into_iter ().zip (
+			journal_problems.iter (),
+		).map (
+			|(journal_entry, problems)|
+
+			match journal_entry {
+
+				JournalEntry::Changed (file_data) => {
+
+					let file_path =
+						file_data.path.clone ();
+
+					match repair_file_data (
+						file_data,
+						problems) {
+
+						Some (file_data) =>
+							JournalEntry::Changed (file_data),
+
+						None =>
+							JournalEntry::Removed (file_path),
+
+					}
+
+				},
+
+				journal_entry =>
+					journal_entry,
+
+			}
+
+		).collect ();
+
+	let file_database =
+		StoredDatabase {
+			database_format: database_format,
+			database_header: database_header,
+			file_data_list: file_data_list,
+			file_data_ordered: false,
+			scan_generations: scan_generations,
+			journal_entries: journal_entries,
+			journal_complete: true,
+		}.into_file_database (
+			& mut recursive_path_database,
+			& []);
+
+	compact_database_in_storage (
+		output,
+		& mut * database_storage,
+		& file_database,
+	) ?;
+
+	output.message_format (
+		format_args! (
+			"Repaired database, which now contains {} files",
+			file_database.len ()));
+
+	Ok (())
+
+}
+
+fn report_problems (
+	output: & Output,
+	file_data: & FileData,
+	problems: & [DatabaseProblem],
+	suffix: & str,
+	num_problems: & mut u64,
+	num_damaged: & mut u64,
+) {
+
+	if problems.is_empty () {
+		return;
+	}
+
+	for problem in problems.iter () {
+
+		output.message_format (
+			format_args! (
+				"{}{}: {}",
+				file_data.path.to_string_lossy (),
+				suffix,
+				problem.description ()));
+
+	}
+
+	* num_problems += problems.len () as u64;
+	* num_damaged += 1;
+
+}
+
+// ex: noet ts=4 filetype=rust
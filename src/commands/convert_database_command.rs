@@ -0,0 +1,53 @@
+use output::Output;
+
+use arguments::*;
+use commands::*;
+use database::*;
+
+pub fn convert_database_command (
+	output: & Output,
+	arguments: & Arguments,
+) -> Result <(), String> {
+
+	let mut recursive_path_database =
+		RecursivePathDatabase::new ();
+
+	let mut input_storage =
+		open_database_storage (
+			arguments.database_path.as_ref ().unwrap (),
+			arguments.database_format,
+			arguments.journal_size_limit);
+
+	let file_database =
+		read_database_from_storage (
+			output,
+			& mut * input_storage,
+			& arguments.root_paths,
+			& mut recursive_path_database,
+		) ?;
+
+	let mut output_storage =
+		open_database_storage (
+			arguments.output_database_path.as_ref ().unwrap (),
+			arguments.database_format,
+			arguments.journal_size_limit);
+
+	write_database_to_storage (
+		output,
+		& mut * output_storage,
+		& file_database,
+	) ?;
+
+	output.message_format (
+		format_args! (
+			"Wrote {} files to {} in {} format",
+			file_database.len (),
+			arguments.output_database_path.as_ref ().unwrap ()
+				.to_string_lossy (),
+			arguments.database_format.name ()));
+
+	Ok (())
+
+}
+
+// ex: noet ts=4 filetype=rust
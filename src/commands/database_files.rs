@@ -0,0 +1,118 @@
+use output::Output;
+
+use database::*;
+use operations::*;
+use types::*;
+
+/// Reads a database from storage, working out which format it is in from the
+/// contents. Files in subvolumes which are now mounted somewhere else are moved
+/// to their new paths.
+
+pub fn read_database_from_storage (
+	output: & Output,
+	database_storage: & mut DatabaseStorage,
+	root_paths: & [PathRef],
+	recursive_path_database: & mut RecursivePathDatabase,
+) -> Result <FileDatabase, String> {
+
+	output.message_format (
+		format_args! (
+			"Reading database from {}",
+			database_storage.path ().to_string_lossy ()));
+
+	let file_database =
+		database_storage.read (
+			recursive_path_database,
+			root_paths,
+		).map_err (
+			|error_string|
+
+			format! (
+				"Error reading database: {}",
+				error_string)
+
+		) ?;
+
+	let (file_database, num_relocated) =
+		relocate_subvolumes (
+			recursive_path_database,
+			root_paths,
+			& mut SubvolumeLocator::new (),
+			file_database);
+
+	if num_relocated > 0 {
+
+		output.message_format (
+			format_args! (
+				"Found {} files in subvolumes which have moved",
+				num_relocated));
+
+	}
+
+	Ok (file_database)
+
+}
+
+/// Brings a database in storage up to date. Usually this only records what has
+/// changed since it was last written.
+
+pub fn write_database_to_storage (
+	output: & Output,
+	database_storage: & mut DatabaseStorage,
+	file_database: & FileDatabase,
+) -> Result <(), String> {
+
+	output.status_format (
+		format_args! (
+			"Writing database to {}",
+			database_storage.path ().to_string_lossy ()));
+
+	database_storage.write (
+		file_database,
+	).map_err (
+		|error_string|
+
+		format! (
+			"Error writing database: {}",
+			error_string)
+
+	) ?;
+
+	output.clear_status ();
+
+	Ok (())
+
+}
+
+/// Brings a database in storage up to date, and compacts it so that it is
+/// quick to read next time.
+
+pub fn compact_database_in_storage (
+	output: & Output,
+	database_storage: & mut DatabaseStorage,
+	file_database: & FileDatabase,
+) -> Result <(), String> {
+
+	output.status_format (
+		format_args! (
+			"Compacting database {}",
+			database_storage.path ().to_string_lossy ()));
+
+	database_storage.compact (
+		file_database,
+	).map_err (
+		|error_string|
+
+		format! (
+			"Error writing database: {}",
+			error_string)
+
+	) ?;
+
+	output.clear_status ();
+
+	Ok (())
+
+}
+
+// ex: noet ts=4 filetype=rust
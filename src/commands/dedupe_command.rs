@@ -1,23 +1,21 @@
+use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::collections::HashSet;
-use std::error::Error;
-use std::ffi::OsStr;
-use std::fs;
-use std::fs::File;
-use std::os::unix::ffi::OsStrExt;
-use std::path::PathBuf;
-
-use flate2::Compression;
-use flate2::read::GzDecoder;
-use flate2::write::GzEncoder;
 
 use output::Output;
 
 use arguments::*;
+use commands::*;
 use database::*;
+use misc::*;
 use operations::*;
 use types::*;
 
+/// Groups of files with the same content on the same filesystem, as indexes
+/// into the file database.
+
+type DeduplicationCandidates = HashMap <(FilesystemId, Hash), Vec <usize>>;
+
 pub fn dedupe_command (
 	output: & Output,
 	arguments: & Arguments,
@@ -26,12 +24,24 @@ pub fn dedupe_command (
 	let mut recursive_path_database =
 		RecursivePathDatabase::new ();
 
+	let mut database_storage =
+		arguments.database_path.as_ref ().map (
+			|database_path|
+
+			open_database_storage (
+				database_path,
+				arguments.database_format,
+				arguments.journal_size_limit)
+
+		);
+
 	// load existing database
 
 	let mut file_database =
 		read_database (
 			output,
 			arguments,
+			& mut database_storage,
 			& mut recursive_path_database,
 		) ?;
 
@@ -49,32 +59,25 @@ pub fn dedupe_command (
 
 	write_database (
 		output,
-		arguments,
-		& mut file_database,
-	) ?;
-
-	// calculate content hashes
-
-	calculate_content_hashes (
-		output,
-		arguments,
-		& mut file_database,
+		& mut database_storage,
+		& file_database,
 	) ?;
 
-	// calculate extent hashes
+	// hash and deduplicate
 
-	calculate_extent_hashes (
+	process_file_database (
 		output,
 		arguments,
+		& mut database_storage,
 		& mut file_database,
 	) ?;
 
-	// perform deduplication
+	// rewrite database in full, so the journal doesn't have to be replayed
 
-	perform_deduplication (
+	compact_database (
 		output,
-		arguments,
-		& mut file_database,
+		& mut database_storage,
+		& file_database,
 	) ?;
 
 	// return
@@ -83,217 +86,260 @@ pub fn dedupe_command (
 
 }
 
-fn read_database (
+/// Runs every step after scanning: hashing, working out what to deduplicate,
+/// and deduplicating it. Only files which have changed since these were last
+/// done are hashed again.
+
+pub fn process_file_database (
 	output: & Output,
 	arguments: & Arguments,
-	recursive_path_database: & mut RecursivePathDatabase,
-) -> Result <FileDatabase, String> {
-
-	// if it doesn't exist just call new
-
-	if ! (
-		arguments.database_path.is_some ()
-		&& arguments.database_path.as_ref ().unwrap ().exists ()
-	) {
-
-		return Ok (
-			FileDatabaseBuilder::new ().build ()
-		);
+	database_storage: & mut Option <Box <DatabaseStorage>>,
+	file_database: & mut FileDatabase,
+) -> Result <(), String> {
 
-	}
+	// calculate content hashes
 
-	// read existing database
+	calculate_content_hashes (
+		output,
+		arguments,
+		database_storage,
+		file_database,
+	) ?;
 
-	output.message_format (
-		format_args! (
-			"Reading database from {}",
-			arguments.database_path
-				.as_ref ()
-				.unwrap ()
-				.to_string_lossy ()));
+	// calculate extent hashes, which a dry run leaves alone, since reading
+	// extents takes an ioctl
 
-	let database_file = try! (
+	if ! arguments.dry_run {
 
-		File::open (
-			arguments.database_path
-				.as_ref ()
-				.unwrap (),
-		).map_err (
-			|io_error|
+		calculate_extent_hashes (
+			output,
+			arguments,
+			database_storage,
+			file_database,
+		) ?;
 
-			format! (
-				"Error reading database: {}",
-				io_error.description ())
+	}
 
-		)
+	// calculate chunk tables
 
-	);
+	if arguments.block_dedupe {
 
-	let mut database_reader = try! (
+		calculate_chunk_tables (
+			output,
+			arguments,
+			database_storage,
+			file_database,
+		) ?;
 
-		GzDecoder::new (
-			database_file,
-		).map_err (
-			|io_error|
+	}
 
-			format! (
-				"Error reading database: {}",
-				io_error.description ())
+	// work out what to deduplicate
 
-		)
+	let dedupe_map =
+		build_dedupe_map (
+			output,
+			arguments,
+			file_database);
 
-	);
+	write_database (
+		output,
+		database_storage,
+		file_database,
+	) ?;
 
-	FileDatabase::read (
-		recursive_path_database,
-		& arguments.root_paths,
-		& mut database_reader,
-	).map_err (
-		|error_string|
+	// perform deduplication
 
-		format! (
-			"Error reading database: {}",
-			error_string)
+	if arguments.dry_run {
 
-	)
+		print_deduplication_plan (
+			output,
+			arguments,
+			file_database,
+			& dedupe_map,
+		);
 
-}
+	} else {
 
-fn write_database (
-	output: & Output,
-	arguments: & Arguments,
-	file_database: & FileDatabase,
-) -> Result <(), String> {
+		perform_deduplication (
+			output,
+			arguments,
+			database_storage,
+			file_database,
+			dedupe_map,
+		) ?;
 
-	if arguments.database_path.is_none () {
-		return Ok (());
 	}
 
-	let database_path =
-		arguments.database_path.as_ref ().unwrap ();
+	// perform block deduplication
 
-	output.status_format (
-		format_args! (
-			"Writing database to {}",
-			database_path.to_string_lossy ()));
-
-	let database_path_temp_bytes: Vec <u8> =
-		database_path.as_os_str ().as_bytes ().iter ().chain (
-			b".temp".iter (),
-		).map (
-			|byte_ref|
-			* byte_ref
-		).collect ();
+	if arguments.block_dedupe {
 
-	let database_path_temp =
-		PathBuf::from (
-			OsStr::from_bytes (
-				& database_path_temp_bytes));
+		if arguments.dry_run {
 
-	let database_file = try! (
+			print_block_deduplication_plan (
+				output,
+				arguments,
+				file_database,
+			);
 
-		File::create (
-			& database_path_temp,
-		).map_err (
-			|io_error|
+		} else {
 
-			format! (
-				"Error writing database: {}",
-				io_error.description ())
+			perform_block_deduplication (
+				output,
+				arguments,
+				database_storage,
+				file_database,
+			) ?;
 
-		)
+		}
 
-	);
+	}
 
-	let mut database_writer =
-		GzEncoder::new (
-			database_file,
-			Compression::Fast);
+	Ok (())
 
-	try! (
-		file_database.write (
-			& mut database_writer));
+}
 
-	let database_file = try! (
+pub fn read_database (
+	output: & Output,
+	arguments: & Arguments,
+	database_storage: & mut Option <Box <DatabaseStorage>>,
+	recursive_path_database: & mut RecursivePathDatabase,
+) -> Result <FileDatabase, String> {
 
-		database_writer.finish (
-		).map_err (
-			|io_error|
+	// if it doesn't exist just call new
 
-			format! (
-				"Error writing database: {}",
-				io_error.description ())
+	if ! (
+		database_storage.is_some ()
+		&& database_storage.as_ref ().unwrap ().path ().exists ()
+	) {
 
-		)
+		return Ok (
+			FileDatabaseBuilder::new ().build ()
+		);
 
-	);
+	}
 
-	try! (
+	// read existing database
 
-		database_file.sync_data (
-		).map_err (
-			|io_error|
+	read_database_from_storage (
+		output,
+		& mut ** database_storage.as_mut ().unwrap (),
+		& arguments.root_paths,
+		recursive_path_database,
+	)
 
-			format! (
-				"Error writing database: {}",
-				io_error.description ())
+}
 
-		)
+pub fn write_database (
+	output: & Output,
+	database_storage: & mut Option <Box <DatabaseStorage>>,
+	file_database: & FileDatabase,
+) -> Result <(), String> {
 
-	);
+	if database_storage.is_none () {
+		return Ok (());
+	}
 
-	try! (
-		fs::rename (
-			& database_path_temp,
-			database_path,
-		).map_err (
-			|io_error|
+	write_database_to_storage (
+		output,
+		& mut ** database_storage.as_mut ().unwrap (),
+		file_database,
+	)
 
-			format! (
-				"Error writing database: {}",
-				io_error.description ())
+}
 
-		)
-	);
+pub fn compact_database (
+	output: & Output,
+	database_storage: & mut Option <Box <DatabaseStorage>>,
+	file_database: & FileDatabase,
+) -> Result <(), String> {
 
-	output.clear_status ();
+	if database_storage.is_none () {
+		return Ok (());
+	}
 
-	Ok (())
+	compact_database_in_storage (
+		output,
+		& mut ** database_storage.as_mut ().unwrap (),
+		file_database,
+	)
 
 }
 
-fn scan_directories (
+pub fn scan_directories (
 	output: & Output,
 	arguments: & Arguments,
 	recursive_path_database: & mut RecursivePathDatabase,
 	file_database: FileDatabase,
 ) -> Result <FileDatabase, String> {
 
+	let path_filter =
+		PathFilter::new (
+			& arguments.include_patterns,
+			& arguments.exclude_patterns,
+		) ?;
+
 	let directory_scanner =
 		DirectoryScanner::new (
 			& arguments.root_paths,
+			& path_filter,
+			arguments.use_ignore_files,
+			arguments.content_hash_algorithm,
+			arguments.incremental,
+			arguments.cross_subvolumes,
+			arguments.skip_readonly_subvolumes,
 			file_database,
 		);
 
-	Ok (
-		directory_scanner.scan_directories (
-			output,
-			recursive_path_database,
-		) ?
-	)
+	directory_scanner.scan_directories (
+		output,
+		recursive_path_database)
 
 }
 
 fn calculate_content_hashes (
 	output: & Output,
 	arguments: & Arguments,
+	database_storage: & mut Option <Box <DatabaseStorage>>,
 	file_database: & mut FileDatabase,
 ) -> Result <(), String> {
 
+	// cheap partial hashes first, then full hashes only where these collide
+
+	for stage in [
+		ContentHashStage::Partial,
+		ContentHashStage::Full,
+	].iter () {
+
+		calculate_content_hashes_stage (
+			output,
+			arguments,
+			database_storage,
+			file_database,
+			* stage,
+		) ?;
+
+	}
+
+	Ok (())
+
+}
+
+fn calculate_content_hashes_stage (
+	output: & Output,
+	arguments: & Arguments,
+	database_storage: & mut Option <Box <DatabaseStorage>>,
+	file_database: & mut FileDatabase,
+	stage: ContentHashStage,
+) -> Result <(), String> {
+
 	let mut content_hasher =
 		ContentHasher::new (
 			& arguments.root_paths,
+			stage,
+			arguments.content_hash_algorithm,
 			arguments.content_hash_batch_size,
+			arguments.hash_threads,
 			file_database,
 		);
 
@@ -311,7 +357,8 @@ fn calculate_content_hashes (
 
 		output.message_format (
 			format_args! (
-				"Hashed contents of {} out of {} files, {} remaining",
+				"Hashed {} of {} out of {} files, {} remaining",
+				stage.description (),
 				content_hasher.num_processed (),
 				content_hasher.num_to_process (),
 				content_hasher.num_remaining ()));
@@ -320,7 +367,7 @@ fn calculate_content_hashes (
 
 		write_database (
 			output,
-			arguments,
+			database_storage,
 			content_hasher.file_database (),
 		) ?;
 
@@ -328,11 +375,13 @@ fn calculate_content_hashes (
 
 	output.message_format (
 		format_args! (
-			"Hashed contents of {} files with {} errors, ignored {} with fresh \
-			hashes",
+			"Hashed {} of {} files with {} errors, ignored {} with fresh \
+			hashes and {} with no possible duplicates",
+			stage.description (),
 			content_hasher.num_updated (),
 			content_hasher.num_errors (),
-			content_hasher.num_fresh ()));
+			content_hasher.num_fresh (),
+			content_hasher.num_unneeded ()));
 
 	// write out updated database
 
@@ -340,7 +389,7 @@ fn calculate_content_hashes (
 
 		write_database (
 			output,
-			arguments,
+			database_storage,
 			& content_hasher.file_database (),
 		) ?;
 
@@ -353,12 +402,14 @@ fn calculate_content_hashes (
 fn calculate_extent_hashes (
 	output: & Output,
 	arguments: & Arguments,
+	database_storage: & mut Option <Box <DatabaseStorage>>,
 	file_database: & mut FileDatabase,
 ) -> Result <(), String> {
 
 	let mut extent_hasher =
 		ExtentHasher::new (
 			& arguments.root_paths,
+			arguments.content_hash_algorithm,
 			arguments.extent_hash_batch_size,
 			file_database);
 
@@ -388,7 +439,7 @@ fn calculate_extent_hashes (
 
 		write_database (
 			output,
-			arguments,
+			database_storage,
 			& extent_hasher.file_database (),
 		) ?;
 
@@ -407,7 +458,7 @@ fn calculate_extent_hashes (
 
 		write_database (
 			output,
-			arguments,
+			database_storage,
 			extent_hasher.file_database (),
 		) ?;
 
@@ -417,18 +468,97 @@ fn calculate_extent_hashes (
 
 }
 
+fn calculate_chunk_tables (
+	output: & Output,
+	arguments: & Arguments,
+	database_storage: & mut Option <Box <DatabaseStorage>>,
+	file_database: & mut FileDatabase,
+) -> Result <(), String> {
+
+	let mut chunk_hasher =
+		ChunkHasher::new (
+			& arguments.root_paths,
+			arguments.content_hash_algorithm,
+			arguments.block_dedupe_minimum_file_size,
+			arguments.chunking_method,
+			arguments.chunk_size,
+			arguments.content_hash_batch_size,
+			file_database);
+
+	loop {
+
+		// calculate a batch of chunk tables
+
+		chunk_hasher.calculate_chunk_tables (
+			output,
+		);
+
+		if chunk_hasher.num_remaining () == 0 {
+			break;
+		}
+
+		output.message_format (
+			format_args! (
+				"Hashed chunks of {} out of {} files, {} remaining",
+				chunk_hasher.num_updated ()
+					+ chunk_hasher.num_errors (),
+				chunk_hasher.num_updated ()
+					+ chunk_hasher.num_errors ()
+					+ chunk_hasher.num_remaining (),
+				chunk_hasher.num_remaining ()));
+
+		// write out updated database
+
+		write_database (
+			output,
+			database_storage,
+			chunk_hasher.file_database (),
+		) ?;
+
+	}
+
+	output.message_format (
+		format_args! (
+			"Hashed chunks of {} files, {} errors, skipped {}",
+			chunk_hasher.num_updated (),
+			chunk_hasher.num_errors (),
+			chunk_hasher.num_fresh ()));
+
+	// write out updated database
+
+	if chunk_hasher.num_updated () > 0 {
+
+		write_database (
+			output,
+			database_storage,
+			chunk_hasher.file_database (),
+		) ?;
+
+	}
+
+	Ok (())
+
+}
+
 pub fn build_dedupe_map (
 	output: & Output,
 	arguments: & Arguments,
-	file_database: & FileDatabase,
+	file_database: & mut FileDatabase,
 ) -> HashMap <RecursivePathRef, RecursivePathRef> {
 
+	// forget sharing from previous runs, groups may have changed
+
+	for file_data in file_database.iter_mut () {
+		file_data.group_size = 0;
+		file_data.group_shared = 0;
+	}
+
 	// find all unique files
 
 	let deduplication_candidates =
 		group_deduplication_candidates (
 			arguments,
-			& file_database);
+			file_database);
 
 	let unique_hash_count =
 		deduplication_candidates.len ();
@@ -438,11 +568,47 @@ pub fn build_dedupe_map (
 			"Found {} unique hashes",
 			unique_hash_count));
 
+	// hardlinks of an inode already share everything, so they only count as a
+	// single member of their group, represented by the first of them
+
+	let (deduplication_candidates, inode_links) =
+		merge_hardlinks (
+			file_database,
+			deduplication_candidates);
+
+	let linked_groups: Vec <usize> =
+		deduplication_candidates.values ().filter (
+			|file_data_indices|
+
+			file_data_indices.len () == 1
+			&& inode_links.contains_key (
+				& file_data_indices [0])
+
+		).map (
+			|file_data_indices|
+			file_data_indices [0]
+		).collect ();
+
+	output.message_format (
+		format_args! (
+			"Found {} unique hashes whose files are all hardlinks of one inode",
+			linked_groups.len ()));
+
+	if arguments.dry_run {
+
+		print_linked_groups (
+			output,
+			file_database,
+			& linked_groups,
+			& inode_links);
+
+	}
+
 	// filter to duplicated files
 
-	let deduplication_candidates: HashMap <Hash, Vec <usize>> =
+	let deduplication_candidates: DeduplicationCandidates =
 		deduplication_candidates.into_iter ().filter (
-			|& (ref _hash, ref file_data_indices)|
+			|& (ref _group_key, ref file_data_indices)|
 
 			file_data_indices.len () > 1
 
@@ -456,14 +622,15 @@ pub fn build_dedupe_map (
 			"Found {} unique hashes with multiple instances",
 			duplicated_file_count));
 
-	// filter to files with physical extents
+	// filter to files with physical extents, keeping those whose extents are
+	// unknown in a dry run
 
-	let deduplication_candidates: HashMap <Hash, Vec <usize>> =
+	let deduplication_candidates: DeduplicationCandidates =
 		deduplication_candidates.into_iter ().map (
-			|(content_hash, file_data_indices)|
+			|(group_key, file_data_indices)|
 
 		(
-			content_hash,
+			group_key,
 
 			file_data_indices.into_iter ().filter (
 				|& file_data_index|
@@ -471,11 +638,18 @@ pub fn build_dedupe_map (
 				file_database [file_data_index].extent_hash
 					!= ZERO_HASH
 
+				|| (
+					arguments.dry_run
+					&& file_database [file_data_index].extent_hash_time
+						== 0
+				)
+
 			).collect ()
 		)
 
 	).filter (
-		|& (ref _hash, ref file_data_indices): & (Hash, Vec <usize>)|
+		|& (ref _group_key, ref file_data_indices):
+			& ((FilesystemId, Hash), Vec <usize>)|
 
 		file_data_indices.len () > 1
 
@@ -489,11 +663,13 @@ pub fn build_dedupe_map (
 			"Found {} unique hashes which can be deduplicated",
 			physical_duplicated_file_count));
 
-	// filter to files which are not deduplicated
+	// filter to files which are not deduplicated, or might not be
 
-	let deduplication_candidates: HashMap <Hash, Vec <usize>> =
-		deduplication_candidates.into_iter ().filter (
-			|& (ref _hash, ref file_indices)| {
+	let (deduplication_candidates, deduplicated_candidates): (
+		DeduplicationCandidates,
+		DeduplicationCandidates,
+	) = deduplication_candidates.into_iter ().partition (
+		|& (ref _group_key, ref file_indices)| {
 
 		let first_file_index =
 			file_indices [0];
@@ -507,12 +683,24 @@ pub fn build_dedupe_map (
 		file_indices.iter ().any (
 			|file_index|
 
-			file_database [* file_index].extent_hash
+			file_database [* file_index].extent_hash == ZERO_HASH
+
+			|| file_database [* file_index].extent_hash
 				!= first_extent_hash
 
 		)
 
-	}).collect ();
+	});
+
+	for (_group_key, file_data_indices) in deduplicated_candidates {
+
+		record_group_sharing (
+			file_database,
+			& file_data_indices,
+			& inode_links,
+			file_data_indices.len ());
+
+	}
 
 	let physical_not_deduplicated_file_count =
 		deduplication_candidates.len ();
@@ -522,30 +710,115 @@ pub fn build_dedupe_map (
 			"Found {} unique hashes which need deduplication",
 			physical_not_deduplicated_file_count));
 
-	// work out what to deduplicate
+	// work out what to deduplicate, leaving out files which already share the
+	// source's extents
 
-	let dedupe_map: HashMap <RecursivePathRef, RecursivePathRef> =
-		deduplication_candidates.into_iter ().flat_map (
-			|(_hash, file_data_indices)| {
+	let mut dedupe_map: HashMap <RecursivePathRef, RecursivePathRef> =
+		HashMap::new ();
 
-		let ref first_file_data =
-			file_database [
-				file_data_indices [0]];
+	let mut num_already_shared: u64 = 0;
 
-		let first_file_path =
-			first_file_data.path.clone ();
+	for (_group_key, file_data_indices) in deduplication_candidates {
 
-		file_data_indices.into_iter ().map (
-			move |file_data_index|
+		let source_file_index =
+			choose_source (
+				arguments.source_policy,
+				! arguments.dry_run,
+				& arguments.preferred_source_paths,
+				file_database,
+				& file_data_indices);
 
-			(
-				file_database [file_data_index].path.clone (),
-				first_file_path.clone (),
-			)
+		let source_file_path =
+			file_database [source_file_index].path.clone ();
 
-		)
+		// a dry run doesn't read extents, so it can't tell which files
+		// already share them
+
+		let source_extents =
+			if arguments.dry_run {
+				Vec::new ()
+			} else {
+				get_data_extents (
+					& source_file_path.to_path (),
+				).unwrap_or_default ()
+			};
+
+		let unshared_file_indices: Vec <usize> =
+			file_data_indices.iter ().cloned ().filter (
+				|& file_data_index|
+
+				file_data_index != source_file_index
+
+				&& (
+					arguments.dry_run
+					|| ! get_data_extents (
+						& file_database [file_data_index].path.to_path (),
+					).map (
+						|file_extents|
+
+						shares_extents (
+							& source_extents,
+							& file_extents)
+
+					).unwrap_or (false)
+				)
+
+			).collect ();
+
+		let num_shared =
+			file_data_indices.len () - unshared_file_indices.len ();
+
+		// files in read only subvolumes can't be changed, so they keep their
+		// own extents unless they were chosen as the source
+
+		let target_file_indices: Vec <usize> =
+			unshared_file_indices.into_iter ().filter (
+				|& file_data_index|
+
+				! file_database [file_data_index].is_read_only ()
+
+			).collect ();
 
-	}).collect ();
+		if ! arguments.dry_run {
+
+			record_group_sharing (
+				file_database,
+				& file_data_indices,
+				& inode_links,
+				num_shared);
+
+		}
+
+		num_already_shared += num_shared as u64 - 1;
+
+		// defragmenting the source would stop other files sharing it, and
+		// files in read only subvolumes can't be defragmented at all
+
+		if (
+			num_shared == 1
+			&& ! file_database [source_file_index].is_read_only ()
+		) {
+
+			dedupe_map.insert (
+				source_file_path.clone (),
+				source_file_path.clone ());
+
+		}
+
+		for target_file_index in target_file_indices {
+
+			dedupe_map.insert (
+				file_database [target_file_index].path.clone (),
+				source_file_path.clone ());
+
+		}
+
+	}
+
+	output.message_format (
+		format_args! (
+			"Found {} files which already share extents with their source",
+			num_already_shared));
 
 	output.message_format (
 		format_args! (
@@ -558,10 +831,165 @@ pub fn build_dedupe_map (
 
 }
 
+/// Records how many files in a group of identical files refer to the same data
+/// on disk, against each of them.
+
+fn record_group_sharing (
+	file_database: & mut FileDatabase,
+	file_data_indices: & [usize],
+	inode_links: & HashMap <usize, Vec <usize>>,
+	num_shared: usize,
+) {
+
+	for & file_data_index in file_data_indices.iter () {
+
+		let linked_indices =
+			inode_links.get (
+				& file_data_index,
+			).map (
+				|linked_indices|
+				& linked_indices [..]
+			).unwrap_or (
+				& [],
+			);
+
+		for & index in Some (file_data_index).iter ().chain (
+			linked_indices.iter ()) {
+
+			let ref mut file_data =
+				file_database [index];
+
+			file_data.group_size = file_data_indices.len () as u64;
+			file_data.group_shared = num_shared as u64;
+
+		}
+
+	}
+
+}
+
+/// Leaves only the first hardlink of each inode in each group, returning the
+/// groups along with the other links of each inode which has more than one,
+/// keyed by the index of the first.
+
+fn merge_hardlinks (
+	file_database: & FileDatabase,
+	deduplication_candidates: DeduplicationCandidates,
+) -> (
+	DeduplicationCandidates,
+	HashMap <usize, Vec <usize>>,
+) {
+
+	let mut inode_links: HashMap <usize, Vec <usize>> =
+		HashMap::new ();
+
+	let deduplication_candidates =
+		deduplication_candidates.into_iter ().map (
+			|(group_key, file_data_indices)| {
+
+			let mut first_links: HashMap <InodeId, usize> =
+				HashMap::new ();
+
+			let mut member_indices: Vec <usize> =
+				Vec::new ();
+
+			for file_data_index in file_data_indices {
+
+				if let Some (inode_id) =
+					file_database [file_data_index].inode_id () {
+
+					if let Some (& first_index) =
+						first_links.get (
+							& inode_id) {
+
+						inode_links.entry (
+							first_index,
+						).or_default ().push (
+							file_data_index);
+
+						continue;
+
+					}
+
+					first_links.insert (
+						inode_id,
+						file_data_index);
+
+				}
+
+				member_indices.push (
+					file_data_index);
+
+			}
+
+			(group_key, member_indices)
+
+		}).collect ();
+
+	(deduplication_candidates, inode_links)
+
+}
+
+/// Lists the groups of identical files which are already all hardlinks of the
+/// same inode, so there is nothing to deduplicate.
+
+fn print_linked_groups (
+	output: & Output,
+	file_database: & FileDatabase,
+	linked_groups: & [usize],
+	inode_links: & HashMap <usize, Vec <usize>>,
+) {
+
+	let mut linked_groups: Vec <(Vec <RecursivePathRef>, u64)> =
+		linked_groups.iter ().map (
+			|& first_index| {
+
+			let mut paths: Vec <RecursivePathRef> =
+				Some (first_index).iter ().chain (
+					inode_links [& first_index].iter (),
+				).map (
+					|& file_data_index|
+					file_database [file_data_index].path.clone ()
+				).collect ();
+
+			paths.sort ();
+
+			(paths, file_database [first_index].size)
+
+		}).collect ();
+
+	linked_groups.sort ();
+
+	for & (ref paths, file_size) in linked_groups.iter () {
+
+		output.message_format (
+			format_args! (
+				"Already linked {} files of {}",
+				paths.len (),
+				format_size (file_size)));
+
+		for path in paths.iter () {
+
+			output.message_format (
+				format_args! (
+					"  {}",
+					path.to_string_lossy ()));
+
+		}
+
+	}
+
+}
+
+/// Groups files with the same content, which can be deduplicated against each
+/// other. Files on different filesystems can't share extents, so they are
+/// never grouped together, even if their content is the same, and files whose
+/// filesystem can't be identified are left out.
+
 fn group_deduplication_candidates (
 	arguments: & Arguments,
 	file_database: & FileDatabase,
-) -> HashMap <Hash, Vec <usize>> {
+) -> DeduplicationCandidates {
 
 	let mut identical_files_map =
 		HashMap::new ();
@@ -572,6 +1000,10 @@ fn group_deduplication_candidates (
 			root_path.clone ()
 		).collect ();
 
+	let root_devices =
+		root_devices (
+			& arguments.root_paths);
+
 	for (file_index, file_data)
 	in file_database.iter ().enumerate () {
 
@@ -579,6 +1011,10 @@ fn group_deduplication_candidates (
 			continue;
 		}
 
+		if file_data.content_hash == ZERO_HASH {
+			continue;
+		}
+
 		if (
 
 			(
@@ -598,8 +1034,20 @@ fn group_deduplication_candidates (
 			continue;
 		}
 
+		let filesystem_id =
+			match file_data.filesystem_id (
+				& root_devices) {
+
+			Some (filesystem_id) => filesystem_id,
+			None => continue,
+
+		};
+
 		identical_files_map.entry (
-			file_data.content_hash,
+			(
+				filesystem_id,
+				file_data.content_hash,
+			),
 		).or_insert_with (
 			|| Vec::new (),
 		).push (
@@ -612,21 +1060,121 @@ fn group_deduplication_candidates (
 
 }
 
+fn print_deduplication_plan (
+	output: & Output,
+	arguments: & Arguments,
+	file_database: & FileDatabase,
+	dedupe_map: & HashMap <RecursivePathRef, RecursivePathRef>,
+) {
+
+	let source_paths: HashSet <RecursivePathRef> =
+		dedupe_map.values ().cloned ().collect ();
+
+	let source_files: HashMap <RecursivePathRef, & FileData> =
+		file_database.iter ().filter (
+			|file_data|
+
+			source_paths.contains (
+				& file_data.path)
+
+		).map (
+			|file_data|
+
+			(
+				file_data.path.clone (),
+				file_data,
+			)
+
+		).collect ();
+
+	// group targets by their source
+
+	let mut dedupe_groups: BTreeMap <
+		RecursivePathRef,
+		Vec <RecursivePathRef>,
+	> = BTreeMap::new ();
+
+	for (target_path, source_path) in dedupe_map.iter () {
+
+		let targets =
+			dedupe_groups.entry (
+				source_path.clone (),
+			).or_insert_with (
+				|| Vec::new (),
+			);
+
+		if target_path != source_path {
+
+			targets.push (
+				target_path.clone ());
+
+		}
+
+	}
+
+	let mut num_targets: u64 = 0;
+	let mut size_reclaimed: u64 = 0;
+
+	for (source_path, targets) in dedupe_groups.iter_mut () {
+
+		targets.sort ();
+
+		let ref source_file =
+			source_files [source_path];
+
+		let file_size =
+			source_file.size;
+
+		output.message_format (
+			format_args! (
+				"Would deduplicate {} files of {} against {}",
+				targets.len (),
+				format_size (file_size),
+				source_path.to_string_lossy ()));
+
+		for target_path in targets.iter () {
+
+			output.message_format (
+				format_args! (
+					"  {}",
+					target_path.to_string_lossy ()));
+
+		}
+
+		num_targets += targets.len () as u64;
+		size_reclaimed += file_size * targets.len () as u64;
+
+	}
+
+	output.message_format (
+		format_args! (
+			"Would deduplicate {} files in {} groups, reclaiming up to {}",
+			num_targets,
+			dedupe_groups.len (),
+			format_size (size_reclaimed)));
+
+	output.message (
+		"Extents are not read in a dry run, so it is unknown whether some of \
+		these files already share their data");
+
+	if arguments.source_policy.reads_extents () {
+
+		output.message (
+			"The source policy needs extents, so sources are shown in path \
+			order instead");
+
+	}
+
+}
+
 fn perform_deduplication (
 	output: & Output,
 	arguments: & Arguments,
+	database_storage: & mut Option <Box <DatabaseStorage>>,
 	file_database: & mut FileDatabase,
+	mut dedupe_map: HashMap <RecursivePathRef, RecursivePathRef>,
 ) -> Result <(), String> {
 
-	let mut dedupe_map =
-		build_dedupe_map (
-			output,
-			arguments,
-			& file_database);
-
-	let file_database =
-		file_database;
-
 	let mut file_deduper =
 		FileDeduper::new ();
 
@@ -649,9 +1197,11 @@ fn perform_deduplication (
 			format_args! (
 				"Deduped {} out of {} files, {} remaining",
 				file_deduper.num_updated ()
-					+ file_deduper.num_errors (),
+					+ file_deduper.num_errors ()
+					+ file_deduper.num_mismatched (),
 				file_deduper.num_updated ()
 					+ file_deduper.num_errors ()
+					+ file_deduper.num_mismatched ()
 					+ file_deduper.num_remaining (),
 				file_deduper.num_remaining ()));
 
@@ -659,7 +1209,7 @@ fn perform_deduplication (
 
 		write_database (
 			output,
-			arguments,
+			database_storage,
 			& file_database,
 		) ?;
 
@@ -667,18 +1217,144 @@ fn perform_deduplication (
 
 	output.message_format (
 		format_args! (
-			"Deduped {} files with {} errors, ignored {} already deduped",
+			"Deduped {} files with {} errors, skipped {} which failed \
+			verification, ignored {} already deduped",
 			file_deduper.num_updated (),
 			file_deduper.num_errors (),
+			file_deduper.num_mismatched (),
 			file_deduper.num_fresh ()));
 
 	// write out updated database
 
-	if file_deduper.num_updated () > 0 {
+	if file_deduper.num_updated () + file_deduper.num_mismatched () > 0 {
 
 		write_database (
+			output,
+			database_storage,
+			& file_database,
+		) ?;
+
+	}
+
+	Ok (())
+
+}
+
+fn print_block_deduplication_plan (
+	output: & Output,
+	arguments: & Arguments,
+	file_database: & FileDatabase,
+) {
+
+	let block_dedupe_map =
+		build_block_dedupe_map (
+			& arguments.root_paths,
+			file_database);
+
+	let mut num_ranges: u64 = 0;
+	let mut size_shared: u64 = 0;
+
+	for (target_index, block_ranges) in block_dedupe_map.iter () {
+
+		let target_size: u64 =
+			block_ranges.iter ().map (
+				|block_range|
+				block_range.length
+			).sum ();
+
+		output.message_format (
+			format_args! (
+				"Would deduplicate {} in {} ranges of {}",
+				format_size (target_size),
+				block_ranges.len (),
+				file_database [* target_index].path.to_string_lossy ()));
+
+		num_ranges += block_ranges.len () as u64;
+		size_shared += target_size;
+
+	}
+
+	output.message_format (
+		format_args! (
+			"Would deduplicate {} ranges in {} files, reclaiming up to {}",
+			num_ranges,
+			block_dedupe_map.len (),
+			format_size (size_shared)));
+
+}
+
+fn perform_block_deduplication (
+	output: & Output,
+	arguments: & Arguments,
+	database_storage: & mut Option <Box <DatabaseStorage>>,
+	file_database: & mut FileDatabase,
+) -> Result <(), String> {
+
+	let mut block_dedupe_map =
+		build_block_dedupe_map (
+			& arguments.root_paths,
+			file_database);
+
+	output.message_format (
+		format_args! (
+			"Found {} files with ranges to deduplicate, {} reclaimable",
+			block_dedupe_map.len (),
+			format_size (
+				block_dedupe_map_size (
+					& block_dedupe_map))));
+
+	let mut block_deduper =
+		BlockDeduper::new ();
+
+	loop {
+
+		// deduplicate a batch of files
+
+		block_deduper.dedupe_ranges (
 			output,
 			arguments,
+			file_database,
+			& mut block_dedupe_map,
+		) ?;
+
+		if block_deduper.num_remaining () == 0 {
+			break;
+		}
+
+		output.message_format (
+			format_args! (
+				"Deduped ranges of {} out of {} files, {} remaining",
+				block_deduper.num_updated ()
+					+ block_deduper.num_errors (),
+				block_deduper.num_updated ()
+					+ block_deduper.num_errors ()
+					+ block_deduper.num_remaining (),
+				block_deduper.num_remaining ()));
+
+		// write out updated database
+
+		write_database (
+			output,
+			database_storage,
+			& file_database,
+		) ?;
+
+	}
+
+	output.message_format (
+		format_args! (
+			"Deduped ranges of {} files with {} errors, {} shared",
+			block_deduper.num_updated (),
+			block_deduper.num_errors (),
+			format_size (block_deduper.size_deduped ())));
+
+	// write out updated database
+
+	if block_deduper.num_updated () + block_deduper.num_errors () > 0 {
+
+		write_database (
+			output,
+			database_storage,
 			& file_database,
 		) ?;
 
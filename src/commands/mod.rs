@@ -1,7 +1,15 @@
+mod check_database_command;
+mod convert_database_command;
+mod database_files;
 mod dedupe_command;
 mod print_extents_command;
+mod watch_command;
 
+pub use self::check_database_command::*;
+pub use self::convert_database_command::*;
+pub use self::database_files::*;
 pub use self::dedupe_command::*;
 pub use self::print_extents_command::*;
+pub use self::watch_command::*;
 
 // ex: noet ts=4 filetype=rust
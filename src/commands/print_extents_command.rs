@@ -1,8 +1,7 @@
-use btrfs;
-
 use output::Output;
 
 use arguments::*;
+use linux;
 
 pub fn print_extents_command (
 	output: & Output,
@@ -18,7 +17,7 @@ pub fn print_extents_command (
 
 		let file_extents =
 			try! (
-				btrfs::get_file_extent_map_for_path (
+				linux::get_file_extent_map (
 					path.as_ref ()));
 
 		for file_extent in file_extents {
@@ -0,0 +1,160 @@
+use std::time::Duration;
+
+use output::Output;
+
+use arguments::*;
+use commands::*;
+use database::*;
+use operations::*;
+
+/// Brings everything up to date as the dedupe command does, and then keeps
+/// running, watching the root paths for changes. Whenever changes stop for the
+/// debounce time, the paths which changed are scanned again, and the usual
+/// hashing and deduplication steps are run on whatever is now out of date.
+
+pub fn watch_command (
+	output: & Output,
+	arguments: & Arguments,
+) -> Result <(), String> {
+
+	let mut recursive_path_database =
+		RecursivePathDatabase::new ();
+
+	let mut database_storage =
+		arguments.database_path.as_ref ().map (
+			|database_path|
+
+			open_database_storage (
+				database_path,
+				arguments.database_format,
+				arguments.journal_size_limit)
+
+		);
+
+	let path_filter =
+		PathFilter::new (
+			& arguments.include_patterns,
+			& arguments.exclude_patterns,
+		) ?;
+
+	// watch before scanning, so nothing which changes during the scan is missed
+
+	let mut file_watcher =
+		FileWatcher::new (
+			& arguments.root_paths,
+			& path_filter,
+			arguments.cross_subvolumes,
+			arguments.skip_readonly_subvolumes,
+		) ?;
+
+	output.status (
+		"Watching directories");
+
+	file_watcher.watch_root_paths () ?;
+
+	output.clear_status ();
+
+	output.message_format (
+		format_args! (
+			"Watching {} directories",
+			file_watcher.num_watched_directories ()));
+
+	// load existing database and bring it up to date
+
+	let mut file_database =
+		read_database (
+			output,
+			arguments,
+			& mut database_storage,
+			& mut recursive_path_database,
+		) ?;
+
+	file_database =
+		scan_directories (
+			output,
+			arguments,
+			& mut recursive_path_database,
+			file_database,
+		) ?;
+
+	write_database (
+		output,
+		& mut database_storage,
+		& file_database,
+	) ?;
+
+	process_file_database (
+		output,
+		arguments,
+		& mut database_storage,
+		& mut file_database,
+	) ?;
+
+	compact_database (
+		output,
+		& mut database_storage,
+		& file_database,
+	) ?;
+
+	// then deal with changes in batches, as they happen
+
+	let debounce_time =
+		Duration::from_secs (
+			arguments.debounce_time);
+
+	loop {
+
+		output.message (
+			"Waiting for changes");
+
+		let changed_paths =
+			file_watcher.wait_for_changes (
+				debounce_time,
+			) ?;
+
+		if changed_paths.is_empty () {
+			continue;
+		}
+
+		output.message_format (
+			format_args! (
+				"Found {} changed paths, watching {} directories",
+				changed_paths.len (),
+				file_watcher.num_watched_directories ()));
+
+		let directory_scanner =
+			DirectoryScanner::new (
+				& arguments.root_paths,
+				& path_filter,
+				arguments.use_ignore_files,
+				arguments.content_hash_algorithm,
+				false,
+				arguments.cross_subvolumes,
+				arguments.skip_readonly_subvolumes,
+				file_database);
+
+		file_database =
+			directory_scanner.scan_changes (
+				output,
+				& mut recursive_path_database,
+				& changed_paths,
+			) ?;
+
+		write_database (
+			output,
+			& mut database_storage,
+			& file_database,
+		) ?;
+
+		process_file_database (
+			output,
+			arguments,
+			& mut database_storage,
+			& mut file_database,
+		) ?;
+
+	}
+
+}
+
+// ex: noet ts=4 filetype=rust
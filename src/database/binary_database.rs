@@ -0,0 +1,1235 @@
+//! The binary database format starts with an eight byte magic number and a
+//! little endian 32 bit schema version. From schema version two, this is
+//! followed by the version of the tool which wrote it, as a length and bytes.
+//! Then comes one record per file, each starting with a one byte tag, and a
+//! zero tag marks the end.
+//!
+//! Integers are written as LEB128 varints, signed integers are zigzag encoded
+//! first, and hashes are written as raw bytes. Each path is written as the
+//! number of bytes it shares with the previous path, followed by the length
+//! and bytes of the remainder. Optional hashes and the chunk table are only
+//! present if the corresponding bit is set in a field mask. From schema version
+//! four, the mask can also flag the filesystem UUID, subvolume ID and path
+//! relative to the subvolume, which follow the rest of the file's fields.
+//!
+//! From schema version five, the files on each filesystem follow a section
+//! record giving its UUID, and are written without it. Files which aren't known
+//! to be on any filesystem come first, before any section.
+//!
+//! From schema version six, the mask can also flag the inode number and link
+//! count, which follow the subvolume.
+//!
+//! From schema version seven, the UUID in each section record is followed by
+//! the number of scan generations for the filesystem, and then the subvolume
+//! ID, relative path, generation and filter fingerprint of each.
+//!
+//! From schema version eight, the mask can also flag that the subvolume is read
+//! only, which adds nothing else to the record.
+
+use std::ffi::OsStr;
+use std::io::Read;
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::path::PathBuf;
+
+use database::*;
+use hash_algorithm::*;
+use misc::*;
+use types::*;
+
+const RECORD_END: u8 = 0;
+const RECORD_FILE: u8 = 1;
+const RECORD_SECTION: u8 = 2;
+
+const FIELD_PARTIAL_HASH: u64 = 0x01;
+const FIELD_CONTENT_HASH: u64 = 0x02;
+const FIELD_EXTENT_HASH: u64 = 0x04;
+const FIELD_CHUNK_TABLE: u64 = 0x08;
+const FIELD_SUBVOLUME: u64 = 0x10;
+const FIELD_INODE: u64 = 0x20;
+const FIELD_READ_ONLY: u64 = 0x40;
+
+impl FileDatabase {
+
+	/// Reads the binary format, returning the header along with the files and
+	/// scan generations exactly as they were written. Sorting them out, and
+	/// migrations, are left to the caller.
+
+	pub fn read_binary_entries (
+		recursive_path_database: & mut RecursivePathDatabase,
+		source: & mut Read,
+	) -> Result <(Vec <FileData>, Vec <ScanGeneration>, DatabaseHeader), String> {
+
+		let mut magic =
+			[0u8; 8];
+
+		try! (
+			io_result (
+				source.read_exact (
+					& mut magic)));
+
+		if & magic != BINARY_DATABASE_MAGIC {
+			return Err ("Not a binary database".to_owned ());
+		}
+
+		let mut version_bytes =
+			[0u8; 4];
+
+		try! (
+			io_result (
+				source.read_exact (
+					& mut version_bytes)));
+
+		let schema_version =
+			version_bytes.iter ().rev ().fold (
+				0u32,
+				|version, & byte|
+				(version << 8) | byte as u32
+			);
+
+		let database_header =
+			if schema_version < 2 {
+
+			DatabaseHeader {
+				schema_version: schema_version,
+				.. DatabaseHeader::legacy ()
+			}
+
+		} else {
+
+			let tool_version_length =
+				try! (read_varint (source)) as usize;
+
+			DatabaseHeader {
+				schema_version: schema_version,
+				tool_version:
+					String::from_utf8_lossy (
+						& try! (
+							read_bytes (
+								source,
+								tool_version_length)),
+					).into_owned (),
+			}
+
+		};
+
+		try! (
+			database_header.check ());
+
+		let mut entries: Vec <FileData> =
+			Vec::new ();
+
+		let mut previous_path: Vec <u8> =
+			Vec::new ();
+
+		let mut section_filesystem_uuid: Option <Uuid> =
+			None;
+
+		let mut scan_generations: Vec <ScanGeneration> =
+			Vec::new ();
+
+		loop {
+
+			match try! (read_u8 (source)) {
+
+				RECORD_END =>
+					break,
+
+				RECORD_FILE =>
+					(),
+
+				RECORD_SECTION => {
+
+					let filesystem_uuid =
+						try! (read_uuid (source));
+
+					if database_header.schema_version >= 7 {
+
+						let num_scan_generations =
+							try! (read_varint (source));
+
+						for _ in 0 .. num_scan_generations {
+
+							let subvolume_id =
+								try! (read_varint (source));
+
+							let path_length =
+								try! (read_varint (source)) as usize;
+
+							let path_bytes =
+								try! (
+									read_bytes (
+										source,
+										path_length));
+
+							let generation =
+								try! (read_varint (source));
+
+							let filter_fingerprint =
+								try! (read_varint (source));
+
+							scan_generations.push (
+								ScanGeneration {
+									filesystem_uuid: filesystem_uuid,
+									subvolume_id: subvolume_id,
+									path: PathBuf::from (
+										OsStr::from_bytes (
+											& path_bytes)),
+									generation: generation,
+									filter_fingerprint: filter_fingerprint,
+								});
+
+						}
+
+					}
+
+					section_filesystem_uuid =
+						Some (filesystem_uuid);
+
+					continue;
+
+				},
+
+				tag =>
+					return Err (
+						format! (
+							"Invalid record tag: {}",
+							tag)),
+
+			}
+
+			let shared_length =
+				try! (read_varint (source)) as usize;
+
+			let suffix_length =
+				try! (read_varint (source)) as usize;
+
+			if shared_length > previous_path.len () {
+				return Err ("Invalid path prefix length".to_owned ());
+			}
+
+			previous_path.truncate (
+				shared_length);
+
+			previous_path.extend (
+				try! (
+					read_bytes (
+						source,
+						suffix_length)));
+
+			let file_path =
+				try! (
+					recursive_path_database.for_path (
+						PathBuf::from (
+							OsStr::from_bytes (
+								& previous_path)),
+					).ok_or_else (
+						||
+
+						format! (
+							"Invalid path: {}",
+							String::from_utf8_lossy (
+								& previous_path))
+
+					));
+
+			let size =
+				try! (read_varint (source));
+
+			let content_hash_algorithm =
+				match try! (read_u8 (source)) {
+					0 => HashAlgorithm::Sha256,
+					1 => HashAlgorithm::Blake3,
+					2 => HashAlgorithm::Xxh3_128,
+					value => return Err (
+						format! (
+							"Invalid hash algorithm: {}",
+							value)),
+				};
+
+			let fields =
+				try! (read_varint (source));
+
+			let partial_hash =
+				try! (
+					read_optional_hash (
+						source,
+						fields & FIELD_PARTIAL_HASH != 0));
+
+			let partial_hash_time =
+				try! (read_signed (source));
+
+			let content_hash =
+				try! (
+					read_optional_hash (
+						source,
+						fields & FIELD_CONTENT_HASH != 0));
+
+			let content_hash_time =
+				try! (read_signed (source));
+
+			let extent_hash =
+				try! (
+					read_optional_hash (
+						source,
+						fields & FIELD_EXTENT_HASH != 0));
+
+			let extent_hash_time =
+				try! (read_signed (source));
+
+			let extent_hash_version =
+				try! (read_varint (source)) as u32;
+
+			let defragment_time =
+				try! (read_signed (source));
+
+			let deduplicate_time =
+				try! (read_signed (source));
+
+			let verify_time =
+				try! (read_signed (source));
+
+			let group_size =
+				try! (read_varint (source));
+
+			let group_shared =
+				try! (read_varint (source));
+
+			let chunk_table =
+				if fields & FIELD_CHUNK_TABLE != 0 {
+					Some (try! (read_chunk_table (source)))
+				} else {
+					None
+				};
+
+			let mtime =
+				try! (read_signed (source));
+
+			let ctime =
+				try! (read_signed (source));
+
+			let mode =
+				try! (read_varint (source)) as u32;
+
+			let uid =
+				try! (read_varint (source)) as u32;
+
+			let gid =
+				try! (read_varint (source)) as u32;
+
+			let subvolume =
+				if fields & FIELD_SUBVOLUME != 0 {
+
+				let filesystem_uuid =
+					if database_header.schema_version < 5 {
+
+					try! (read_uuid (source))
+
+				} else {
+
+					try! (
+						section_filesystem_uuid.ok_or_else (
+							||
+
+							"Subvolume given outside of a filesystem \
+							section".to_owned ()
+
+						))
+
+				};
+
+				let subvolume_id =
+					try! (read_varint (source));
+
+				let relative_path_length =
+					try! (read_varint (source)) as usize;
+
+				let relative_path_bytes =
+					try! (
+						read_bytes (
+							source,
+							relative_path_length));
+
+				Subvolume::from_relative_path (
+					filesystem_uuid,
+					subvolume_id,
+					fields & FIELD_READ_ONLY != 0,
+					& file_path,
+					Path::new (
+						OsStr::from_bytes (
+							& relative_path_bytes)))
+
+			} else {
+				None
+			};
+
+			let (ino, nlink) =
+				if fields & FIELD_INODE != 0 {
+
+				(
+					try! (read_varint (source)),
+					try! (read_varint (source)),
+				)
+
+			} else {
+				(0, 0)
+			};
+
+			entries.push (
+				FileData {
+
+				path: file_path,
+				root_path: None,
+				subvolume: subvolume,
+				size: size,
+
+				content_hash_algorithm: content_hash_algorithm,
+
+				partial_hash: partial_hash,
+				partial_hash_time: partial_hash_time,
+				partial_hash_needed: false,
+
+				content_hash: content_hash,
+				content_hash_time: content_hash_time,
+				content_hash_needed: false,
+
+				extent_hash: extent_hash,
+				extent_hash_time: extent_hash_time,
+				extent_hash_version: extent_hash_version,
+
+				defragment_time: defragment_time,
+				deduplicate_time: deduplicate_time,
+				verify_time: verify_time,
+
+				group_size: group_size,
+				group_shared: group_shared,
+
+				chunk_table: chunk_table,
+
+				mtime: mtime,
+				ctime: ctime,
+
+				mode: mode,
+				uid: uid,
+				gid: gid,
+
+				ino: ino,
+				nlink: nlink,
+
+			});
+
+		}
+
+		Ok ((
+			entries,
+			scan_generations,
+			database_header,
+		))
+
+	}
+
+	pub fn write_binary (
+		& self,
+		target: & mut Write,
+	) -> Result <(), String> {
+
+		let mut buffer: Vec <u8> =
+			Vec::new ();
+
+		buffer.extend (
+			BINARY_DATABASE_MAGIC.iter ());
+
+		for index in 0 .. 4 {
+
+			buffer.push (
+				(DATABASE_SCHEMA_VERSION >> (index * 8)) as u8);
+
+		}
+
+		write_varint (
+			& mut buffer,
+			TOOL_VERSION.len () as u64);
+
+		buffer.extend (
+			TOOL_VERSION.as_bytes ().iter ());
+
+		let mut previous_path: Vec <u8> =
+			Vec::new ();
+
+		for section in self.sections () {
+
+			if let Some (filesystem_uuid) = section.filesystem_uuid {
+
+				buffer.push (
+					RECORD_SECTION);
+
+				buffer.extend (
+					filesystem_uuid.iter ());
+
+				write_varint (
+					& mut buffer,
+					section.scan_generations.len () as u64);
+
+				for scan_generation in section.scan_generations.iter () {
+
+					let path_bytes =
+						scan_generation.path.as_os_str ().as_bytes ();
+
+					write_varint (
+						& mut buffer,
+						scan_generation.subvolume_id);
+
+					write_varint (
+						& mut buffer,
+						path_bytes.len () as u64);
+
+					buffer.extend (
+						path_bytes.iter ());
+
+					write_varint (
+						& mut buffer,
+						scan_generation.generation);
+
+					write_varint (
+						& mut buffer,
+						scan_generation.filter_fingerprint);
+
+				}
+
+			}
+
+			for file_data in section.file_data_list {
+
+				let path =
+					file_data.path.to_path ();
+
+				let path_bytes =
+					path.as_os_str ().as_bytes ();
+
+				let shared_length =
+					previous_path.iter ().zip (
+						path_bytes.iter (),
+					).take_while (
+						|& (previous_byte, byte)|
+						previous_byte == byte
+					).count ();
+
+				buffer.push (
+					RECORD_FILE);
+
+				write_varint (
+					& mut buffer,
+					shared_length as u64);
+
+				write_varint (
+					& mut buffer,
+					(path_bytes.len () - shared_length) as u64);
+
+				buffer.extend (
+					path_bytes [shared_length .. ].iter ());
+
+				previous_path.clear ();
+
+				previous_path.extend (
+					path_bytes.iter ());
+
+				write_varint (
+					& mut buffer,
+					file_data.size);
+
+				buffer.push (
+					match file_data.content_hash_algorithm {
+						HashAlgorithm::Sha256 => 0,
+						HashAlgorithm::Blake3 => 1,
+						HashAlgorithm::Xxh3_128 => 2,
+					});
+
+				let subvolume_relative_path =
+					file_data.subvolume.as_ref ().and_then (
+						|subvolume|
+
+						subvolume.relative_path (
+							& file_data.path,
+						).map (
+							|relative_path|
+							(subvolume, relative_path)
+						)
+
+					);
+
+				let fields =
+					if file_data.partial_hash != ZERO_HASH {
+						FIELD_PARTIAL_HASH
+					} else { 0 }
+				|
+					if file_data.content_hash != ZERO_HASH {
+						FIELD_CONTENT_HASH
+					} else { 0 }
+				|
+					if file_data.extent_hash != ZERO_HASH {
+						FIELD_EXTENT_HASH
+					} else { 0 }
+				|
+					if file_data.chunk_table.is_some () {
+						FIELD_CHUNK_TABLE
+					} else { 0 }
+				|
+					if subvolume_relative_path.is_some () {
+						FIELD_SUBVOLUME
+					} else { 0 }
+				|
+					if subvolume_relative_path.as_ref ().map (
+						|& (subvolume, _)|
+						subvolume.read_only
+					).unwrap_or (false) {
+						FIELD_READ_ONLY
+					} else { 0 }
+				|
+					if file_data.ino != 0 {
+						FIELD_INODE
+					} else { 0 };
+
+				write_varint (
+					& mut buffer,
+					fields);
+
+				write_optional_hash (
+					& mut buffer,
+					& file_data.partial_hash);
+
+				write_signed (
+					& mut buffer,
+					file_data.partial_hash_time);
+
+				write_optional_hash (
+					& mut buffer,
+					& file_data.content_hash);
+
+				write_signed (
+					& mut buffer,
+					file_data.content_hash_time);
+
+				write_optional_hash (
+					& mut buffer,
+					& file_data.extent_hash);
+
+				write_signed (
+					& mut buffer,
+					file_data.extent_hash_time);
+
+				write_varint (
+					& mut buffer,
+					file_data.extent_hash_version as u64);
+
+				write_signed (
+					& mut buffer,
+					file_data.defragment_time);
+
+				write_signed (
+					& mut buffer,
+					file_data.deduplicate_time);
+
+				write_signed (
+					& mut buffer,
+					file_data.verify_time);
+
+				write_varint (
+					& mut buffer,
+					file_data.group_size);
+
+				write_varint (
+					& mut buffer,
+					file_data.group_shared);
+
+				if let Some (ref chunk_table) = file_data.chunk_table {
+
+					write_chunk_table (
+						& mut buffer,
+						chunk_table);
+
+				}
+
+				write_signed (
+					& mut buffer,
+					file_data.mtime);
+
+				write_signed (
+					& mut buffer,
+					file_data.ctime);
+
+				write_varint (
+					& mut buffer,
+					file_data.mode as u64);
+
+				write_varint (
+					& mut buffer,
+					file_data.uid as u64);
+
+				write_varint (
+					& mut buffer,
+					file_data.gid as u64);
+
+				if let Some ((subvolume, relative_path)) =
+					subvolume_relative_path {
+
+					write_varint (
+						& mut buffer,
+						subvolume.subvolume_id);
+
+					let relative_path_bytes =
+						relative_path.as_os_str ().as_bytes ();
+
+					write_varint (
+						& mut buffer,
+						relative_path_bytes.len () as u64);
+
+					buffer.extend (
+						relative_path_bytes.iter ());
+
+				}
+
+				if file_data.ino != 0 {
+
+					write_varint (
+						& mut buffer,
+						file_data.ino);
+
+					write_varint (
+						& mut buffer,
+						file_data.nlink);
+
+				}
+
+				// write out in reasonably sized pieces
+
+				if buffer.len () >= 0x10000 {
+
+					try! (
+						io_result (
+							target.write_all (
+								& buffer)));
+
+					buffer.clear ();
+
+				}
+
+			}
+
+		}
+
+		buffer.push (
+			RECORD_END);
+
+		try! (
+			io_result (
+				target.write_all (
+					& buffer)));
+
+		Ok (())
+
+	}
+
+}
+
+/// Reads a chunk table in the binary format. This is also used to store chunk
+/// tables in a single column of the SQLite database.
+
+pub fn read_chunk_table (
+	source: & mut Read,
+) -> Result <ChunkTable, String> {
+
+	let chunking_method =
+		match try! (read_u8 (source)) {
+			0 => ChunkingMethod::Fixed,
+			1 => ChunkingMethod::ContentDefined,
+			value => return Err (
+				format! (
+					"Invalid chunking method: {}",
+					value)),
+		};
+
+	let chunk_size =
+		try! (read_varint (source));
+
+	let deduplicate_time =
+		try! (read_signed (source));
+
+	let num_chunks =
+		try! (read_varint (source));
+
+	let mut chunks: Vec <Chunk> =
+		Vec::new ();
+
+	let mut offset: u64 = 0;
+
+	for _ in 0 .. num_chunks {
+
+		let length =
+			try! (read_varint (source));
+
+		let hash =
+			try! (read_hash (source));
+
+		chunks.push (
+			Chunk {
+				offset: offset,
+				length: length,
+				hash: hash,
+			});
+
+		offset += length;
+
+	}
+
+	Ok (ChunkTable {
+		chunking_method: chunking_method,
+		chunk_size: chunk_size,
+		deduplicate_time: deduplicate_time,
+		chunks: chunks,
+	})
+
+}
+
+pub fn write_chunk_table (
+	buffer: & mut Vec <u8>,
+	chunk_table: & ChunkTable,
+) {
+
+	buffer.push (
+		match chunk_table.chunking_method {
+			ChunkingMethod::Fixed => 0,
+			ChunkingMethod::ContentDefined => 1,
+		});
+
+	write_varint (
+		buffer,
+		chunk_table.chunk_size);
+
+	write_signed (
+		buffer,
+		chunk_table.deduplicate_time);
+
+	write_varint (
+		buffer,
+		chunk_table.chunks.len () as u64);
+
+	for chunk in chunk_table.chunks.iter () {
+
+		write_varint (
+			buffer,
+			chunk.length);
+
+		buffer.extend (
+			chunk.hash.iter ());
+
+	}
+
+}
+
+fn read_u8 (
+	source: & mut Read,
+) -> Result <u8, String> {
+
+	let mut byte =
+		[0u8; 1];
+
+	try! (
+		io_result (
+			source.read_exact (
+				& mut byte)));
+
+	Ok (byte [0])
+
+}
+
+fn read_bytes (
+	source: & mut Read,
+	length: usize,
+) -> Result <Vec <u8>, String> {
+
+	// the length comes from the file, so don't trust it for the allocation
+
+	let mut bytes: Vec <u8> =
+		Vec::new ();
+
+	try! (
+		io_result (
+			source.take (length as u64).read_to_end (
+				& mut bytes)));
+
+	if bytes.len () != length {
+
+		return Err (
+			format! (
+				"Unexpected end of file reading {} bytes",
+				length));
+
+	}
+
+	Ok (bytes)
+
+}
+
+fn read_hash (
+	source: & mut Read,
+) -> Result <Hash, String> {
+
+	let mut hash: Hash =
+		ZERO_HASH;
+
+	try! (
+		io_result (
+			source.read_exact (
+				& mut hash)));
+
+	Ok (hash)
+
+}
+
+fn read_uuid (
+	source: & mut Read,
+) -> Result <Uuid, String> {
+
+	let mut uuid: Uuid =
+		ZERO_UUID;
+
+	try! (
+		io_result (
+			source.read_exact (
+				& mut uuid)));
+
+	Ok (uuid)
+
+}
+
+fn read_optional_hash (
+	source: & mut Read,
+	present: bool,
+) -> Result <Hash, String> {
+
+	if present {
+		read_hash (source)
+	} else {
+		Ok (ZERO_HASH)
+	}
+
+}
+
+fn write_optional_hash (
+	buffer: & mut Vec <u8>,
+	hash: & Hash,
+) {
+
+	if * hash != ZERO_HASH {
+
+		buffer.extend (
+			hash.iter ());
+
+	}
+
+}
+
+fn read_varint (
+	source: & mut Read,
+) -> Result <u64, String> {
+
+	let mut value: u64 = 0;
+	let mut shift: u32 = 0;
+
+	loop {
+
+		let byte =
+			try! (read_u8 (source));
+
+		if shift > 63 {
+			return Err ("Invalid varint".to_owned ());
+		}
+
+		value |= ((byte & 0x7f) as u64) << shift;
+
+		if byte & 0x80 == 0 {
+			return Ok (value);
+		}
+
+		shift += 7;
+
+	}
+
+}
+
+fn write_varint (
+	buffer: & mut Vec <u8>,
+	mut value: u64,
+) {
+
+	while value >= 0x80 {
+
+		buffer.push (
+			(value as u8 & 0x7f) | 0x80);
+
+		value >>= 7;
+
+	}
+
+	buffer.push (
+		value as u8);
+
+}
+
+fn read_signed (
+	source: & mut Read,
+) -> Result <i64, String> {
+
+	let value =
+		try! (read_varint (source));
+
+	Ok ((value >> 1) as i64 ^ - ((value & 1) as i64))
+
+}
+
+fn write_signed (
+	buffer: & mut Vec <u8>,
+	value: i64,
+) {
+
+	write_varint (
+		buffer,
+		((value << 1) ^ (value >> 63)) as u64);
+
+}
+
+#[ cfg (test) ]
+mod tests {
+
+	use super::*;
+
+	#[ test ]
+	fn varint_round_trip () {
+
+		let values: & [u64] =
+			& [0, 1, 0x7f, 0x80, 300, 0x3fff, 0x4000, u64::MAX - 1, u64::MAX];
+
+		for & value in values {
+
+			let mut buffer: Vec <u8> =
+				Vec::new ();
+
+			write_varint (
+				& mut buffer,
+				value);
+
+			assert_eq! (
+				read_varint (& mut & buffer [..]),
+				Ok (value));
+
+		}
+
+	}
+
+	#[ test ]
+	fn varint_truncated () {
+
+		let mut buffer: Vec <u8> =
+			Vec::new ();
+
+		write_varint (
+			& mut buffer,
+			300);
+
+		assert! (
+			read_varint (
+				& mut & buffer [0 .. 1],
+			).is_err ());
+
+	}
+
+	#[ test ]
+	fn bytes_truncated () {
+
+		let buffer: & [u8] =
+			& [1, 2, 3];
+
+		assert_eq! (
+			read_bytes (& mut & buffer [..], 3),
+			Ok (vec! [1, 2, 3]));
+
+		assert! (
+			read_bytes (
+				& mut & buffer [..],
+				0xffffffff,
+			).is_err ());
+
+	}
+
+	#[ test ]
+	fn signed_round_trip () {
+
+		let values: & [i64] =
+			& [0, 1, -1, 63, -64, 64, -65, i64::MIN, i64::MAX];
+
+		for & value in values {
+
+			let mut buffer: Vec <u8> =
+				Vec::new ();
+
+			write_signed (
+				& mut buffer,
+				value);
+
+			assert_eq! (
+				read_signed (& mut & buffer [..]),
+				Ok (value));
+
+		}
+
+		// small values of either sign stay small
+
+		let mut buffer: Vec <u8> =
+			Vec::new ();
+
+		write_signed (
+			& mut buffer,
+			-64);
+
+		assert_eq! (
+			buffer.len (),
+			1);
+
+	}
+
+	#[ test ]
+	fn path_round_trip () {
+
+		let mut recursive_path_database =
+			RecursivePathDatabase::new ();
+
+		// paths sharing prefixes of different lengths, including one whose
+		// whole path is a prefix of the next, and one sharing nothing
+
+		let paths = [
+			"/data/a",
+			"/data/ab",
+			"/data/b/c/d",
+			"/data/b/c/e",
+			"/data/bc",
+			"/other",
+		];
+
+		let file_data_list: Vec <FileData> =
+			paths.iter ().map (
+				|path|
+
+				test_file_data (
+					recursive_path_database.for_path (
+						path,
+					).unwrap ())
+
+			).collect ();
+
+		let file_database =
+			FileDatabase::new (
+				file_data_list);
+
+		let mut buffer: Vec <u8> =
+			Vec::new ();
+
+		file_database.write_binary (
+			& mut buffer,
+		).unwrap ();
+
+		let (entries, scan_generations, database_header) =
+			FileDatabase::read_binary_entries (
+				& mut recursive_path_database,
+				& mut & buffer [..],
+			).unwrap ();
+
+		assert_eq! (
+			database_header.schema_version,
+			DATABASE_SCHEMA_VERSION);
+
+		assert! (
+			scan_generations.is_empty ());
+
+		let read_paths: Vec <String> =
+			entries.iter ().map (
+				|file_data|
+				file_data.path.to_string_lossy ()
+			).collect ();
+
+		assert_eq! (
+			read_paths,
+			paths.iter ().map (
+				|path|
+				path.to_string ()
+			).collect::<Vec <String>> ());
+
+		for (entry, original) in entries.iter ().zip (file_database.iter ()) {
+
+			assert_eq! (
+				entry.size,
+				original.size);
+
+			assert_eq! (
+				entry.mtime,
+				original.mtime);
+
+		}
+
+	}
+
+	fn test_file_data (
+		path: RecursivePathRef,
+	) -> FileData {
+
+		let depth =
+			path.to_path ().components ().count () as i64;
+
+		FileData {
+
+			path: path,
+			root_path: None,
+			subvolume: None,
+
+			size: 0x1000 * depth as u64,
+
+			content_hash_algorithm: HashAlgorithm::Sha256,
+
+			partial_hash: ZERO_HASH,
+			partial_hash_time: 0,
+			partial_hash_needed: false,
+
+			content_hash: ZERO_HASH,
+			content_hash_time: 0,
+			content_hash_needed: false,
+
+			extent_hash: ZERO_HASH,
+			extent_hash_time: 0,
+			extent_hash_version: 0,
+
+			defragment_time: 0,
+			deduplicate_time: 0,
+			verify_time: 0,
+
+			group_size: 0,
+			group_shared: 0,
+
+			chunk_table: None,
+
+			mtime: - depth,
+			ctime: depth,
+
+			mode: 0o100644,
+			uid: 0,
+			gid: 0,
+
+			ino: 0,
+			nlink: 0,
+
+		}
+
+	}
+
+}
+
+// ex: noet ts=4 filetype=rust
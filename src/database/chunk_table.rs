@@ -0,0 +1,65 @@
+use types::*;
+
+/// The hashes of consecutive chunks of a file's contents, used to find ranges
+/// which are shared between files which are not identical as a whole. Chunks
+/// are hashed with the file's content hash algorithm.
+
+#[ derive (Clone, Debug, Eq, Hash, PartialEq) ]
+pub struct ChunkTable {
+	pub chunking_method: ChunkingMethod,
+	pub chunk_size: u64,
+	pub deduplicate_time: i64,
+	pub chunks: Vec <Chunk>,
+}
+
+#[ derive (Clone, Copy, Debug, Eq, Hash, PartialEq) ]
+pub struct Chunk {
+	pub offset: u64,
+	pub length: u64,
+	pub hash: Hash,
+}
+
+/// Fixed chunks all have the same size, except the last. Content defined
+/// chunks end wherever a rolling hash of the contents says so, which means
+/// they still line up after data is inserted or removed earlier in a file, and
+/// the chunk size is only an average.
+
+#[ derive (Clone, Copy, Debug, Eq, Hash, PartialEq) ]
+pub enum ChunkingMethod {
+	Fixed,
+	ContentDefined,
+}
+
+impl ChunkingMethod {
+
+	pub fn names (
+	) -> & 'static [& 'static str] {
+		& [ "fixed", "content-defined" ]
+	}
+
+	pub fn from_name (
+		name: & str,
+	) -> Option <ChunkingMethod> {
+
+		match name {
+			"fixed" => Some (ChunkingMethod::Fixed),
+			"content-defined" => Some (ChunkingMethod::ContentDefined),
+			_ => None,
+		}
+
+	}
+
+	pub fn name (
+		& self,
+	) -> & 'static str {
+
+		match * self {
+			ChunkingMethod::Fixed => "fixed",
+			ChunkingMethod::ContentDefined => "content-defined",
+		}
+
+	}
+
+}
+
+// ex: noet ts=4 filetype=rust
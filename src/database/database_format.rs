@@ -0,0 +1,99 @@
+use std::fs::File;
+use std::path::Path;
+
+use misc::*;
+
+/// The formats the database can be written in. The JSON format is a gzipped
+/// list of JSON objects, one per line. The binary format is described in the
+/// `binary_database` module. The SQLite format keeps one row per file, and is
+/// updated in place rather than rewritten. Any of them can be read regardless
+/// of this setting, since binary and SQLite databases start with a magic
+/// number.
+
+#[ derive (Clone, Copy, Debug, Eq, PartialEq) ]
+pub enum DatabaseFormat {
+	Json,
+	Binary,
+	Sqlite,
+}
+
+pub const BINARY_DATABASE_MAGIC: & 'static [u8; 8] = b"BTRFSDDB";
+
+pub const SQLITE_DATABASE_MAGIC: & 'static [u8; 16] = b"SQLite format 3\0";
+
+impl DatabaseFormat {
+
+	pub fn names (
+	) -> & 'static [& 'static str] {
+		& [ "json", "binary", "sqlite" ]
+	}
+
+	pub fn from_name (
+		name: & str,
+	) -> Option <DatabaseFormat> {
+
+		match name {
+			"json" => Some (DatabaseFormat::Json),
+			"binary" => Some (DatabaseFormat::Binary),
+			"sqlite" => Some (DatabaseFormat::Sqlite),
+			_ => None,
+		}
+
+	}
+
+	pub fn name (
+		& self,
+	) -> & 'static str {
+
+		match * self {
+			DatabaseFormat::Json => "json",
+			DatabaseFormat::Binary => "binary",
+			DatabaseFormat::Sqlite => "sqlite",
+		}
+
+	}
+
+	/// Works out the format of a database from its first few bytes.
+
+	pub fn detect (
+		header: & [u8],
+	) -> DatabaseFormat {
+
+		if header.starts_with (BINARY_DATABASE_MAGIC) {
+			DatabaseFormat::Binary
+		} else if header.starts_with (SQLITE_DATABASE_MAGIC) {
+			DatabaseFormat::Sqlite
+		} else {
+			DatabaseFormat::Json
+		}
+
+	}
+
+	/// Works out which format an existing database file is in.
+
+	pub fn detect_file (
+		database_path: & Path,
+	) -> Result <DatabaseFormat, String> {
+
+		let mut header =
+			[0u8; 16];
+
+		let header_length =
+			io_result (
+				read_fully (
+					& mut io_result (
+						File::open (
+							database_path),
+					) ?,
+					& mut header),
+			) ?;
+
+		Ok (
+			DatabaseFormat::detect (
+				& header [0 .. header_length]))
+
+	}
+
+}
+
+// ex: noet ts=4 filetype=rust
@@ -0,0 +1,425 @@
+//! The journal lists the changes made since the database was last written in
+//! full, one JSON record per line, and lives in a file next to it. Each record
+//! holds the complete state of a file, so replaying a journal over a database
+//! which already includes some of its changes does no harm.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+use serde_json;
+
+use database::*;
+use misc::*;
+
+/// A single change recorded in the journal.
+
+#[ derive (Debug) ]
+pub enum JournalEntry {
+	Changed (FileData),
+	Removed (RecursivePathRef),
+}
+
+/// Reads the changes recorded in a journal, in the order they were made. An
+/// incomplete record at the end, left by an interrupted write, is ignored, and
+/// false is returned alongside the changes so the caller knows not to append to
+/// it.
+
+pub fn read_journal (
+	recursive_path_database: & mut RecursivePathDatabase,
+	source: & mut Read,
+) -> Result <(Vec <JournalEntry>, bool), String> {
+
+	let mut source =
+		BufReader::new (
+			source);
+
+	let mut journal_entries: Vec <JournalEntry> =
+		Vec::new ();
+
+	let mut complete = true;
+
+	let mut line: Vec <u8> =
+		Vec::new ();
+
+	loop {
+
+		line.clear ();
+
+		let bytes_read =
+			io_result (
+				source.read_until (
+					b'\n',
+					& mut line),
+			) ?;
+
+		if bytes_read == 0 {
+			break;
+		}
+
+		if line.last () != Some (& b'\n') {
+
+			complete = false;
+
+			break;
+
+		}
+
+		let journal_record: JournalRecord =
+			serde_json::from_slice (
+				& line,
+			).map_err (
+				|serde_error|
+
+				format! (
+					"Journal deserialization error: {}",
+					serde_error)
+
+			) ?;
+
+		if let Some (file_data_record) = journal_record.file {
+
+			journal_entries.push (
+				JournalEntry::Changed (
+					decode_file_data_record (
+						recursive_path_database,
+						file_data_record,
+					) ?));
+
+		}
+
+		if let Some (removed_path) = journal_record.removed {
+
+			if let Some (removed_path) =
+				recursive_path_database.for_path (
+					removed_path) {
+
+				journal_entries.push (
+					JournalEntry::Removed (
+						removed_path));
+
+			}
+
+		}
+
+	}
+
+	Ok ((journal_entries, complete))
+
+}
+
+/// Applies changes read from a journal to the files read from a database. The
+/// result is in no particular order.
+
+pub fn apply_journal (
+	file_data_list: Vec <FileData>,
+	journal_entries: Vec <JournalEntry>,
+) -> Vec <FileData> {
+
+	let mut file_data_map: HashMap <RecursivePathRef, FileData> =
+		file_data_list.into_iter ().map (
+			|file_data|
+			(file_data.path.clone (), file_data)
+		).collect ();
+
+	for journal_entry in journal_entries.into_iter () {
+
+		match journal_entry {
+
+			JournalEntry::Changed (file_data) => {
+
+				file_data_map.insert (
+					file_data.path.clone (),
+					file_data);
+
+			},
+
+			JournalEntry::Removed (removed_path) => {
+
+				file_data_map.remove (
+					& removed_path);
+
+			},
+
+		}
+
+	}
+
+	file_data_map.into_values ().collect ()
+
+}
+
+/// Appends a record to the journal for every file which has changed since the
+/// fingerprints were taken, and for every one which has gone, then updates the
+/// fingerprints to match.
+
+pub fn write_journal (
+	fingerprints: & mut HashMap <RecursivePathRef, u64>,
+	file_database: & FileDatabase,
+	target: & mut Write,
+) -> Result <(), String> {
+
+	let mut new_fingerprints: HashMap <RecursivePathRef, u64> =
+		HashMap::new ();
+
+	for file_data in file_database.iter () {
+
+		let fingerprint =
+			file_data.fingerprint ();
+
+		if fingerprints.get (
+			& file_data.path,
+		) != Some (& fingerprint) {
+
+			write_journal_record (
+				target,
+				& JournalRecord {
+					file: Some (
+						encode_file_data_record (
+							file_data)),
+					removed: None,
+				},
+			) ?;
+
+		}
+
+		new_fingerprints.insert (
+			file_data.path.clone (),
+			fingerprint);
+
+	}
+
+	for path in fingerprints.keys () {
+
+		if new_fingerprints.contains_key (path) {
+			continue;
+		}
+
+		write_journal_record (
+			target,
+			& JournalRecord {
+				file: None,
+				removed: Some (path.to_path ()),
+			},
+		) ?;
+
+	}
+
+	* fingerprints =
+		new_fingerprints;
+
+	Ok (())
+
+}
+
+/// Takes a fingerprint of every file, to compare against when the journal is
+/// next written.
+
+pub fn database_fingerprints (
+	file_database: & FileDatabase,
+) -> HashMap <RecursivePathRef, u64> {
+
+	file_database.iter ().map (
+		|file_data|
+		(file_data.path.clone (), file_data.fingerprint ())
+	).collect ()
+
+}
+
+pub fn journal_path (
+	database_path: & Path,
+) -> PathBuf {
+
+	let mut journal_path =
+		database_path.as_os_str ().to_owned ();
+
+	journal_path.push (
+		".journal");
+
+	PathBuf::from (
+		journal_path)
+
+}
+
+pub fn remove_journal (
+	database_path: & Path,
+) -> Result <(), String> {
+
+	match fs::remove_file (
+		journal_path (
+			database_path)) {
+
+		Err (ref io_error)
+		if io_error.kind () == io::ErrorKind::NotFound =>
+			Ok (()),
+
+		result =>
+			io_result (result),
+
+	}
+
+}
+
+fn write_journal_record (
+	target: & mut Write,
+	journal_record: & JournalRecord,
+) -> Result <(), String> {
+
+	let mut journal_json =
+		serde_json::to_vec (
+			journal_record,
+		).map_err (
+			|serde_error|
+
+			format! (
+				"Journal serialization error: {}",
+				serde_error)
+
+		) ?;
+
+	journal_json.push (
+		b'\n');
+
+	io_result (
+		target.write_all (
+			& journal_json),
+	)
+
+}
+
+#[ cfg (test) ]
+mod tests {
+
+	use super::*;
+
+	fn file_record (
+		path: & str,
+		size: u64,
+	) -> String {
+
+		format! (
+			"{{\"file\":{{\"path\":\"{}\",\"size\":{},\"mtime\":0,\
+			\"ctime\":0,\"mode\":33188,\"uid\":0,\"gid\":0}}}}\n",
+			path,
+			size)
+
+	}
+
+	fn replay (
+		journal: & str,
+	) -> Result <(Vec <(String, u64)>, bool), String> {
+
+		let mut recursive_path_database =
+			RecursivePathDatabase::new ();
+
+		let (journal_entries, complete) =
+			read_journal (
+				& mut recursive_path_database,
+				& mut journal.as_bytes (),
+			) ?;
+
+		let mut files: Vec <(String, u64)> =
+			apply_journal (
+				Vec::new (),
+				journal_entries,
+			).iter ().map (
+				|file_data|
+				(file_data.path.to_string_lossy (), file_data.size)
+			).collect ();
+
+		files.sort ();
+
+		Ok ((files, complete))
+
+	}
+
+	#[ test ]
+	fn replay_complete_journal () {
+
+		let journal =
+			format! (
+				"{}{}{}{{\"removed\":\"/data/b\"}}\n",
+				file_record ("/data/a", 1),
+				file_record ("/data/b", 2),
+				file_record ("/data/a", 3));
+
+		assert_eq! (
+			replay (& journal),
+			Ok ((
+				vec! [ ("/data/a".to_string (), 3) ],
+				true,
+			)));
+
+	}
+
+	#[ test ]
+	fn replay_torn_last_line () {
+
+		let journal =
+			format! (
+				"{}{}",
+				file_record ("/data/a", 1),
+				file_record ("/data/b", 2));
+
+		// an interrupted write leaves part of a record without its newline
+
+		let torn_journal =
+			format! (
+				"{}{}",
+				journal,
+				& file_record ("/data/c", 3) [0 .. 30]);
+
+		assert_eq! (
+			replay (& torn_journal),
+			Ok ((
+				vec! [
+					("/data/a".to_string (), 1),
+					("/data/b".to_string (), 2),
+				],
+				false,
+			)));
+
+		// a record which is complete, but still ends without a newline, is
+		// treated the same way
+
+		let unterminated_journal =
+			format! (
+				"{}{}",
+				journal,
+				file_record ("/data/c", 3).trim_end ());
+
+		assert_eq! (
+			replay (& unterminated_journal),
+			Ok ((
+				vec! [
+					("/data/a".to_string (), 1),
+					("/data/b".to_string (), 2),
+				],
+				false,
+			)));
+
+	}
+
+	#[ test ]
+	fn replay_corrupt_line () {
+
+		let journal =
+			format! (
+				"{}{{\"file\":\n{}",
+				file_record ("/data/a", 1),
+				file_record ("/data/b", 2));
+
+		assert! (
+			replay (& journal).is_err ());
+
+	}
+
+}
+
+// ex: noet ts=4 filetype=rust
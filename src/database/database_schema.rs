@@ -0,0 +1,434 @@
+//! Every database starts with a header giving the version of its schema, and
+//! the version of the tool which wrote it. Databases written before the header
+//! was added are treated as schema version one.
+//!
+//! When the information recorded about files changes, the schema version is
+//! increased, and a migration is added to upgrade databases from the previous
+//! version. Migrations are applied in order when a database is read, and the
+//! upgraded database replaces the original the next time it is written.
+//! Databases with a newer schema than this tool understands are refused.
+
+use database::*;
+use types::*;
+
+pub const DATABASE_SCHEMA_VERSION: u32 = 8;
+
+pub const TOOL_VERSION: & 'static str = env! ("CARGO_PKG_VERSION");
+
+#[ derive (Clone, Debug, Eq, PartialEq) ]
+pub struct DatabaseHeader {
+	pub schema_version: u32,
+	pub tool_version: String,
+}
+
+/// Upgrades a database from the previous schema version to `schema_version`.
+/// The SQL is run against SQLite databases before they are read, and the
+/// function is then applied to every file, whatever the format.
+
+pub struct DatabaseMigration {
+	pub schema_version: u32,
+	pub description: & 'static str,
+	pub sqlite_statements: & 'static str,
+	pub migrate_file_data: fn (& mut FileData),
+}
+
+pub const DATABASE_MIGRATIONS: & 'static [DatabaseMigration] = & [
+
+	DatabaseMigration {
+		schema_version: 2,
+		description: "add database header",
+		sqlite_statements: "
+			CREATE TABLE IF NOT EXISTS metadata (
+				name TEXT NOT NULL PRIMARY KEY,
+				value TEXT NOT NULL
+			);
+		",
+		migrate_file_data: migrate_file_data_unchanged,
+	},
+
+	DatabaseMigration {
+		schema_version: 3,
+		description: "invalidate hashes damaged by a decoding bug",
+		sqlite_statements: "",
+		migrate_file_data: invalidate_damaged_hashes,
+	},
+
+	DatabaseMigration {
+		schema_version: 4,
+		description: "record filesystem and subvolume",
+		sqlite_statements: "
+			ALTER TABLE files ADD COLUMN filesystem_uuid TEXT;
+			ALTER TABLE files ADD COLUMN subvolume_id INTEGER;
+			ALTER TABLE files ADD COLUMN subvolume_path TEXT;
+			CREATE INDEX IF NOT EXISTS files_subvolume
+				ON files (filesystem_uuid, subvolume_id);
+		",
+		migrate_file_data: migrate_file_data_unchanged,
+	},
+
+	DatabaseMigration {
+		schema_version: 5,
+		description: "group files by filesystem",
+		sqlite_statements: "",
+		migrate_file_data: migrate_file_data_unchanged,
+	},
+
+	DatabaseMigration {
+		schema_version: 6,
+		description: "record inode and link count",
+		sqlite_statements: "
+			ALTER TABLE files ADD COLUMN ino INTEGER NOT NULL DEFAULT 0;
+			ALTER TABLE files ADD COLUMN nlink INTEGER NOT NULL DEFAULT 0;
+		",
+		migrate_file_data: migrate_file_data_unchanged,
+	},
+
+	DatabaseMigration {
+		schema_version: 7,
+		description: "record scan generations",
+		sqlite_statements: "
+			CREATE TABLE IF NOT EXISTS scan_generations (
+				filesystem_uuid TEXT NOT NULL,
+				subvolume_id INTEGER NOT NULL,
+				path TEXT NOT NULL,
+				generation INTEGER NOT NULL,
+				filter_fingerprint INTEGER NOT NULL,
+				PRIMARY KEY (filesystem_uuid, subvolume_id, path)
+			);
+		",
+		migrate_file_data: migrate_file_data_unchanged,
+	},
+
+	DatabaseMigration {
+		schema_version: 8,
+		description: "record read only subvolumes",
+		sqlite_statements: "
+			ALTER TABLE files ADD COLUMN subvolume_read_only INTEGER NOT NULL
+				DEFAULT 0;
+		",
+		migrate_file_data: migrate_file_data_unchanged,
+	},
+
+];
+
+impl DatabaseHeader {
+
+	/// The header written by this version of the tool.
+
+	pub fn current (
+	) -> DatabaseHeader {
+
+		DatabaseHeader {
+			schema_version: DATABASE_SCHEMA_VERSION,
+			tool_version: TOOL_VERSION.to_owned (),
+		}
+
+	}
+
+	/// Stands in for the header of a database written before there was one.
+
+	pub fn legacy (
+	) -> DatabaseHeader {
+
+		DatabaseHeader {
+			schema_version: 1,
+			tool_version: "unknown".to_owned (),
+		}
+
+	}
+
+	/// Refuses databases with a schema this version of the tool doesn't know
+	/// about, before trying to make sense of the rest of them.
+
+	pub fn check (
+		& self,
+	) -> Result <(), String> {
+
+		if self.schema_version > DATABASE_SCHEMA_VERSION {
+
+			return Err (
+				format! (
+					"Database schema version {} was written by btrfs-dedupe \
+					{}, but this is version {}, which only supports up to \
+					schema version {}",
+					self.schema_version,
+					self.tool_version,
+					TOOL_VERSION,
+					DATABASE_SCHEMA_VERSION));
+
+		}
+
+		if self.schema_version < 1 {
+
+			return Err (
+				format! (
+					"Invalid database schema version: {}",
+					self.schema_version));
+
+		}
+
+		Ok (())
+
+	}
+
+	pub fn needs_migration (
+		& self,
+	) -> bool {
+		self.schema_version < DATABASE_SCHEMA_VERSION
+	}
+
+	/// Lists the migrations needed to bring a database with this header up to
+	/// date, in the order they should be applied.
+
+	pub fn migrations (
+		& self,
+	) -> Vec <& 'static DatabaseMigration> {
+
+		DATABASE_MIGRATIONS.iter ().filter (
+			|migration|
+			migration.schema_version > self.schema_version
+		).collect ()
+
+	}
+
+}
+
+/// Applies every migration needed for a database with the given header to the
+/// files it contains.
+
+pub fn migrate_file_database (
+	database_header: & DatabaseHeader,
+	file_database: & mut FileDatabase,
+) {
+
+	for migration in database_header.migrations () {
+
+		for file_data in file_database.iter_mut () {
+
+			(migration.migrate_file_data) (
+				file_data);
+
+		}
+
+	}
+
+}
+
+/// Applies every migration needed for a database with the given header to a
+/// single file.
+
+pub fn migrate_file_data (
+	database_header: & DatabaseHeader,
+	file_data: & mut FileData,
+) {
+
+	for migration in database_header.migrations () {
+
+		(migration.migrate_file_data) (
+			file_data);
+
+	}
+
+}
+
+/// Older versions decoded hashes with the last byte copied from the 22nd, so
+/// any hash where those bytes match may have been damaged. A few undamaged
+/// hashes will match by chance, and these are simply calculated again.
+
+pub fn is_damaged_hash (
+	hash: & Hash,
+) -> bool {
+
+	* hash != ZERO_HASH
+	&& hash [HASH_SIZE - 1] == hash [21]
+
+}
+
+fn invalidate_damaged_hashes (
+	file_data: & mut FileData,
+) {
+
+	if is_damaged_hash (& file_data.partial_hash) {
+
+		file_data.partial_hash = ZERO_HASH;
+		file_data.partial_hash_time = 0;
+
+	}
+
+	if is_damaged_hash (& file_data.content_hash) {
+
+		file_data.content_hash = ZERO_HASH;
+		file_data.content_hash_time = 0;
+		file_data.verify_time = 0;
+
+	}
+
+	if is_damaged_hash (& file_data.extent_hash) {
+
+		file_data.extent_hash = ZERO_HASH;
+		file_data.extent_hash_time = 0;
+
+	}
+
+	let chunk_table_damaged =
+		file_data.chunk_table.as_ref ().map (
+			|chunk_table|
+
+			chunk_table.chunks.iter ().any (
+				|chunk|
+				is_damaged_hash (& chunk.hash))
+
+		).unwrap_or (false);
+
+	if chunk_table_damaged {
+		file_data.chunk_table = None;
+	}
+
+}
+
+fn migrate_file_data_unchanged (
+	_file_data: & mut FileData,
+) {
+}
+
+#[ cfg (test) ]
+mod tests {
+
+	use serde_json;
+
+	use super::*;
+
+	fn header (
+		schema_version: u32,
+	) -> DatabaseHeader {
+
+		DatabaseHeader {
+			schema_version: schema_version,
+			tool_version: "0.0.0".to_owned (),
+		}
+
+	}
+
+	#[ test ]
+	fn one_migration_per_schema_version () {
+
+		let schema_versions: Vec <u32> =
+			DATABASE_MIGRATIONS.iter ().map (
+				|migration|
+				migration.schema_version
+			).collect ();
+
+		assert_eq! (
+			schema_versions,
+			(2 ..= DATABASE_SCHEMA_VERSION).collect::<Vec <u32>> ());
+
+	}
+
+	#[ test ]
+	fn migrations_needed () {
+
+		assert! (
+			DatabaseHeader::legacy ().needs_migration ());
+
+		assert_eq! (
+			DatabaseHeader::legacy ().migrations ().len (),
+			DATABASE_MIGRATIONS.len ());
+
+		assert! (
+			! DatabaseHeader::current ().needs_migration ());
+
+		assert! (
+			DatabaseHeader::current ().migrations ().is_empty ());
+
+		// only later migrations are applied, in order
+
+		let migrations =
+			header (1).migrations ();
+
+		assert_eq! (
+			migrations [0].schema_version,
+			2);
+
+		assert_eq! (
+			migrations.last ().unwrap ().schema_version,
+			DATABASE_SCHEMA_VERSION);
+
+	}
+
+	#[ test ]
+	fn check_schema_version () {
+
+		assert! (
+			DatabaseHeader::legacy ().check ().is_ok ());
+
+		assert! (
+			DatabaseHeader::current ().check ().is_ok ());
+
+		assert! (
+			header (0).check ().is_err ());
+
+		assert! (
+			header (DATABASE_SCHEMA_VERSION + 1).check ().is_err ());
+
+	}
+
+	#[ test ]
+	fn damaged_hashes () {
+
+		let mut hash =
+			ZERO_HASH;
+
+		assert! (! is_damaged_hash (& hash));
+
+		for (index, byte) in hash.iter_mut ().enumerate () {
+			* byte = index as u8 + 1;
+		}
+
+		assert! (! is_damaged_hash (& hash));
+
+		hash [HASH_SIZE - 1] = hash [21];
+
+		assert! (is_damaged_hash (& hash));
+
+	}
+
+	#[ test ]
+	fn migrate_damaged_hashes () {
+
+		let file_data_record: FileDataRecord =
+			serde_json::from_str (
+				"{\"path\":\"/data/a\",\"size\":1,\"mtime\":0,\"ctime\":0,\
+				\"mode\":33188,\"uid\":0,\"gid\":0,\
+				\"partial_hash\":\"0102030405060708090a0b0c0d0e0f10\
+				1112131415161718191a1b1c1d1e1f20\",\
+				\"partial_hash_time\":100,\
+				\"content_hash\":\"0102030405060708090a0b0c0d0e0f10\
+				1112131415161718191a1b1c1d1e1f16\",\
+				\"content_hash_time\":100,\"verify_time\":100}",
+			).unwrap ();
+
+		let mut file_data =
+			decode_file_data_record (
+				& mut RecursivePathDatabase::new (),
+				file_data_record,
+			).unwrap ();
+
+		let partial_hash =
+			file_data.partial_hash;
+
+		migrate_file_data (
+			& header (2),
+			& mut file_data);
+
+		assert_eq! (file_data.partial_hash, partial_hash);
+		assert_eq! (file_data.partial_hash_time, 100);
+
+		assert_eq! (file_data.content_hash, ZERO_HASH);
+		assert_eq! (file_data.content_hash_time, 0);
+		assert_eq! (file_data.verify_time, 0);
+
+	}
+
+}
+
+// ex: noet ts=4 filetype=rust
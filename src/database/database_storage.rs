@@ -0,0 +1,152 @@
+use std::path::Path;
+use std::path::PathBuf;
+
+use database::*;
+use types::*;
+
+/// Somewhere the file database is kept between runs. Storage keeps track of
+/// what it last wrote, so that each write only needs to record what has
+/// changed since.
+
+pub trait DatabaseStorage {
+
+	fn path (
+		& self,
+	) -> & Path;
+
+	/// Reads the whole database. Whatever format the existing database is in
+	/// can be read, so the format can be changed between runs.
+
+	fn read (
+		& mut self,
+		recursive_path_database: & mut RecursivePathDatabase,
+		root_paths: & [PathRef],
+	) -> Result <FileDatabase, String>;
+
+	/// Reads the database exactly as it is stored, without putting it in
+	/// order, applying the journal, or migrating it, so that it can be checked
+	/// for damage. Nothing is remembered for later writes.
+
+	fn read_stored (
+		& mut self,
+		recursive_path_database: & mut RecursivePathDatabase,
+	) -> Result <StoredDatabase, String>;
+
+	/// Brings the stored database up to date with the one given.
+
+	fn write (
+		& mut self,
+		file_database: & FileDatabase,
+	) -> Result <(), String>;
+
+	/// Brings the stored database up to date, and leaves it in a form which is
+	/// quickest to read next time.
+
+	fn compact (
+		& mut self,
+		file_database: & FileDatabase,
+	) -> Result <(), String>;
+
+}
+
+/// The contents of a database as they are stored.
+
+pub struct StoredDatabase {
+	pub database_format: DatabaseFormat,
+	pub database_header: DatabaseHeader,
+	pub file_data_list: Vec <FileData>,
+	pub file_data_ordered: bool,
+	pub scan_generations: Vec <ScanGeneration>,
+	pub journal_entries: Vec <JournalEntry>,
+	pub journal_complete: bool,
+}
+
+impl StoredDatabase {
+
+	/// Applies the journal and any migrations, and builds the database. Where
+	/// a file appears more than once, the last one is kept.
+
+	pub fn into_file_database (
+		self,
+		recursive_path_database: & mut RecursivePathDatabase,
+		root_paths: & [PathRef],
+	) -> FileDatabase {
+
+		let database_header =
+			self.database_header;
+
+		let file_data_list =
+			if self.journal_entries.is_empty () {
+				self.file_data_list
+			} else {
+				apply_journal (
+					self.file_data_list,
+					self.journal_entries)
+			};
+
+		let mut file_database =
+			FileDatabase::from_unordered (
+				recursive_path_database,
+				root_paths,
+				file_data_list);
+
+		migrate_file_database (
+			& database_header,
+			& mut file_database);
+
+		file_database.set_scan_generations (
+			self.scan_generations);
+
+		file_database
+
+	}
+
+}
+
+/// Creates the storage which writes a database in the given format.
+
+pub fn open_database_storage (
+	database_path: & Path,
+	database_format: DatabaseFormat,
+	journal_size_limit: u64,
+) -> Box <DatabaseStorage> {
+
+	match FlatFileFormat::from_database_format (
+		database_format) {
+
+		Some (flat_file_format) =>
+			Box::new (
+				FlatFileStorage::new (
+					database_path,
+					flat_file_format,
+					journal_size_limit)),
+
+		None =>
+			Box::new (
+				SqliteStorage::new (
+					database_path)),
+
+	}
+
+}
+
+/// Works out the name of a file which is written first, and then renamed over
+/// the database, so an interruption can't leave a partially written database
+/// behind.
+
+pub fn temporary_database_path (
+	database_path: & Path,
+) -> PathBuf {
+
+	let mut database_path_temp =
+		database_path.as_os_str ().to_owned ();
+
+	database_path_temp.push (
+		".temp");
+
+	PathBuf::from (
+		database_path_temp)
+
+}
+
+// ex: noet ts=4 filetype=rust
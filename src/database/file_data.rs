@@ -1,4 +1,10 @@
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash;
+use std::hash::Hasher;
+
 use database::*;
+use hash_algorithm::*;
 use types::*;
 
 #[ derive (Debug, Eq, Hash, PartialEq) ]
@@ -6,17 +12,32 @@ pub struct FileData {
 
 	pub path: RecursivePathRef,
 	pub root_path: Option <PathRef>,
+	pub subvolume: Option <Subvolume>,
 
     pub size: u64,
 
+	pub content_hash_algorithm: HashAlgorithm,
+
+	pub partial_hash: Hash,
+	pub partial_hash_time: i64,
+	pub partial_hash_needed: bool,
+
 	pub content_hash: Hash,
 	pub content_hash_time: i64,
+	pub content_hash_needed: bool,
 
 	pub extent_hash: Hash,
 	pub extent_hash_time: i64,
+	pub extent_hash_version: u32,
 
 	pub defragment_time: i64,
 	pub deduplicate_time: i64,
+	pub verify_time: i64,
+
+	pub group_size: u64,
+	pub group_shared: u64,
+
+	pub chunk_table: Option <ChunkTable>,
 
 	pub mtime: i64,
 	pub ctime: i64,
@@ -25,6 +46,130 @@ pub struct FileData {
     pub uid: u32,
     pub gid: u32,
 
+	pub ino: u64,
+	pub nlink: u64,
+
+}
+
+impl FileData {
+
+	/// Identifies the filesystem the file is on. Files which aren't known to
+	/// be in a subvolume are identified by the device of their root path,
+	/// which is looked up in the given map. Returns None if neither is known.
+
+	pub fn filesystem_id (
+		& self,
+		root_devices: & HashMap <PathRef, u64>,
+	) -> Option <FilesystemId> {
+
+		if let Some (ref subvolume) = self.subvolume {
+
+			return Some (
+				FilesystemId::Uuid (
+					subvolume.filesystem_uuid));
+
+		}
+
+		self.root_path.as_ref ().and_then (
+			|root_path|
+			root_devices.get (root_path)
+		).map (
+			|& device|
+			FilesystemId::Device (device)
+		)
+
+	}
+
+	/// Whether the file is in a read only subvolume, so it can only be used as
+	/// the source of a deduplication, and never changed.
+
+	pub fn is_read_only (
+		& self,
+	) -> bool {
+
+		self.subvolume.as_ref ().map (
+			|subvolume|
+			subvolume.read_only
+		).unwrap_or (false)
+
+	}
+
+	/// Identifies the inode a file refers to, which is shared by all of its
+	/// hardlinks. Returns None if the inode or its subvolume isn't known.
+
+	pub fn inode_id (
+		& self,
+	) -> Option <InodeId> {
+
+		if self.ino == 0 {
+			return None;
+		}
+
+		self.subvolume.as_ref ().map (
+			|subvolume|
+
+			(
+				subvolume.filesystem_uuid,
+				subvolume.subvolume_id,
+				self.ino,
+			)
+
+		)
+
+	}
+
+	/// Summarises everything about a file which is stored in the database, so
+	/// that changes can be detected without keeping a copy of it.
+
+	pub fn fingerprint (
+		& self,
+	) -> u64 {
+
+		let mut hasher =
+			DefaultHasher::new ();
+
+		hash::Hash::hash (
+			& (
+				self.size,
+				self.content_hash_algorithm,
+				self.partial_hash,
+				self.partial_hash_time,
+				self.content_hash,
+				self.content_hash_time,
+				self.extent_hash,
+				self.extent_hash_time,
+				self.extent_hash_version,
+			),
+			& mut hasher);
+
+		hash::Hash::hash (
+			& (
+				self.defragment_time,
+				self.deduplicate_time,
+				self.verify_time,
+				self.group_size,
+				self.group_shared,
+				& self.chunk_table,
+				self.mtime,
+				self.ctime,
+				self.mode,
+				self.uid,
+				self.gid,
+			),
+			& mut hasher);
+
+		hash::Hash::hash (
+			& (
+				& self.subvolume,
+				self.ino,
+				self.nlink,
+			),
+			& mut hasher);
+
+		hasher.finish ()
+
+	}
+
 }
 
 // ex: noet ts=4 filetype=rust
@@ -1,3 +1,4 @@
+use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::error::Error;
 use std::io::Read;
@@ -5,6 +6,7 @@ use std::io::BufRead;
 use std::io::BufReader;
 use std::io::Write;
 use std::ops::Index;
+use std::ops::IndexMut;
 use std::slice;
 use std::vec;
 
@@ -12,11 +14,22 @@ use rustc_serialize::hex::ToHex;
 use serde_json;
 
 use database::*;
+use hash_algorithm::*;
 use misc::*;
 use types::*;
 
 pub struct FileDatabase {
 	file_data_ordered: Vec <FileData>,
+	scan_generations: Vec <ScanGeneration>,
+}
+
+/// The files on one filesystem, along with the generations they were scanned
+/// at, as they are written out.
+
+pub struct DatabaseSection <'a> {
+	pub filesystem_uuid: Option <Uuid>,
+	pub file_data_list: Vec <& 'a FileData>,
+	pub scan_generations: Vec <& 'a ScanGeneration>,
 }
 
 impl FileDatabase {
@@ -28,19 +41,27 @@ impl FileDatabase {
 
 		FileDatabase {
 			file_data_ordered: file_data_ordered,
+			scan_generations: Vec::new (),
 		}
 
 	}
 
-	pub fn read (
+	/// Builds a database from files in any order, sorting them and working out
+	/// their root paths. Where a file appears more than once, the last one is
+	/// kept.
+
+	pub fn from_unordered (
 		recursive_path_database: & mut RecursivePathDatabase,
 		root_paths: & [PathRef],
-		source: & mut Read,
-	) -> Result <FileDatabase, String> {
+		mut file_data_list: Vec <FileData>,
+	) -> FileDatabase {
 
-		let source =
-			BufReader::new (
-				source);
+		// this sort is stable, so duplicates stay in the same order
+
+		file_data_list.sort_by (
+			|left, right|
+			left.path.cmp (& right.path)
+		);
 
 		let mut database_builder =
 			FileDatabaseBuilder::new ();
@@ -62,6 +83,57 @@ impl FileDatabase {
 
 			).collect ();
 
+		let mut file_data_iter =
+			file_data_list.into_iter ().peekable ();
+
+		while let Some (mut file_data) = file_data_iter.next () {
+
+			if file_data_iter.peek ().map (
+				|next_file_data|
+				next_file_data.path == file_data.path
+			).unwrap_or (false) {
+				continue;
+			}
+
+			file_data.root_path =
+				database_builder.find_root (
+					& mut root_map,
+					file_data.path.clone ());
+
+			database_builder.insert (
+				file_data);
+
+		}
+
+		database_builder.build ()
+
+	}
+
+	/// Reads the JSON format, returning the header along with the files and
+	/// scan generations exactly as they were written. Sorting them out, and
+	/// migrations, are left to the caller.
+
+	pub fn read_entries (
+		recursive_path_database: & mut RecursivePathDatabase,
+		source: & mut Read,
+	) -> Result <(Vec <FileData>, Vec <ScanGeneration>, DatabaseHeader), String> {
+
+		let source =
+			BufReader::new (
+				source);
+
+		let mut entries: Vec <FileData> =
+			Vec::new ();
+
+		let mut database_header: Option <DatabaseHeader> =
+			None;
+
+		let mut section_filesystem_uuid: Option <String> =
+			None;
+
+		let mut scan_generations: Vec <ScanGeneration> =
+			Vec::new ();
+
 		for input_line_result in source.lines () {
 
 			let input_line = try! (
@@ -74,74 +146,124 @@ impl FileDatabase {
 
 			));
 
-			let file_data_record: FileDataRecord =
+			// the first line is the header, unless it was written before there
+			// was one
+
+			if database_header.is_none () {
+
+				let database_header_record: Option <DatabaseHeaderRecord> =
+					serde_json::from_str (
+						& input_line,
+					).ok ();
+
+				let header_present =
+					database_header_record.is_some ();
+
+				database_header =
+					Some (
+						database_header_record.map (
+							|database_header_record|
+
+							DatabaseHeader {
+								schema_version:
+									database_header_record.schema_version,
+								tool_version:
+									database_header_record.tool_version,
+							}
+
+						).unwrap_or_else (
+							DatabaseHeader::legacy));
+
 				try! (
+					database_header.as_ref ().unwrap ().check ());
 
-				serde_json::from_str (
-					& input_line,
-				).map_err (
-					|serde_error|
+				if header_present {
+					continue;
+				}
 
-					format! (
-						"Deserialization error: {}",
-						serde_error)
+			}
 
-				)
+			// files follow the section for their filesystem, if any, and only
+			// give the filesystem themselves in older databases
 
-			);
+			let mut file_data_record: FileDataRecord =
+				match serde_json::from_str (
+					& input_line) {
 
-			let file_path =
-				recursive_path_database.for_path (
-					file_data_record.path,
-				).unwrap ();
+				Ok (file_data_record) =>
+					file_data_record,
 
-			let root_path =
-				database_builder.find_root (
-					& mut root_map,
-					file_path.clone ());
+				Err (serde_error) => {
+
+					let filesystem_section_record: FilesystemSectionRecord =
+						try! (
 
-			let file_data =
-				FileData {
+						serde_json::from_str (
+							& input_line,
+						).map_err (
+							|_|
 
-				path: file_path,
-				root_path: root_path,
-				size: file_data_record.size,
+							format! (
+								"Deserialization error: {}",
+								serde_error)
 
-				content_hash:
-					decode_hash (
-						& file_data_record.content_hash),
+						)
 
-				content_hash_time:
-					file_data_record.content_hash_time.unwrap_or (0),
+					);
 
-				extent_hash:
-					decode_hash (
-						& file_data_record.extent_hash),
+					let filesystem_uuid =
+						try! (
+							decode_uuid (
+								& filesystem_section_record.filesystem));
 
-				extent_hash_time:
-					file_data_record.extent_hash_time.unwrap_or (0),
+					for scan_generation_record
+					in filesystem_section_record.scans.unwrap_or_default () {
 
-				defragment_time:
-					file_data_record.defragment_time.unwrap_or (0),
+						scan_generations.push (
+							ScanGeneration {
+								filesystem_uuid: filesystem_uuid,
+								subvolume_id:
+									scan_generation_record.subvolume_id,
+								path: scan_generation_record.path,
+								generation:
+									scan_generation_record.generation,
+								filter_fingerprint:
+									scan_generation_record.filter_fingerprint,
+							});
 
-				deduplicate_time:
-					file_data_record.deduplicate_time.unwrap_or (0),
+					}
 
-				mtime: file_data_record.mtime,
-				ctime: file_data_record.ctime,
+					section_filesystem_uuid =
+						Some (
+							filesystem_section_record.filesystem);
 
-				mode: file_data_record.mode,
-				uid: file_data_record.uid,
-				gid: file_data_record.gid,
+					continue;
+
+				},
 
 			};
 
-			database_builder.insert (
-				file_data);
+			if file_data_record.filesystem_uuid.is_none () {
+
+				file_data_record.filesystem_uuid =
+					section_filesystem_uuid.clone ();
+
+			}
+
+			entries.push (
+				try! (
+					decode_file_data_record (
+						recursive_path_database,
+						file_data_record)));
 
 		}
 
-		Ok (database_builder.build ())
+		Ok ((
+			entries,
+			scan_generations,
+			database_header.unwrap_or_else (
+				DatabaseHeader::current),
+		))
 
 	}
 
@@ -150,104 +272,148 @@ impl FileDatabase {
 		database_output: & mut Write,
 	) -> Result <(), String> {
 
-		for file_data
-		in self.file_data_ordered.iter () {
+		let database_header_json = try! (
 
-			let file_data_record =
-				FileDataRecord {
+			serde_json::to_string (
+				& DatabaseHeaderRecord {
+					schema_version: DATABASE_SCHEMA_VERSION,
+					tool_version: TOOL_VERSION.to_owned (),
+				},
+			).map_err (
+				|serde_error|
 
-				path: file_data.path.to_path (),
-				size: file_data.size,
+				format! (
+					"Serialization error: {}",
+					serde_error)
 
-				content_hash: if file_data.content_hash == ZERO_HASH {
-					None
-				} else {
-					Some (file_data.content_hash.to_hex ())
-				},
+			)
 
-				content_hash_time: if file_data.content_hash_time == 0 {
-					None
-				} else {
-					Some (file_data.content_hash_time)
-				},
+		);
 
-				extent_hash: if file_data.extent_hash == ZERO_HASH {
-					None
-				} else {
-					Some (file_data.extent_hash.to_hex ())
-				},
+		try! (
 
-				extent_hash_time: if file_data.extent_hash_time == 0 {
-					None
-				} else {
-					Some (file_data.extent_hash_time)
-				},
+			database_output.write_all (
+				database_header_json.as_bytes (),
+			).and_then (
+				|()|
 
-				defragment_time: if file_data.defragment_time == 0 {
-					None
-				} else {
-					Some (file_data.defragment_time)
-				},
+				database_output.write_all (
+					b"\n")
 
-				deduplicate_time: if file_data.deduplicate_time == 0 {
-					None
-				} else {
-					Some (file_data.deduplicate_time)
-				},
+			).map_err (
+				|io_error|
 
-				mtime: file_data.mtime,
-				ctime: file_data.ctime,
+				format! (
+					"IO error: {}",
+					io_error.description ())
 
-				mode: file_data.mode,
-				uid: file_data.uid,
-				gid: file_data.gid,
+			)
 
-			};
+		);
 
-			let file_data_json = try! (
+		for section in self.sections () {
 
-				serde_json::to_string (
-					& file_data_record,
-				).map_err (
-					|serde_error|
+			if let Some (filesystem_uuid) = section.filesystem_uuid {
 
-					format! (
-						"Serialization error: {}",
-						serde_error)
+				let filesystem_section_json = try! (
 
-				)
+					serde_json::to_string (
+						& FilesystemSectionRecord {
+							filesystem: format_uuid (& filesystem_uuid),
+							scans: encode_scan_generation_records (
+								& section.scan_generations),
+						},
+					).map_err (
+						|serde_error|
 
-			);
+						format! (
+							"Serialization error: {}",
+							serde_error)
 
-			try! (
+					)
 
-				database_output.write_all (
-					file_data_json.as_bytes (),
-				).map_err (
-					|io_error|
+				);
 
-					format! (
-						"IO error: {}",
-						io_error.description ())
+				try! (
 
-				)
+					database_output.write_all (
+						filesystem_section_json.as_bytes (),
+					).and_then (
+						|()|
 
-			);
+						database_output.write_all (
+							b"\n")
 
-			try! (
+					).map_err (
+						|io_error|
 
-				database_output.write (
-					b"\n",
-				).map_err (
-					|io_error|
+						format! (
+							"IO error: {}",
+							io_error.description ())
 
-					format! (
-						"IO error: {}",
-						io_error.description ())
+					)
 
-				)
+				);
+
+			}
+
+			for file_data
+			in section.file_data_list {
+
+				let mut file_data_record =
+					encode_file_data_record (
+						file_data);
+
+				// the filesystem is given by the section
+
+				file_data_record.filesystem_uuid = None;
+
+				let file_data_json = try! (
+
+					serde_json::to_string (
+						& file_data_record,
+					).map_err (
+						|serde_error|
+
+						format! (
+							"Serialization error: {}",
+							serde_error)
 
-			);
+					)
+
+				);
+
+				try! (
+
+					database_output.write_all (
+						file_data_json.as_bytes (),
+					).map_err (
+						|io_error|
+
+						format! (
+							"IO error: {}",
+							io_error.description ())
+
+					)
+
+				);
+
+				try! (
+
+					database_output.write (
+						b"\n",
+					).map_err (
+						|io_error|
+
+						format! (
+							"IO error: {}",
+							io_error.description ())
+
+					)
+
+				);
+
+			}
 
 		}
 
@@ -255,15 +421,124 @@ impl FileDatabase {
 
 	}
 
+	pub fn len (
+		& self,
+	) -> usize {
+		self.file_data_ordered.len ()
+	}
+
 	pub fn iter (
 		& self,
-	) -> slice::Iter <FileData> {
+	) -> slice::Iter <'_, FileData> {
 		self.file_data_ordered.iter ()
 	}
 
+	/// Splits the files up by filesystem, as they are written out. Files which
+	/// aren't known to be on any filesystem come first, followed by each
+	/// filesystem in order of UUID, and files stay in order within each.
+
+	pub fn sections <'a> (
+		& 'a self,
+	) -> Vec <DatabaseSection <'a>> {
+
+		let mut sections: BTreeMap <Option <Uuid>, DatabaseSection> =
+			BTreeMap::new ();
+
+		for file_data in self.file_data_ordered.iter () {
+
+			let filesystem_uuid =
+				file_data.subvolume.as_ref ().map (
+					|subvolume|
+					subvolume.filesystem_uuid);
+
+			sections.entry (
+				filesystem_uuid,
+			).or_insert_with (
+				|| DatabaseSection {
+					filesystem_uuid: filesystem_uuid,
+					file_data_list: Vec::new (),
+					scan_generations: Vec::new (),
+				}
+			).file_data_list.push (
+				file_data);
+
+		}
+
+		for scan_generation in self.scan_generations.iter () {
+
+			let filesystem_uuid =
+				Some (scan_generation.filesystem_uuid);
+
+			sections.entry (
+				filesystem_uuid,
+			).or_insert_with (
+				|| DatabaseSection {
+					filesystem_uuid: filesystem_uuid,
+					file_data_list: Vec::new (),
+					scan_generations: Vec::new (),
+				}
+			).scan_generations.push (
+				scan_generation);
+
+		}
+
+		sections.into_values ().collect ()
+
+	}
+
+	pub fn scan_generations (
+		& self,
+	) -> & [ScanGeneration] {
+		& self.scan_generations
+	}
+
+	pub fn set_scan_generations (
+		& mut self,
+		scan_generations: Vec <ScanGeneration>,
+	) {
+		self.scan_generations = scan_generations;
+	}
+
+	/// Records that a path was scanned, replacing the records for it and
+	/// anything beneath it. Records above it which were made with a different
+	/// filter are removed too, since part of what they cover no longer matches
+	/// their filter.
+
+	pub fn record_scan_generation (
+		& mut self,
+		scan_generation: ScanGeneration,
+	) {
+
+		self.scan_generations.retain (
+			|existing_scan_generation|
+
+			! (
+				existing_scan_generation.filesystem_uuid
+					== scan_generation.filesystem_uuid
+				&& existing_scan_generation.subvolume_id
+					== scan_generation.subvolume_id
+				&& (
+					existing_scan_generation.path.starts_with (
+						& scan_generation.path)
+					|| (
+						scan_generation.path.starts_with (
+							& existing_scan_generation.path)
+						&& existing_scan_generation.filter_fingerprint
+							!= scan_generation.filter_fingerprint
+					)
+				)
+			)
+
+		);
+
+		self.scan_generations.push (
+			scan_generation);
+
+	}
+
 	pub fn iter_mut (
 		& mut self,
-	) -> slice::IterMut <FileData> {
+	) -> slice::IterMut <'_, FileData> {
 		self.file_data_ordered.iter_mut ()
 	}
 
@@ -275,6 +550,416 @@ impl FileDatabase {
 
 }
 
+/// Converts a record from the JSON format, as used in the database and its
+/// journal. The root path is left for the caller to fill in.
+
+pub fn decode_file_data_record (
+	recursive_path_database: & mut RecursivePathDatabase,
+	file_data_record: FileDataRecord,
+) -> Result <FileData, String> {
+
+	let file_path =
+		try! (
+			recursive_path_database.for_path (
+				& file_data_record.path,
+			).ok_or_else (
+				||
+
+				format! (
+					"Invalid path: {}",
+					file_data_record.path.to_string_lossy ())
+
+			));
+
+	let content_hash_algorithm =
+		match file_data_record.content_hash_algorithm {
+
+		Some (ref name) =>
+			try! (
+				HashAlgorithm::from_name (
+					name,
+				).ok_or_else (
+					||
+
+					format! (
+						"Unknown hash algorithm: {}",
+						name)
+
+				)),
+
+		None =>
+			HashAlgorithm::Sha256,
+
+	};
+
+	let subvolume =
+		match (
+			file_data_record.filesystem_uuid,
+			file_data_record.subvolume_id,
+			file_data_record.subvolume_path,
+		) {
+
+		(
+			Some (filesystem_uuid),
+			Some (subvolume_id),
+			Some (subvolume_path),
+		) =>
+			Subvolume::from_relative_path (
+				try! (
+					decode_uuid (
+						& filesystem_uuid)),
+				subvolume_id,
+				file_data_record.subvolume_read_only.unwrap_or (false),
+				& file_path,
+				& subvolume_path),
+
+		_ =>
+			None,
+
+	};
+
+	Ok (FileData {
+
+		path: file_path,
+		root_path: None,
+		subvolume: subvolume,
+		size: file_data_record.size,
+
+		content_hash_algorithm: content_hash_algorithm,
+
+		partial_hash:
+			try! (
+				decode_hash (
+					& file_data_record.partial_hash)),
+
+		partial_hash_time:
+			file_data_record.partial_hash_time.unwrap_or (0),
+
+		partial_hash_needed: false,
+
+		content_hash:
+			try! (
+				decode_hash (
+					& file_data_record.content_hash)),
+
+		content_hash_time:
+			file_data_record.content_hash_time.unwrap_or (0),
+
+		content_hash_needed: false,
+
+		extent_hash:
+			try! (
+				decode_hash (
+					& file_data_record.extent_hash)),
+
+		extent_hash_time:
+			file_data_record.extent_hash_time.unwrap_or (0),
+
+		extent_hash_version:
+			file_data_record.extent_hash_version.unwrap_or (0),
+
+		defragment_time:
+			file_data_record.defragment_time.unwrap_or (0),
+
+		deduplicate_time:
+			file_data_record.deduplicate_time.unwrap_or (0),
+
+		verify_time:
+			file_data_record.verify_time.unwrap_or (0),
+
+		group_size:
+			file_data_record.group_size.unwrap_or (0),
+
+		group_shared:
+			file_data_record.group_shared.unwrap_or (0),
+
+		chunk_table:
+			match file_data_record.chunk_table {
+
+			Some (chunk_table_record) =>
+				Some (try! (
+					decode_chunk_table (
+						chunk_table_record))),
+
+			None =>
+				None,
+
+		},
+
+		mtime: file_data_record.mtime,
+		ctime: file_data_record.ctime,
+
+		mode: file_data_record.mode,
+		uid: file_data_record.uid,
+		gid: file_data_record.gid,
+
+		ino: file_data_record.ino.unwrap_or (0),
+		nlink: file_data_record.nlink.unwrap_or (0),
+
+	})
+
+}
+
+fn encode_scan_generation_records (
+	scan_generations: & [& ScanGeneration],
+) -> Option <Vec <ScanGenerationRecord>> {
+
+	if scan_generations.is_empty () {
+		return None;
+	}
+
+	Some (
+		scan_generations.iter ().map (
+			|scan_generation|
+
+			ScanGenerationRecord {
+				subvolume_id: scan_generation.subvolume_id,
+				path: scan_generation.path.clone (),
+				generation: scan_generation.generation,
+				filter_fingerprint: scan_generation.filter_fingerprint,
+			}
+
+		).collect ())
+
+}
+
+pub fn encode_file_data_record (
+	file_data: & FileData,
+) -> FileDataRecord {
+
+	FileDataRecord {
+
+		path: file_data.path.to_path (),
+
+		filesystem_uuid: file_data.subvolume.as_ref ().map (
+			|subvolume|
+			format_uuid (& subvolume.filesystem_uuid)
+		),
+
+		subvolume_id: file_data.subvolume.as_ref ().map (
+			|subvolume|
+			subvolume.subvolume_id
+		),
+
+		subvolume_path: file_data.subvolume.as_ref ().and_then (
+			|subvolume|
+			subvolume.relative_path (& file_data.path)
+		),
+
+		subvolume_read_only: file_data.subvolume.as_ref ().and_then (
+			|subvolume|
+
+			if subvolume.read_only {
+				Some (true)
+			} else {
+				None
+			}
+
+		),
+
+		size: file_data.size,
+
+		content_hash_algorithm:
+			if file_data.content_hash_algorithm
+				== HashAlgorithm::Sha256 {
+			None
+		} else {
+			Some (file_data.content_hash_algorithm.name ().to_owned ())
+		},
+
+		partial_hash: if file_data.partial_hash == ZERO_HASH {
+			None
+		} else {
+			Some (file_data.partial_hash.to_hex ())
+		},
+
+		partial_hash_time: if file_data.partial_hash_time == 0 {
+			None
+		} else {
+			Some (file_data.partial_hash_time)
+		},
+
+		content_hash: if file_data.content_hash == ZERO_HASH {
+			None
+		} else {
+			Some (file_data.content_hash.to_hex ())
+		},
+
+		content_hash_time: if file_data.content_hash_time == 0 {
+			None
+		} else {
+			Some (file_data.content_hash_time)
+		},
+
+		extent_hash: if file_data.extent_hash == ZERO_HASH {
+			None
+		} else {
+			Some (file_data.extent_hash.to_hex ())
+		},
+
+		extent_hash_time: if file_data.extent_hash_time == 0 {
+			None
+		} else {
+			Some (file_data.extent_hash_time)
+		},
+
+		extent_hash_version: if file_data.extent_hash_version == 0 {
+			None
+		} else {
+			Some (file_data.extent_hash_version)
+		},
+
+		defragment_time: if file_data.defragment_time == 0 {
+			None
+		} else {
+			Some (file_data.defragment_time)
+		},
+
+		deduplicate_time: if file_data.deduplicate_time == 0 {
+			None
+		} else {
+			Some (file_data.deduplicate_time)
+		},
+
+		verify_time: if file_data.verify_time == 0 {
+			None
+		} else {
+			Some (file_data.verify_time)
+		},
+
+		group_size: if file_data.group_size == 0 {
+			None
+		} else {
+			Some (file_data.group_size)
+		},
+
+		group_shared: if file_data.group_shared == 0 {
+			None
+		} else {
+			Some (file_data.group_shared)
+		},
+
+		chunk_table:
+			file_data.chunk_table.as_ref ().map (
+				encode_chunk_table),
+
+		mtime: file_data.mtime,
+		ctime: file_data.ctime,
+
+		mode: file_data.mode,
+		uid: file_data.uid,
+		gid: file_data.gid,
+
+		ino: if file_data.ino != 0 {
+			Some (file_data.ino)
+		} else {
+			None
+		},
+
+		nlink: if file_data.ino != 0 {
+			Some (file_data.nlink)
+		} else {
+			None
+		},
+
+	}
+
+}
+
+fn decode_chunk_table (
+	chunk_table_record: ChunkTableRecord,
+) -> Result <ChunkTable, String> {
+
+	let chunking_method =
+		match chunk_table_record.chunking_method {
+
+		Some (ref name) =>
+			try! (
+				ChunkingMethod::from_name (
+					name,
+				).ok_or_else (
+					||
+
+					format! (
+						"Unknown chunking method: {}",
+						name)
+
+				)),
+
+		None =>
+			ChunkingMethod::Fixed,
+
+	};
+
+	let mut offset: u64 = 0;
+
+	let chunks: Vec <Chunk> =
+		try! (
+			chunk_table_record.chunks.into_iter ().map (
+				|(length, hash)| {
+
+				let chunk =
+					Chunk {
+						offset: offset,
+						length: length,
+						hash: try! (decode_hash (& Some (hash))),
+					};
+
+				offset += length;
+
+				Ok (chunk)
+
+			}).collect::<Result <Vec <Chunk>, String>> ());
+
+	Ok (ChunkTable {
+
+		chunking_method: chunking_method,
+		chunk_size: chunk_table_record.chunk_size,
+
+		deduplicate_time:
+			chunk_table_record.deduplicate_time.unwrap_or (0),
+
+		chunks: chunks,
+
+	})
+
+}
+
+fn encode_chunk_table (
+	chunk_table: & ChunkTable,
+) -> ChunkTableRecord {
+
+	ChunkTableRecord {
+
+		chunking_method:
+			if chunk_table.chunking_method == ChunkingMethod::Fixed {
+				None
+			} else {
+				Some (chunk_table.chunking_method.name ().to_owned ())
+			},
+
+		chunk_size: chunk_table.chunk_size,
+
+		deduplicate_time: if chunk_table.deduplicate_time == 0 {
+			None
+		} else {
+			Some (chunk_table.deduplicate_time)
+		},
+
+		chunks: chunk_table.chunks.iter ().map (
+			|chunk|
+
+			(
+				chunk.length,
+				chunk.hash.to_hex (),
+			)
+
+		).collect (),
+
+	}
+
+}
+
 impl Index <usize> for FileDatabase {
 
 	type Output = FileData;
@@ -290,4 +975,17 @@ impl Index <usize> for FileDatabase {
 
 }
 
+impl IndexMut <usize> for FileDatabase {
+
+	fn index_mut (
+		& mut self,
+		index: usize,
+	) -> & mut FileData {
+
+		& mut self.file_data_ordered [index]
+
+	}
+
+}
+
 // ex: noet ts=4 filetype=rust
@@ -0,0 +1,472 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::path::Path;
+use std::path::PathBuf;
+
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+
+use database::*;
+use misc::*;
+use types::*;
+
+/// Keeps the database in a single file, in either the JSON or the binary
+/// format, with a journal of changes alongside it. Each write only appends the
+/// files which have changed to the journal, and the database is rewritten in
+/// full when it is compacted, or when the journal grows past a size limit.
+/// The journal only holds files, so the database is also rewritten whenever
+/// the scan generations change.
+
+pub struct FlatFileStorage {
+	database_path: PathBuf,
+	database_format: FlatFileFormat,
+	journal_size_limit: u64,
+	fingerprints: Option <HashMap <RecursivePathRef, u64>>,
+	scan_generations: Vec <ScanGeneration>,
+	journal_size: u64,
+}
+
+/// The database formats which are kept in a single file.
+
+#[ derive (Clone, Copy, Debug, Eq, PartialEq) ]
+pub enum FlatFileFormat {
+	Json,
+	Binary,
+}
+
+impl FlatFileFormat {
+
+	/// Returns None for SQLite, which keeps its database in its own way.
+
+	pub fn from_database_format (
+		database_format: DatabaseFormat,
+	) -> Option <FlatFileFormat> {
+
+		match database_format {
+			DatabaseFormat::Json => Some (FlatFileFormat::Json),
+			DatabaseFormat::Binary => Some (FlatFileFormat::Binary),
+			DatabaseFormat::Sqlite => None,
+		}
+
+	}
+
+	pub fn database_format (
+		& self,
+	) -> DatabaseFormat {
+
+		match * self {
+			FlatFileFormat::Json => DatabaseFormat::Json,
+			FlatFileFormat::Binary => DatabaseFormat::Binary,
+		}
+
+	}
+
+}
+
+impl FlatFileStorage {
+
+	pub fn new (
+		database_path: & Path,
+		database_format: FlatFileFormat,
+		journal_size_limit: u64,
+	) -> FlatFileStorage {
+
+		FlatFileStorage {
+			database_path: database_path.to_owned (),
+			database_format: database_format,
+			journal_size_limit: journal_size_limit,
+			fingerprints: None,
+			scan_generations: Vec::new (),
+			journal_size: 0,
+		}
+
+	}
+
+	/// Reads the files and scan generations from the database itself, exactly
+	/// as they were written.
+
+	fn read_snapshot (
+		& self,
+		recursive_path_database: & mut RecursivePathDatabase,
+	) -> Result <(Vec <FileData>, Vec <ScanGeneration>, DatabaseHeader), String> {
+
+		let database_file =
+			io_result (
+				File::open (
+					& self.database_path),
+			) ?;
+
+		let mut database_reader =
+			BufReader::new (
+				database_file);
+
+		match self.database_format {
+
+			FlatFileFormat::Binary =>
+				FileDatabase::read_binary_entries (
+					recursive_path_database,
+					& mut database_reader,
+				),
+
+			FlatFileFormat::Json => {
+
+				let mut database_reader =
+					io_result (
+						GzDecoder::new (
+							database_reader),
+					) ?;
+
+				FileDatabase::read_entries (
+					recursive_path_database,
+					& mut database_reader,
+				)
+
+			},
+
+		}
+
+	}
+
+	/// Reads the changes in the journal, if there is one.
+
+	fn read_journal (
+		& mut self,
+		recursive_path_database: & mut RecursivePathDatabase,
+	) -> Result <(Vec <JournalEntry>, bool), String> {
+
+		match File::open (
+			journal_path (
+				& self.database_path)) {
+
+			Ok (mut journal_file) => {
+
+				self.journal_size =
+					io_result (
+						journal_file.metadata (),
+					) ?.len ();
+
+				read_journal (
+					recursive_path_database,
+					& mut journal_file)
+
+			},
+
+			Err (ref io_error)
+			if io_error.kind () == io::ErrorKind::NotFound =>
+				Ok ((Vec::new (), true)),
+
+			Err (io_error) =>
+				Err (
+					io_error.description ().to_string ()),
+
+		}
+
+	}
+
+	/// Writes the database in full to a temporary file, which still needs to
+	/// be renamed into place.
+
+	fn write_snapshot (
+		& self,
+		file_database: & FileDatabase,
+	) -> Result <PathBuf, String> {
+
+		let database_path_temp =
+			temporary_database_path (
+				& self.database_path);
+
+		let database_file =
+			io_result (
+				File::create (
+					& database_path_temp),
+			) ?;
+
+		let database_file =
+			match self.database_format {
+
+			FlatFileFormat::Binary => {
+
+				let mut database_writer =
+					BufWriter::new (
+						database_file);
+
+				file_database.write_binary (
+					& mut database_writer,
+				) ?;
+
+				database_writer.into_inner (
+				).map_err (
+					|into_inner_error|
+					into_inner_error.error ().description ().to_string ()
+				) ?
+
+			},
+
+			FlatFileFormat::Json => {
+
+				let mut database_writer =
+					GzEncoder::new (
+						database_file,
+						Compression::Fast);
+
+				file_database.write (
+					& mut database_writer,
+				) ?;
+
+				io_result (
+					database_writer.finish (),
+				) ?
+
+			},
+
+		};
+
+		io_result (
+			database_file.sync_data (),
+		) ?;
+
+		Ok (database_path_temp)
+
+	}
+
+	fn append_journal (
+		& mut self,
+		file_database: & FileDatabase,
+	) -> Result <(), String> {
+
+		let journal_file =
+			io_result (
+				OpenOptions::new ()
+					.create (true)
+					.append (true)
+					.open (
+						journal_path (
+							& self.database_path)),
+			) ?;
+
+		let mut journal_writer =
+			BufWriter::new (
+				journal_file);
+
+		write_journal (
+			self.fingerprints.as_mut ().unwrap (),
+			file_database,
+			& mut journal_writer,
+		) ?;
+
+		let journal_file =
+			journal_writer.into_inner (
+			).map_err (
+				|into_inner_error|
+				into_inner_error.error ().description ().to_string ()
+			) ?;
+
+		io_result (
+			journal_file.sync_data (),
+		) ?;
+
+		self.journal_size =
+			io_result (
+				journal_file.metadata (),
+			) ?.len ();
+
+		Ok (())
+
+	}
+
+}
+
+impl DatabaseStorage for FlatFileStorage {
+
+	fn path (
+		& self,
+	) -> & Path {
+		& self.database_path
+	}
+
+	fn read (
+		& mut self,
+		recursive_path_database: & mut RecursivePathDatabase,
+		root_paths: & [PathRef],
+	) -> Result <FileDatabase, String> {
+
+		let stored_database =
+			self.read_stored (
+				recursive_path_database,
+			) ?;
+
+		// only append to a journal which belongs to an up to date database in
+		// the format we are writing, and which wasn't left incomplete
+
+		let keep_journal = (
+			stored_database.database_format
+				== self.database_format.database_format ()
+			&& ! stored_database.database_header.needs_migration ()
+			&& stored_database.journal_complete
+		);
+
+		let file_database =
+			stored_database.into_file_database (
+				recursive_path_database,
+				root_paths);
+
+		if keep_journal {
+
+			self.fingerprints =
+				Some (
+					database_fingerprints (
+						& file_database));
+
+			self.scan_generations =
+				file_database.scan_generations ().to_vec ();
+
+		}
+
+		Ok (file_database)
+
+	}
+
+	fn read_stored (
+		& mut self,
+		recursive_path_database: & mut RecursivePathDatabase,
+	) -> Result <StoredDatabase, String> {
+
+		self.fingerprints = None;
+		self.scan_generations = Vec::new ();
+		self.journal_size = 0;
+
+		let database_format =
+			DatabaseFormat::detect_file (
+				& self.database_path,
+			) ?;
+
+		let flat_file_format =
+			match FlatFileFormat::from_database_format (
+				database_format) {
+
+			Some (flat_file_format) =>
+				flat_file_format,
+
+			None =>
+				return SqliteStorage::new (
+					& self.database_path,
+				).read_stored (
+					recursive_path_database,
+				),
+
+		};
+
+		let (file_data_list, scan_generations, database_header) =
+			FlatFileStorage::new (
+				& self.database_path,
+				flat_file_format,
+				0,
+			).read_snapshot (
+				recursive_path_database,
+			) ?;
+
+		let (journal_entries, journal_complete) =
+			self.read_journal (
+				recursive_path_database,
+			) ?;
+
+		Ok (StoredDatabase {
+			database_format: database_format,
+			database_header: database_header,
+			file_data_list: file_data_list,
+			file_data_ordered: true,
+			scan_generations: scan_generations,
+			journal_entries: journal_entries,
+			journal_complete: journal_complete,
+		})
+
+	}
+
+	fn write (
+		& mut self,
+		file_database: & FileDatabase,
+	) -> Result <(), String> {
+
+		if (
+			self.fingerprints.is_none ()
+			|| self.journal_size >= self.journal_size_limit
+			|| file_database.scan_generations () != & self.scan_generations [..]
+		) {
+
+			self.compact (
+				file_database)
+
+		} else {
+
+			self.append_journal (
+				file_database)
+
+		}
+
+	}
+
+	fn compact (
+		& mut self,
+		file_database: & FileDatabase,
+	) -> Result <(), String> {
+
+		let database_path_temp =
+			self.write_snapshot (
+				file_database,
+			) ?;
+
+		// a journal which is replayed over the new database mustn't be older
+		// than it, so bring it up to date first, or if it can't be appended
+		// to, remove it, which leaves the old database as it was
+
+		if self.fingerprints.is_some () {
+
+			self.append_journal (
+				file_database,
+			) ?;
+
+		} else {
+
+			remove_journal (
+				& self.database_path,
+			) ?;
+
+		}
+
+		io_result (
+			fs::rename (
+				& database_path_temp,
+				& self.database_path),
+		) ?;
+
+		// the new database includes everything in the journal, so if this is
+		// interrupted then replaying it again does no harm
+
+		remove_journal (
+			& self.database_path,
+		) ?;
+
+		self.fingerprints =
+			Some (
+				database_fingerprints (
+					file_database));
+
+		self.scan_generations =
+			file_database.scan_generations ().to_vec ();
+
+		self.journal_size = 0;
+
+		Ok (())
+
+	}
+
+}
+
+// ex: noet ts=4 filetype=rust
@@ -1,13 +1,33 @@
+mod binary_database;
+mod chunk_table;
+mod database_format;
+mod database_journal;
+mod database_schema;
+mod database_storage;
 mod file_data;
 mod file_database;
 mod file_database_builder;
+mod flat_file_storage;
 mod recursive_path;
+mod scan_generation;
 mod serde_types;
+mod sqlite_storage;
+mod subvolume;
 
+pub use self::binary_database::*;
+pub use self::chunk_table::*;
+pub use self::database_format::*;
+pub use self::database_journal::*;
+pub use self::database_schema::*;
+pub use self::database_storage::*;
 pub use self::file_data::*;
 pub use self::file_database::*;
 pub use self::file_database_builder::*;
+pub use self::flat_file_storage::*;
 pub use self::recursive_path::*;
+pub use self::scan_generation::*;
 pub use self::serde_types::*;
+pub use self::sqlite_storage::*;
+pub use self::subvolume::*;
 
 // ex: noet ts=4 filetype=rust
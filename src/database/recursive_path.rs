@@ -30,17 +30,19 @@ impl RecursivePath {
 		self.parent.clone ()
 	}
 
-	#[ allow (dead_code) ]
+	/// The number of components below the root directory.
+
+	pub fn depth (
+		& self,
+	) -> u16 {
+		self.depth
+	}
+
 	pub fn name (
 		& self,
 	) -> Option <& OsStr> {
 
-		self.name.as_ref ().map (
-			|name|
-
-			name.as_os_str ()
-
-		)
+		self.name.as_deref ()
 
 	}
 
@@ -0,0 +1,74 @@
+use std::path::Path;
+use std::path::PathBuf;
+
+use types::*;
+
+/// Records that everything beneath a path in a btrfs subvolume was scanned as
+/// of a generation of the subvolume. The path is relative to the top of the
+/// subvolume, and is empty for the top itself, so it stays the same wherever
+/// the subvolume is mounted. Only the inodes which have changed since then
+/// need to be looked at on the next scan.
+///
+/// Directories where nothing has changed are carried over without being
+/// filtered again, so the scan also records a fingerprint of the filter which
+/// decided what was left out, and is only used by scans with the same filter.
+
+#[ derive (Clone, Debug, Eq, PartialEq) ]
+pub struct ScanGeneration {
+	pub filesystem_uuid: Uuid,
+	pub subvolume_id: u64,
+	pub path: PathBuf,
+	pub generation: u64,
+	pub filter_fingerprint: u64,
+}
+
+impl ScanGeneration {
+
+	/// Whether this covers a path in a subvolume, because it is the same path
+	/// or one of its ancestors.
+
+	pub fn covers (
+		& self,
+		filesystem_uuid: Uuid,
+		subvolume_id: u64,
+		path: & Path,
+	) -> bool {
+
+		self.filesystem_uuid == filesystem_uuid
+		&& self.subvolume_id == subvolume_id
+		&& path.starts_with (
+			& self.path)
+
+	}
+
+}
+
+/// Finds the generation a path in a subvolume was last scanned at, if it, or
+/// one of its ancestors, has been scanned with the same filter.
+
+pub fn find_scan_generation (
+	scan_generations: & [ScanGeneration],
+	filesystem_uuid: Uuid,
+	subvolume_id: u64,
+	path: & Path,
+	filter_fingerprint: u64,
+) -> Option <u64> {
+
+	scan_generations.iter ().filter (
+		|scan_generation|
+
+		scan_generation.covers (
+			filesystem_uuid,
+			subvolume_id,
+			path)
+
+		&& scan_generation.filter_fingerprint == filter_fingerprint
+
+	).map (
+		|scan_generation|
+		scan_generation.generation
+	).max ()
+
+}
+
+// ex: noet ts=4 filetype=rust
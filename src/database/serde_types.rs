@@ -1,6 +1,142 @@
-include! (
-	concat! (
-		env! ("OUT_DIR"),
-		"/serde_types.rs"));
+use std::path::PathBuf;
+
+/// The first line of the JSON format, see `DatabaseHeader`.
+
+#[ derive (Debug, Deserialize, Serialize) ]
+pub struct DatabaseHeaderRecord {
+	pub schema_version: u32,
+	pub tool_version: String,
+}
+
+/// Starts the section of the JSON format for the files on one filesystem. From
+/// schema version five, files in a section leave out the filesystem UUID. From
+/// schema version seven, the section also lists the generations its subvolumes
+/// were scanned at.
+
+#[ derive (Debug, Deserialize, Serialize) ]
+pub struct FilesystemSectionRecord {
+
+	pub filesystem: String,
+
+    #[ serde (skip_serializing_if = "Option::is_none") ]
+	pub scans: Option <Vec <ScanGenerationRecord>>,
+
+}
+
+#[ derive (Debug, Deserialize, Serialize) ]
+pub struct ScanGenerationRecord {
+	pub subvolume_id: u64,
+	pub path: PathBuf,
+	pub generation: u64,
+	pub filter_fingerprint: u64,
+}
+
+#[ derive (Debug, Deserialize, Serialize) ]
+pub struct FileDataRecord {
+
+	pub path: PathBuf,
+
+    #[ serde (skip_serializing_if = "Option::is_none") ]
+	pub filesystem_uuid: Option <String>,
+
+    #[ serde (skip_serializing_if = "Option::is_none") ]
+	pub subvolume_id: Option <u64>,
+
+    #[ serde (skip_serializing_if = "Option::is_none") ]
+	pub subvolume_path: Option <PathBuf>,
+
+    #[ serde (skip_serializing_if = "Option::is_none") ]
+	pub subvolume_read_only: Option <bool>,
+
+    pub size: u64,
+
+    #[ serde (skip_serializing_if = "Option::is_none") ]
+    pub content_hash_algorithm: Option <String>,
+
+    #[ serde (skip_serializing_if = "Option::is_none") ]
+    pub partial_hash: Option <String>,
+
+    #[ serde (skip_serializing_if = "Option::is_none") ]
+	pub partial_hash_time: Option <i64>,
+
+    #[ serde (skip_serializing_if = "Option::is_none") ]
+    pub content_hash: Option <String>,
+
+    #[ serde (skip_serializing_if = "Option::is_none") ]
+	pub content_hash_time: Option <i64>,
+
+    #[ serde (skip_serializing_if = "Option::is_none") ]
+	pub extent_hash: Option <String>,
+
+    #[ serde (skip_serializing_if = "Option::is_none") ]
+	pub extent_hash_time: Option <i64>,
+
+    #[ serde (skip_serializing_if = "Option::is_none") ]
+	pub extent_hash_version: Option <u32>,
+
+    #[ serde (skip_serializing_if = "Option::is_none") ]
+	pub defragment_time: Option <i64>,
+
+    #[ serde (skip_serializing_if = "Option::is_none") ]
+	pub deduplicate_time: Option <i64>,
+
+    #[ serde (skip_serializing_if = "Option::is_none") ]
+	pub verify_time: Option <i64>,
+
+    #[ serde (skip_serializing_if = "Option::is_none") ]
+	pub group_size: Option <u64>,
+
+    #[ serde (skip_serializing_if = "Option::is_none") ]
+	pub group_shared: Option <u64>,
+
+    #[ serde (skip_serializing_if = "Option::is_none") ]
+	pub chunk_table: Option <ChunkTableRecord>,
+
+	pub mtime: i64,
+	pub ctime: i64,
+
+	pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+
+    #[ serde (skip_serializing_if = "Option::is_none") ]
+	pub ino: Option <u64>,
+
+    #[ serde (skip_serializing_if = "Option::is_none") ]
+	pub nlink: Option <u64>,
+
+}
+
+/// Chunks are stored as pairs of length and hash, the offset of each follows
+/// from the lengths of those before it.
+
+#[ derive (Debug, Deserialize, Serialize) ]
+pub struct ChunkTableRecord {
+
+    #[ serde (skip_serializing_if = "Option::is_none") ]
+	pub chunking_method: Option <String>,
+
+	pub chunk_size: u64,
+
+    #[ serde (skip_serializing_if = "Option::is_none") ]
+	pub deduplicate_time: Option <i64>,
+
+	pub chunks: Vec <(u64, String)>,
+
+}
+
+/// A line in the journal, either the new state of a file, or the path of one
+/// which is no longer present.
+
+#[ derive (Debug, Deserialize, Serialize) ]
+pub struct JournalRecord {
+
+    #[ serde (skip_serializing_if = "Option::is_none") ]
+	pub file: Option <FileDataRecord>,
+
+    #[ serde (skip_serializing_if = "Option::is_none") ]
+	pub removed: Option <PathBuf>,
+
+}
 
 // ex: noet ts=4 filetype=rust
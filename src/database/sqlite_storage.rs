@@ -0,0 +1,1173 @@
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::path::PathBuf;
+
+use rusqlite;
+use rusqlite::Connection;
+use rusqlite::Transaction;
+use rusqlite::types::ToSql;
+use rusqlite::types::Value;
+use rusqlite::types::ValueRef;
+
+use database::*;
+use hash_algorithm::*;
+use misc::*;
+use types::*;
+
+const CREATE_SCHEMA: & 'static str = "
+	CREATE TABLE IF NOT EXISTS files (
+		path TEXT NOT NULL PRIMARY KEY,
+		size INTEGER NOT NULL,
+		content_hash_algorithm TEXT NOT NULL,
+		partial_hash BLOB,
+		partial_hash_time INTEGER NOT NULL,
+		content_hash BLOB,
+		content_hash_time INTEGER NOT NULL,
+		extent_hash BLOB,
+		extent_hash_time INTEGER NOT NULL,
+		extent_hash_version INTEGER NOT NULL,
+		defragment_time INTEGER NOT NULL,
+		deduplicate_time INTEGER NOT NULL,
+		verify_time INTEGER NOT NULL,
+		group_size INTEGER NOT NULL,
+		group_shared INTEGER NOT NULL,
+		chunk_table BLOB,
+		mtime INTEGER NOT NULL,
+		ctime INTEGER NOT NULL,
+		mode INTEGER NOT NULL,
+		uid INTEGER NOT NULL,
+		gid INTEGER NOT NULL,
+		filesystem_uuid TEXT,
+		subvolume_id INTEGER,
+		subvolume_path TEXT,
+		ino INTEGER NOT NULL DEFAULT 0,
+		nlink INTEGER NOT NULL DEFAULT 0,
+		subvolume_read_only INTEGER NOT NULL DEFAULT 0
+	);
+	CREATE INDEX IF NOT EXISTS files_subvolume
+		ON files (filesystem_uuid, subvolume_id);
+	CREATE TABLE IF NOT EXISTS scan_generations (
+		filesystem_uuid TEXT NOT NULL,
+		subvolume_id INTEGER NOT NULL,
+		path TEXT NOT NULL,
+		generation INTEGER NOT NULL,
+		filter_fingerprint INTEGER NOT NULL,
+		PRIMARY KEY (filesystem_uuid, subvolume_id, path)
+	);
+	CREATE TABLE IF NOT EXISTS metadata (
+		name TEXT NOT NULL PRIMARY KEY,
+		value TEXT NOT NULL
+	);
+";
+
+const SELECT_FILES: & 'static str = "
+	SELECT
+		path, size, content_hash_algorithm,
+		partial_hash, partial_hash_time,
+		content_hash, content_hash_time,
+		extent_hash, extent_hash_time, extent_hash_version,
+		defragment_time, deduplicate_time, verify_time,
+		group_size, group_shared,
+		chunk_table,
+		mtime, ctime, mode, uid, gid,
+		filesystem_uuid, subvolume_id, subvolume_path,
+		ino, nlink, subvolume_read_only
+	FROM files
+";
+
+const REPLACE_FILE: & 'static str = "
+	INSERT OR REPLACE INTO files (
+		path, size, content_hash_algorithm,
+		partial_hash, partial_hash_time,
+		content_hash, content_hash_time,
+		extent_hash, extent_hash_time, extent_hash_version,
+		defragment_time, deduplicate_time, verify_time,
+		group_size, group_shared,
+		chunk_table,
+		mtime, ctime, mode, uid, gid,
+		filesystem_uuid, subvolume_id, subvolume_path,
+		ino, nlink, subvolume_read_only
+	) VALUES (
+		?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12,
+		?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23, ?24,
+		?25, ?26, ?27
+	)
+";
+
+const DELETE_FILE: & 'static str = "
+	DELETE FROM files WHERE path = ?1
+";
+
+const SELECT_SCAN_GENERATIONS: & 'static str = "
+	SELECT filesystem_uuid, subvolume_id, path, generation, filter_fingerprint
+	FROM scan_generations
+";
+
+const DELETE_SCAN_GENERATIONS: & 'static str = "
+	DELETE FROM scan_generations
+";
+
+const INSERT_SCAN_GENERATION: & 'static str = "
+	INSERT INTO scan_generations (
+		filesystem_uuid, subvolume_id, path, generation, filter_fingerprint
+	) VALUES (
+		?1, ?2, ?3, ?4, ?5
+	)
+";
+
+const SELECT_TOOL_VERSION: & 'static str = "
+	SELECT value FROM metadata WHERE name = 'tool_version'
+";
+
+const REPLACE_TOOL_VERSION: & 'static str = "
+	INSERT OR REPLACE INTO metadata (name, value) VALUES ('tool_version', ?1)
+";
+
+/// Keeps the database in SQLite, with one row per file. A fingerprint of each
+/// row is remembered when it is read or written, so that later writes only
+/// touch the rows which have changed. Paths are stored as text where they are
+/// valid UTF-8, so that the database is easy to query by hand, and as blobs
+/// otherwise. There are only a few scan generations, and these are all written
+/// again whenever any of them change.
+///
+/// The schema version is kept in SQLite's `user_version`, and the version of
+/// the tool which last wrote to the database in the `metadata` table.
+
+pub struct SqliteStorage {
+	database_path: PathBuf,
+	connection: Option <Connection>,
+	fingerprints: HashMap <RecursivePathRef, u64>,
+	scan_generations: Vec <ScanGeneration>,
+}
+
+impl SqliteStorage {
+
+	pub fn new (
+		database_path: & Path,
+	) -> SqliteStorage {
+
+		SqliteStorage {
+			database_path: database_path.to_owned (),
+			connection: None,
+			fingerprints: HashMap::new (),
+			scan_generations: Vec::new (),
+		}
+
+	}
+
+	/// Creates a new database from scratch. This is written to a temporary
+	/// file, which then replaces whatever was there before, which might be a
+	/// database in another format.
+
+	fn create (
+		& mut self,
+		file_database: & FileDatabase,
+	) -> Result <(), String> {
+
+		let database_path_temp =
+			temporary_database_path (
+				& self.database_path);
+
+		match fs::remove_file (
+			& database_path_temp) {
+
+			Err (ref io_error)
+			if io_error.kind () == io::ErrorKind::NotFound =>
+				(),
+
+			result =>
+				io_result (result) ?,
+
+		}
+
+		let mut connection =
+			sqlite_result (
+				Connection::open (
+					& database_path_temp),
+			) ?;
+
+		sqlite_result (
+			connection.execute_batch (
+				CREATE_SCHEMA),
+		) ?;
+
+		set_schema_version (
+			& connection,
+		) ?;
+
+		let mut fingerprints =
+			HashMap::new ();
+
+		let mut scan_generations =
+			Vec::new ();
+
+		write_changes (
+			& mut connection,
+			& mut fingerprints,
+			& mut scan_generations,
+			file_database,
+		) ?;
+
+		sqlite_result (
+			connection.close (
+			).map_err (
+				|(_, sqlite_error)|
+				sqlite_error
+			),
+		) ?;
+
+		io_result (
+			fs::rename (
+				& database_path_temp,
+				& self.database_path),
+		) ?;
+
+		// a journal left by a database in another format no longer applies
+
+		remove_journal (
+			& self.database_path,
+		) ?;
+
+		self.connection =
+			Some (
+				sqlite_result (
+					Connection::open (
+						& self.database_path),
+				) ?);
+
+		self.fingerprints =
+			fingerprints;
+
+		self.scan_generations =
+			scan_generations;
+
+		Ok (())
+
+	}
+
+}
+
+impl DatabaseStorage for SqliteStorage {
+
+	fn path (
+		& self,
+	) -> & Path {
+		& self.database_path
+	}
+
+	fn read (
+		& mut self,
+		recursive_path_database: & mut RecursivePathDatabase,
+		root_paths: & [PathRef],
+	) -> Result <FileDatabase, String> {
+
+		self.connection = None;
+		self.fingerprints.clear ();
+		self.scan_generations.clear ();
+
+		// read databases in other formats, which will be replaced on write
+
+		if DatabaseFormat::detect_file (
+			& self.database_path,
+		) ? != DatabaseFormat::Sqlite {
+
+			return FlatFileStorage::new (
+				& self.database_path,
+				FlatFileFormat::Json,
+				0,
+			).read (
+				recursive_path_database,
+				root_paths,
+			);
+
+		}
+
+		// upgrade the schema, if necessary, rewriting any rows changed by the
+		// migrations in the same transaction, so the new schema version is
+		// never recorded without them
+
+		let mut connection =
+			sqlite_result (
+				Connection::open (
+					& self.database_path),
+			) ?;
+
+		let database_header =
+			read_header (
+				& connection,
+			) ?;
+
+		database_header.check () ?;
+
+		let file_data_list =
+			if database_header.needs_migration () {
+
+			let transaction =
+				sqlite_result (
+					connection.transaction (),
+				) ?;
+
+			migrate_schema (
+				& transaction,
+				& database_header,
+			) ?;
+
+			let file_data_list =
+				migrate_rows (
+					recursive_path_database,
+					& transaction,
+					& database_header,
+				) ?;
+
+			sqlite_result (
+				transaction.commit (),
+			) ?;
+
+			file_data_list
+
+		} else {
+
+			read_rows (
+				recursive_path_database,
+				& connection,
+			) ?
+
+		};
+
+		for file_data in file_data_list.iter () {
+
+			self.fingerprints.insert (
+				file_data.path.clone (),
+				file_data.fingerprint ());
+
+		}
+
+		self.scan_generations =
+			read_scan_generations (
+				& connection,
+			) ?;
+
+		self.connection =
+			Some (connection);
+
+		// rows come back in no particular order
+
+		let mut file_database =
+			FileDatabase::from_unordered (
+				recursive_path_database,
+				root_paths,
+				file_data_list);
+
+		file_database.set_scan_generations (
+			self.scan_generations.clone ());
+
+		Ok (file_database)
+
+	}
+
+	fn read_stored (
+		& mut self,
+		recursive_path_database: & mut RecursivePathDatabase,
+	) -> Result <StoredDatabase, String> {
+
+		self.connection = None;
+		self.fingerprints.clear ();
+		self.scan_generations.clear ();
+
+		let database_format =
+			DatabaseFormat::detect_file (
+				& self.database_path,
+			) ?;
+
+		if let Some (flat_file_format) =
+			FlatFileFormat::from_database_format (
+				database_format) {
+
+			return FlatFileStorage::new (
+				& self.database_path,
+				flat_file_format,
+				0,
+			).read_stored (
+				recursive_path_database,
+			);
+
+		}
+
+		// the schema is upgraded so the rows can be read, but in a transaction
+		// which is rolled back, so the database is left as it was
+
+		let mut connection =
+			sqlite_result (
+				Connection::open (
+					& self.database_path),
+			) ?;
+
+		let database_header =
+			read_header (
+				& connection,
+			) ?;
+
+		database_header.check () ?;
+
+		let transaction =
+			sqlite_result (
+				connection.transaction (),
+			) ?;
+
+		if database_header.needs_migration () {
+
+			migrate_schema (
+				& transaction,
+				& database_header,
+			) ?;
+
+		}
+
+		let file_data_list =
+			read_rows (
+				recursive_path_database,
+				& transaction,
+			) ?;
+
+		let scan_generations =
+			read_scan_generations (
+				& transaction,
+			) ?;
+
+		sqlite_result (
+			transaction.rollback (),
+		) ?;
+
+		Ok (StoredDatabase {
+			database_format: DatabaseFormat::Sqlite,
+			database_header: database_header,
+			file_data_list: file_data_list,
+			file_data_ordered: false,
+			scan_generations: scan_generations,
+			journal_entries: Vec::new (),
+			journal_complete: true,
+		})
+
+	}
+
+	fn write (
+		& mut self,
+		file_database: & FileDatabase,
+	) -> Result <(), String> {
+
+		match self.connection {
+
+			Some (ref mut connection) =>
+				write_changes (
+					connection,
+					& mut self.fingerprints,
+					& mut self.scan_generations,
+					file_database),
+
+			None =>
+				self.create (
+					file_database),
+
+		}
+
+	}
+
+	/// Every write already leaves the database complete, so there is nothing
+	/// more to do here.
+
+	fn compact (
+		& mut self,
+		file_database: & FileDatabase,
+	) -> Result <(), String> {
+
+		self.write (
+			file_database)
+
+	}
+
+}
+
+/// Reads every row in the files table, in the order SQLite returns them.
+
+fn read_rows (
+	recursive_path_database: & mut RecursivePathDatabase,
+	connection: & Connection,
+) -> Result <Vec <FileData>, String> {
+
+	let mut statement =
+		sqlite_result (
+			connection.prepare (
+				SELECT_FILES),
+		) ?;
+
+	let mut rows =
+		sqlite_result (
+			statement.query (
+				[]),
+		) ?;
+
+	let mut file_data_list: Vec <FileData> =
+		Vec::new ();
+
+	while let Some (row) =
+		sqlite_result (
+			rows.next (),
+		) ? {
+
+		file_data_list.push (
+			read_row (
+				recursive_path_database,
+				row,
+			) ?);
+
+	}
+
+	Ok (file_data_list)
+
+}
+
+/// Reads every scan generation.
+
+fn read_scan_generations (
+	connection: & Connection,
+) -> Result <Vec <ScanGeneration>, String> {
+
+	let mut statement =
+		sqlite_result (
+			connection.prepare (
+				SELECT_SCAN_GENERATIONS),
+		) ?;
+
+	let mut rows =
+		sqlite_result (
+			statement.query (
+				[]),
+		) ?;
+
+	let mut scan_generations: Vec <ScanGeneration> =
+		Vec::new ();
+
+	while let Some (row) =
+		sqlite_result (
+			rows.next (),
+		) ? {
+
+		let filesystem_uuid: String =
+			sqlite_result (row.get (0)) ?;
+
+		let path =
+			match sqlite_result (row.get_ref (2)) ? {
+
+			ValueRef::Text (bytes) | ValueRef::Blob (bytes) =>
+				PathBuf::from (
+					OsStr::from_bytes (
+						bytes)),
+
+			_ =>
+				return Err ("Invalid scan generation path".to_owned ()),
+
+		};
+
+		scan_generations.push (
+			ScanGeneration {
+				filesystem_uuid: decode_uuid (
+					& filesystem_uuid,
+				) ?,
+				subvolume_id: sqlite_result (row.get::<_, i64> (1)) ? as u64,
+				path: path,
+				generation: sqlite_result (row.get::<_, i64> (3)) ? as u64,
+				filter_fingerprint:
+					sqlite_result (row.get::<_, i64> (4)) ? as u64,
+			});
+
+	}
+
+	Ok (scan_generations)
+
+}
+
+/// Writes the rows which have changed since the fingerprints were taken, and
+/// deletes those which are no longer present, along with the scan generations
+/// if they have changed, all in a single transaction.
+
+fn write_changes (
+	connection: & mut Connection,
+	fingerprints: & mut HashMap <RecursivePathRef, u64>,
+	scan_generations: & mut Vec <ScanGeneration>,
+	file_database: & FileDatabase,
+) -> Result <(), String> {
+
+	let mut new_fingerprints: HashMap <RecursivePathRef, u64> =
+		HashMap::new ();
+
+	let transaction =
+		sqlite_result (
+			connection.transaction (),
+		) ?;
+
+	{
+
+		let mut replace_statement =
+			sqlite_result (
+				transaction.prepare (
+					REPLACE_FILE),
+			) ?;
+
+		for file_data in file_database.iter () {
+
+			let fingerprint =
+				file_data.fingerprint ();
+
+			if fingerprints.get (
+				& file_data.path,
+			) != Some (& fingerprint) {
+
+				write_row (
+					& mut replace_statement,
+					file_data,
+				) ?;
+
+			}
+
+			new_fingerprints.insert (
+				file_data.path.clone (),
+				fingerprint);
+
+		}
+
+		let mut delete_statement =
+			sqlite_result (
+				transaction.prepare (
+					DELETE_FILE),
+			) ?;
+
+		for path in fingerprints.keys () {
+
+			if new_fingerprints.contains_key (path) {
+				continue;
+			}
+
+			sqlite_result (
+				delete_statement.execute (
+					[ path_value (& path.to_path ()) ]),
+			) ?;
+
+		}
+
+	}
+
+	let scan_generations_changed =
+		file_database.scan_generations () != & scan_generations [..];
+
+	if scan_generations_changed {
+
+		sqlite_result (
+			transaction.execute (
+				DELETE_SCAN_GENERATIONS,
+				[]),
+		) ?;
+
+		let mut insert_statement =
+			sqlite_result (
+				transaction.prepare (
+					INSERT_SCAN_GENERATION),
+			) ?;
+
+		for scan_generation in file_database.scan_generations () {
+
+			sqlite_result (
+				insert_statement.execute (
+					& [
+						& format_uuid (
+							& scan_generation.filesystem_uuid) as & ToSql,
+						& (scan_generation.subvolume_id as i64),
+						& path_value (& scan_generation.path),
+						& (scan_generation.generation as i64),
+						& (scan_generation.filter_fingerprint as i64),
+					] [..]),
+			) ?;
+
+		}
+
+	}
+
+	sqlite_result (
+		transaction.execute (
+			REPLACE_TOOL_VERSION,
+			[ TOOL_VERSION ]),
+	) ?;
+
+	sqlite_result (
+		transaction.commit (),
+	) ?;
+
+	* fingerprints =
+		new_fingerprints;
+
+	if scan_generations_changed {
+
+		* scan_generations =
+			file_database.scan_generations ().to_vec ();
+
+	}
+
+	Ok (())
+
+}
+
+/// Reads the schema version and tool version. Databases written before these
+/// were recorded have a `user_version` of zero.
+
+fn read_header (
+	connection: & Connection,
+) -> Result <DatabaseHeader, String> {
+
+	let schema_version: u32 =
+		sqlite_result (
+			connection.query_row (
+				"PRAGMA user_version",
+				[],
+				|row| row.get (0)),
+		) ?;
+
+	if schema_version == 0 {
+		return Ok (DatabaseHeader::legacy ());
+	}
+
+	let tool_version: String =
+		sqlite_result (
+			connection.query_row (
+				SELECT_TOOL_VERSION,
+				[],
+				|row| row.get (0)),
+		) ?;
+
+	Ok (DatabaseHeader {
+		schema_version: schema_version,
+		tool_version: tool_version,
+	})
+
+}
+
+/// Applies the SQL for every migration the database needs, and records the new
+/// schema version. This must be done in the same transaction as any rows the
+/// migrations change, so it either happens completely or not at all.
+
+fn migrate_schema (
+	transaction: & Transaction,
+	database_header: & DatabaseHeader,
+) -> Result <(), String> {
+
+	for migration in database_header.migrations () {
+
+		transaction.execute_batch (
+			migration.sqlite_statements,
+		).map_err (
+			|sqlite_error|
+
+			format! (
+				"Error migrating to schema version {} ({}): {}",
+				migration.schema_version,
+				migration.description,
+				sqlite_error)
+
+		) ?;
+
+	}
+
+	set_schema_version (
+		transaction,
+	) ?;
+
+	sqlite_result (
+		transaction.execute (
+			REPLACE_TOOL_VERSION,
+			[ TOOL_VERSION ]),
+	) ?;
+
+	Ok (())
+
+}
+
+/// Reads every row and applies the file migrations for the given header to
+/// it, writing back those which have changed.
+
+fn migrate_rows (
+	recursive_path_database: & mut RecursivePathDatabase,
+	transaction: & Transaction,
+	database_header: & DatabaseHeader,
+) -> Result <Vec <FileData>, String> {
+
+	let mut file_data_list =
+		read_rows (
+			recursive_path_database,
+			transaction,
+		) ?;
+
+	let mut replace_statement =
+		sqlite_result (
+			transaction.prepare (
+				REPLACE_FILE),
+		) ?;
+
+	for file_data in file_data_list.iter_mut () {
+
+		let fingerprint =
+			file_data.fingerprint ();
+
+		migrate_file_data (
+			database_header,
+			file_data);
+
+		if file_data.fingerprint () != fingerprint {
+
+			write_row (
+				& mut replace_statement,
+				file_data,
+			) ?;
+
+		}
+
+	}
+
+	Ok (file_data_list)
+
+}
+
+fn set_schema_version (
+	connection: & Connection,
+) -> Result <(), String> {
+
+	sqlite_result (
+		connection.execute_batch (
+			& format! (
+				"PRAGMA user_version = {}",
+				DATABASE_SCHEMA_VERSION)),
+	)
+
+}
+
+fn read_row (
+	recursive_path_database: & mut RecursivePathDatabase,
+	row: & rusqlite::Row,
+) -> Result <FileData, String> {
+
+	let path_bytes =
+		match sqlite_result (row.get_ref (0)) ? {
+
+		ValueRef::Text (bytes) | ValueRef::Blob (bytes) =>
+			bytes.to_owned (),
+
+		_ =>
+			return Err ("Invalid path".to_owned ()),
+
+	};
+
+	let file_path =
+		recursive_path_database.for_path (
+			PathBuf::from (
+				OsStr::from_bytes (
+					& path_bytes)),
+		).ok_or_else (
+			||
+
+			format! (
+				"Invalid path: {}",
+				String::from_utf8_lossy (
+					& path_bytes))
+
+		) ?;
+
+	let content_hash_algorithm_name: String =
+		sqlite_result (row.get (2)) ?;
+
+	let content_hash_algorithm =
+		HashAlgorithm::from_name (
+			& content_hash_algorithm_name,
+		).ok_or_else (
+			||
+
+			format! (
+				"Unknown hash algorithm: {}",
+				content_hash_algorithm_name)
+
+		) ?;
+
+	let chunk_table_bytes: Option <Vec <u8>> =
+		sqlite_result (row.get (15)) ?;
+
+	let chunk_table =
+		match chunk_table_bytes {
+
+		Some (chunk_table_bytes) =>
+			Some (
+				read_chunk_table (
+					& mut & chunk_table_bytes [..],
+				) ?),
+
+		None =>
+			None,
+
+	};
+
+	let filesystem_uuid: Option <String> =
+		sqlite_result (row.get (21)) ?;
+
+	let subvolume_id: Option <i64> =
+		sqlite_result (row.get (22)) ?;
+
+	let subvolume_path =
+		match sqlite_result (row.get_ref (23)) ? {
+
+		ValueRef::Text (bytes) | ValueRef::Blob (bytes) =>
+			Some (
+				PathBuf::from (
+					OsStr::from_bytes (
+						bytes))),
+
+		_ =>
+			None,
+
+	};
+
+	let subvolume_read_only =
+		sqlite_result (row.get::<_, i64> (26)) ? != 0;
+
+	let subvolume =
+		match (filesystem_uuid, subvolume_id, subvolume_path) {
+
+		(
+			Some (filesystem_uuid),
+			Some (subvolume_id),
+			Some (subvolume_path),
+		) =>
+			Subvolume::from_relative_path (
+				decode_uuid (
+					& filesystem_uuid,
+				) ?,
+				subvolume_id as u64,
+				subvolume_read_only,
+				& file_path,
+				& subvolume_path),
+
+		_ =>
+			None,
+
+	};
+
+	Ok (FileData {
+
+		path: file_path,
+		root_path: None,
+		subvolume: subvolume,
+		size: sqlite_result (row.get::<_, i64> (1)) ? as u64,
+
+		content_hash_algorithm: content_hash_algorithm,
+
+		partial_hash: read_hash_column (row, 3) ?,
+		partial_hash_time: sqlite_result (row.get (4)) ?,
+		partial_hash_needed: false,
+
+		content_hash: read_hash_column (row, 5) ?,
+		content_hash_time: sqlite_result (row.get (6)) ?,
+		content_hash_needed: false,
+
+		extent_hash: read_hash_column (row, 7) ?,
+		extent_hash_time: sqlite_result (row.get (8)) ?,
+		extent_hash_version: sqlite_result (row.get (9)) ?,
+
+		defragment_time: sqlite_result (row.get (10)) ?,
+		deduplicate_time: sqlite_result (row.get (11)) ?,
+		verify_time: sqlite_result (row.get (12)) ?,
+
+		group_size: sqlite_result (row.get::<_, i64> (13)) ? as u64,
+		group_shared: sqlite_result (row.get::<_, i64> (14)) ? as u64,
+
+		chunk_table: chunk_table,
+
+		mtime: sqlite_result (row.get (16)) ?,
+		ctime: sqlite_result (row.get (17)) ?,
+
+		mode: sqlite_result (row.get (18)) ?,
+		uid: sqlite_result (row.get (19)) ?,
+		gid: sqlite_result (row.get (20)) ?,
+
+		ino: sqlite_result (row.get::<_, i64> (24)) ? as u64,
+		nlink: sqlite_result (row.get::<_, i64> (25)) ? as u64,
+
+	})
+
+}
+
+fn write_row (
+	statement: & mut rusqlite::Statement,
+	file_data: & FileData,
+) -> Result <(), String> {
+
+	let chunk_table_bytes =
+		file_data.chunk_table.as_ref ().map (
+			|chunk_table| {
+
+			let mut buffer: Vec <u8> =
+				Vec::new ();
+
+			write_chunk_table (
+				& mut buffer,
+				chunk_table);
+
+			buffer
+
+		});
+
+	let subvolume_relative_path =
+		file_data.subvolume.as_ref ().and_then (
+			|subvolume|
+
+			subvolume.relative_path (
+				& file_data.path,
+			).map (
+				|relative_path|
+				(subvolume, relative_path)
+			)
+
+		);
+
+	let filesystem_uuid =
+		subvolume_relative_path.as_ref ().map (
+			|& (subvolume, _)|
+			format_uuid (& subvolume.filesystem_uuid)
+		);
+
+	let subvolume_id =
+		subvolume_relative_path.as_ref ().map (
+			|& (subvolume, _)|
+			subvolume.subvolume_id as i64
+		);
+
+	let subvolume_path =
+		match subvolume_relative_path {
+			Some ((_, ref relative_path)) => path_value (relative_path),
+			None => Value::Null,
+		};
+
+	let subvolume_read_only =
+		subvolume_relative_path.as_ref ().map (
+			|& (subvolume, _)|
+			subvolume.read_only as i64
+		).unwrap_or (0);
+
+	sqlite_result (
+		statement.execute (
+			& [
+				& path_value (& file_data.path.to_path ()) as & ToSql,
+				& (file_data.size as i64),
+				& file_data.content_hash_algorithm.name (),
+				& hash_value (& file_data.partial_hash),
+				& file_data.partial_hash_time,
+				& hash_value (& file_data.content_hash),
+				& file_data.content_hash_time,
+				& hash_value (& file_data.extent_hash),
+				& file_data.extent_hash_time,
+				& file_data.extent_hash_version,
+				& file_data.defragment_time,
+				& file_data.deduplicate_time,
+				& file_data.verify_time,
+				& (file_data.group_size as i64),
+				& (file_data.group_shared as i64),
+				& chunk_table_bytes,
+				& file_data.mtime,
+				& file_data.ctime,
+				& file_data.mode,
+				& file_data.uid,
+				& file_data.gid,
+				& filesystem_uuid,
+				& subvolume_id,
+				& subvolume_path,
+				& (file_data.ino as i64),
+				& (file_data.nlink as i64),
+				& subvolume_read_only,
+			] [..]),
+	) ?;
+
+	Ok (())
+
+}
+
+fn read_hash_column (
+	row: & rusqlite::Row,
+	index: usize,
+) -> Result <Hash, String> {
+
+	let bytes: Option <Vec <u8>> =
+		sqlite_result (row.get (index)) ?;
+
+	match bytes {
+
+		Some (ref bytes) if bytes.len () == HASH_SIZE => {
+
+			let mut hash =
+				ZERO_HASH;
+
+			hash.copy_from_slice (
+				bytes);
+
+			Ok (hash)
+
+		},
+
+		Some (_) =>
+			Err ("Invalid hash length".to_owned ()),
+
+		None =>
+			Ok (ZERO_HASH),
+
+	}
+
+}
+
+fn hash_value (
+	hash: & Hash,
+) -> Option <& [u8]> {
+
+	if * hash == ZERO_HASH {
+		None
+	} else {
+		Some (& hash [..])
+	}
+
+}
+
+fn path_value (
+	path: & Path,
+) -> Value {
+
+	match path.to_str () {
+
+		Some (path_str) =>
+			Value::Text (
+				path_str.to_owned ()),
+
+		None =>
+			Value::Blob (
+				path.as_os_str ().as_bytes ().to_owned ()),
+
+	}
+
+}
+
+fn sqlite_result <Type> (
+	result: Result <Type, rusqlite::Error>,
+) -> Result <Type, String> {
+
+	result.map_err (
+		|sqlite_error|
+		format! ("SQLite error: {}", sqlite_error)
+	)
+
+}
+
+// ex: noet ts=4 filetype=rust
@@ -0,0 +1,168 @@
+use std::path::Path;
+use std::path::PathBuf;
+
+use database::*;
+use types::*;
+
+/// The btrfs subvolume a file is in. The filesystem UUID and subvolume ID stay
+/// the same wherever it is mounted, so files are stored along with these and
+/// their path relative to the top of the subvolume, and can be found again
+/// after the subvolume has moved. The root path is where the top of the
+/// subvolume was when the file was last seen. Files in read only subvolumes,
+/// such as most snapshots, can't be changed, so they are only ever used as the
+/// source of a deduplication.
+
+#[ derive (Clone, Debug, Eq, Hash, PartialEq) ]
+pub struct Subvolume {
+	pub filesystem_uuid: Uuid,
+	pub subvolume_id: u64,
+	pub root_path: RecursivePathRef,
+	pub read_only: bool,
+}
+
+impl Subvolume {
+
+	/// Works out where the top of a subvolume was, from the path of a file in
+	/// it and the file's path relative to the top. Returns None if the two
+	/// don't agree.
+
+	pub fn from_relative_path (
+		filesystem_uuid: Uuid,
+		subvolume_id: u64,
+		read_only: bool,
+		path: & RecursivePathRef,
+		relative_path: & Path,
+	) -> Option <Subvolume> {
+
+		let relative_depth =
+			relative_path.components ().count () as u16;
+
+		if relative_depth == 0 || relative_depth > path.depth () {
+			return None;
+		}
+
+		let root_path =
+			ancestor (
+				path,
+				path.depth () - relative_depth);
+
+		if root_path.to_path ().join (relative_path) != path.to_path () {
+			return None;
+		}
+
+		Some (Subvolume {
+			filesystem_uuid: filesystem_uuid,
+			subvolume_id: subvolume_id,
+			root_path: root_path,
+			read_only: read_only,
+		})
+
+	}
+
+	/// Whether this is the same subvolume as another, wherever each of them was
+	/// mounted.
+
+	pub fn is_same_subvolume (
+		& self,
+		other: & Subvolume,
+	) -> bool {
+
+		self.filesystem_uuid == other.filesystem_uuid
+		&& self.subvolume_id == other.subvolume_id
+
+	}
+
+	/// Works out the path of a file relative to the top of the subvolume.
+
+	pub fn relative_path (
+		& self,
+		path: & RecursivePathRef,
+	) -> Option <PathBuf> {
+
+		if path.depth () <= self.root_path.depth () {
+			return None;
+		}
+
+		let mut names =
+			Vec::new ();
+
+		let mut ancestor =
+			path.clone ();
+
+		while ancestor.depth () > self.root_path.depth () {
+
+			names.push (
+				ancestor.name ().unwrap ().to_owned ());
+
+			ancestor =
+				ancestor.parent ().unwrap ();
+
+		}
+
+		if ancestor != self.root_path {
+			return None;
+		}
+
+		Some (
+			names.iter ().rev ().collect ())
+
+	}
+
+	/// Finds the path a file would have if the top of the subvolume was at
+	/// another path.
+
+	pub fn relocate (
+		& self,
+		recursive_path_database: & mut RecursivePathDatabase,
+		path: & RecursivePathRef,
+		root_path: & RecursivePathRef,
+	) -> Option <(RecursivePathRef, Subvolume)> {
+
+		self.relative_path (
+			path,
+		).and_then (
+			|relative_path|
+
+			recursive_path_database.for_path (
+				root_path.to_path ().join (
+					relative_path))
+
+		).map (
+			|new_path|
+
+			(
+				new_path,
+				Subvolume {
+					filesystem_uuid: self.filesystem_uuid,
+					subvolume_id: self.subvolume_id,
+					root_path: root_path.clone (),
+					read_only: self.read_only,
+				},
+			)
+
+		)
+
+	}
+
+}
+
+fn ancestor (
+	path: & RecursivePathRef,
+	depth: u16,
+) -> RecursivePathRef {
+
+	let mut ancestor =
+		path.clone ();
+
+	while ancestor.depth () > depth {
+
+		ancestor =
+			ancestor.parent ().unwrap ();
+
+	}
+
+	ancestor
+
+}
+
+// ex: noet ts=4 filetype=rust
@@ -0,0 +1,173 @@
+use blake3;
+
+use sha2::Digest;
+use sha2::Sha256;
+
+use xxhash_rust::xxh3::Xxh3;
+
+use types::*;
+
+/// The algorithms available for hashing file contents. Every algorithm
+/// produces a `Hash`, shorter outputs are padded with zeros.
+
+#[ derive (Clone, Copy, Debug, Eq, Hash, PartialEq) ]
+pub enum HashAlgorithm {
+	Sha256,
+	Blake3,
+	Xxh3_128,
+}
+
+pub trait HashFunction {
+
+	fn input (
+		& mut self,
+		data: & [u8],
+	);
+
+	fn result (
+		self: Box <Self>,
+	) -> Hash;
+
+}
+
+impl HashAlgorithm {
+
+	pub fn names (
+	) -> & 'static [& 'static str] {
+		& [ "sha256", "blake3", "xxh3-128" ]
+	}
+
+	pub fn from_name (
+		name: & str,
+	) -> Option <HashAlgorithm> {
+
+		match name {
+			"sha256" => Some (HashAlgorithm::Sha256),
+			"blake3" => Some (HashAlgorithm::Blake3),
+			"xxh3-128" => Some (HashAlgorithm::Xxh3_128),
+			_ => None,
+		}
+
+	}
+
+	pub fn name (
+		& self,
+	) -> & 'static str {
+
+		match * self {
+			HashAlgorithm::Sha256 => "sha256",
+			HashAlgorithm::Blake3 => "blake3",
+			HashAlgorithm::Xxh3_128 => "xxh3-128",
+		}
+
+	}
+
+	/// Non-cryptographic hashes are fast, but collisions are likely enough that
+	/// the contents must be compared before deduplicating.
+
+	pub fn is_cryptographic (
+		& self,
+	) -> bool {
+
+		match * self {
+			HashAlgorithm::Sha256 => true,
+			HashAlgorithm::Blake3 => true,
+			HashAlgorithm::Xxh3_128 => false,
+		}
+
+	}
+
+	pub fn new_hash_function (
+		& self,
+	) -> Box <HashFunction> {
+
+		match * self {
+
+			HashAlgorithm::Sha256 =>
+				Box::new (
+					Sha256::new ()),
+
+			HashAlgorithm::Blake3 =>
+				Box::new (
+					blake3::Hasher::new ()),
+
+			HashAlgorithm::Xxh3_128 =>
+				Box::new (
+					Xxh3::new ()),
+
+		}
+
+	}
+
+}
+
+impl HashFunction for Sha256 {
+
+	fn input (
+		& mut self,
+		data: & [u8],
+	) {
+		Digest::input (self, data);
+	}
+
+	fn result (
+		self: Box <Self>,
+	) -> Hash {
+
+		let mut result: Hash =
+			[0u8; HASH_SIZE];
+
+		result.copy_from_slice (
+			& Digest::result (* self));
+
+		result
+
+	}
+
+}
+
+impl HashFunction for blake3::Hasher {
+
+	fn input (
+		& mut self,
+		data: & [u8],
+	) {
+		self.update (data);
+	}
+
+	fn result (
+		self: Box <Self>,
+	) -> Hash {
+
+		* self.finalize ().as_bytes ()
+
+	}
+
+}
+
+impl HashFunction for Xxh3 {
+
+	fn input (
+		& mut self,
+		data: & [u8],
+	) {
+		self.update (data);
+	}
+
+	fn result (
+		self: Box <Self>,
+	) -> Hash {
+
+		let mut result: Hash =
+			[0u8; HASH_SIZE];
+
+		result [0 .. 16].copy_from_slice (
+			& self.digest128 ().to_le_bytes ());
+
+		result
+
+	}
+
+}
+
+// ex: noet ts=4 filetype=rust
@@ -0,0 +1,189 @@
+use std::fs::File;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+use linux::*;
+
+const FILE_DEDUPE_RANGE_SAME: i32 = 0;
+const FILE_DEDUPE_RANGE_DIFFERS: i32 = 1;
+
+#[ repr (C) ]
+struct IoctlFileDedupeRange {
+	src_offset: u64,
+	src_length: u64,
+	dest_count: u16,
+	reserved1: u16,
+	reserved2: u32,
+}
+
+#[ repr (C) ]
+struct IoctlFileDedupeRangeInfo {
+	dest_fd: i64,
+	dest_offset: u64,
+	bytes_deduped: u64,
+	status: i32,
+	reserved: u32,
+}
+
+/// The kernel takes the destinations as an array following the range itself.
+/// We only ever pass a single destination.
+
+#[ repr (C) ]
+struct IoctlFileDedupeRangeSingle {
+	range: IoctlFileDedupeRange,
+	info: IoctlFileDedupeRangeInfo,
+}
+
+#[ derive (Clone, Copy, Debug, Eq, PartialEq) ]
+pub enum DedupeRangeResult {
+	Same (u64),
+	Differs,
+}
+
+/// Asks the kernel to share a range of the source file with a range of the
+/// target file, if their contents are identical. On success, returns the
+/// number of bytes deduplicated, which may be less than requested.
+
+pub fn deduplicate_range (
+	source_file: & File,
+	source_offset: u64,
+	length: u64,
+	target_file: & File,
+	target_offset: u64,
+) -> Result <DedupeRangeResult, String> {
+
+	let mut dedupe_range =
+		IoctlFileDedupeRangeSingle {
+
+			range: IoctlFileDedupeRange {
+				src_offset: source_offset,
+				src_length: length,
+				dest_count: 1,
+				reserved1: 0,
+				reserved2: 0,
+			},
+
+			info: IoctlFileDedupeRangeInfo {
+				dest_fd: target_file.as_raw_fd () as i64,
+				dest_offset: target_offset,
+				bytes_deduped: 0,
+				status: 0,
+				reserved: 0,
+			},
+
+		};
+
+	ioctl_with_argument (
+		source_file.as_raw_fd (),
+		ioctl_read_write::<IoctlFileDedupeRange> (
+			BTRFS_IOCTL_MAGIC,
+			54),
+		& mut dedupe_range,
+		"Deduplicate range",
+	) ?;
+
+	match dedupe_range.info.status {
+
+		FILE_DEDUPE_RANGE_SAME =>
+			Ok (DedupeRangeResult::Same (
+				dedupe_range.info.bytes_deduped)),
+
+		FILE_DEDUPE_RANGE_DIFFERS =>
+			Ok (DedupeRangeResult::Differs),
+
+		status =>
+			Err (
+				format! (
+					"Deduplicate range failed with status {}",
+					status)),
+
+	}
+
+}
+
+/// Deduplicates the entire source file with each of the target files, in
+/// turn, stopping at the first error.
+
+pub fn deduplicate_files_with_source <
+	SourcePath: AsRef <Path>,
+	TargetPath: AsRef <Path>,
+> (
+	source_path: SourcePath,
+	target_paths: & [TargetPath],
+) -> Result <(), String> {
+
+	let source_path =
+		source_path.as_ref ();
+
+	let source_file =
+		File::open (
+			source_path,
+		).map_err (
+			|error|
+
+			format! (
+				"Error opening {}: {}",
+				source_path.to_string_lossy (),
+				error)
+
+		) ?;
+
+	let source_length =
+		source_file.metadata ().map_err (
+			|error|
+
+			format! (
+				"Error reading metadata for {}: {}",
+				source_path.to_string_lossy (),
+				error)
+
+		) ?.len ();
+
+	for target_path in target_paths.iter () {
+
+		let target_path =
+			target_path.as_ref ();
+
+		// the kernel accepts a target opened only for reading if we could
+		// write to it anyway, and opening it for writing would make it look
+		// modified to anything watching it
+
+		let target_file =
+			File::open (
+				target_path,
+			).map_err (
+				|error|
+
+				format! (
+					"Error opening {}: {}",
+					target_path.to_string_lossy (),
+					error)
+
+			) ?;
+
+		let result =
+			deduplicate_range (
+				& source_file,
+				0,
+				source_length,
+				& target_file,
+				0,
+			) ?;
+
+		if result == DedupeRangeResult::Differs {
+
+			return Err (
+				format! (
+					"Contents of {} differ from {}",
+					target_path.to_string_lossy (),
+					source_path.to_string_lossy ()));
+
+		}
+
+	}
+
+	Ok (())
+
+}
+
+// ex: noet ts=4 filetype=rust
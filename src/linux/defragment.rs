@@ -0,0 +1,121 @@
+use std::fs::File;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+use linux::*;
+
+#[ derive (Clone, Copy, Debug, Eq, PartialEq) ]
+pub enum CompressionType {
+	None,
+	Zlib,
+	Lzo,
+	Zstd,
+}
+
+impl CompressionType {
+
+	pub fn names (
+	) -> & 'static [& 'static str] {
+		& [ "none", "zlib", "lzo", "zstd" ]
+	}
+
+	pub fn from_name (
+		name: & str,
+	) -> Option <CompressionType> {
+
+		match name {
+			"none" => Some (CompressionType::None),
+			"zlib" => Some (CompressionType::Zlib),
+			"lzo" => Some (CompressionType::Lzo),
+			"zstd" => Some (CompressionType::Zstd),
+			_ => None,
+		}
+
+	}
+
+	fn ioctl_value (
+		& self,
+	) -> u32 {
+
+		match * self {
+			CompressionType::None => 0,
+			CompressionType::Zlib => 1,
+			CompressionType::Lzo => 2,
+			CompressionType::Zstd => 3,
+		}
+
+	}
+
+}
+
+#[ repr (C) ]
+struct IoctlDefragRangeArgs {
+	start: u64,
+	len: u64,
+	flags: u64,
+	extent_thresh: u32,
+	compress_type: u32,
+	unused: [u32; 4],
+}
+
+const DEFRAG_RANGE_COMPRESS: u64 = 1;
+const DEFRAG_RANGE_START_IO: u64 = 2;
+
+/// Defragments an entire file. Only extents smaller than the threshold are
+/// rewritten, unless a compression type is given, in which case the kernel
+/// rewrites everything.
+
+pub fn defragment_file (
+	file_path: & Path,
+	extent_threshold: u32,
+	compression_type: CompressionType,
+	flush_to_disk: bool,
+) -> Result <(), String> {
+
+	let file =
+		try! (
+			File::open (
+				file_path,
+			).map_err (
+				|error|
+
+				format! (
+					"Error opening file: {}",
+					error)
+
+			));
+
+	let mut defrag_range_args =
+		IoctlDefragRangeArgs {
+
+			start: 0,
+			len: -1_i64 as u64,
+
+			flags: (
+				if compression_type != CompressionType::None {
+					DEFRAG_RANGE_COMPRESS
+				} else { 0 }
+			|
+				if flush_to_disk {
+					DEFRAG_RANGE_START_IO
+				} else { 0 }
+			),
+
+			extent_thresh: extent_threshold,
+			compress_type: compression_type.ioctl_value (),
+
+			unused: [0; 4],
+
+		};
+
+	ioctl_with_argument (
+		file.as_raw_fd (),
+		ioctl_write::<IoctlDefragRangeArgs> (
+			BTRFS_IOCTL_MAGIC,
+			16),
+		& mut defrag_range_args,
+		"Defragment")
+
+}
+
+// ex: noet ts=4 filetype=rust
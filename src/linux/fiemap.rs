@@ -0,0 +1,175 @@
+use std::fs::File;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+use linux::*;
+
+pub const FIEMAP_EXTENT_LAST: u32 = 0x0001;
+pub const FIEMAP_EXTENT_ENCODED: u32 = 0x0008;
+pub const FIEMAP_EXTENT_SHARED: u32 = 0x2000;
+
+const FIEMAP_FLAG_SYNC: u32 = 0x0001;
+
+const FIEMAP_BATCH_SIZE: usize = 256;
+
+/// A single extent as reported by FIEMAP. On btrfs, compressed extents are
+/// flagged as encoded, and extents referenced from more than one place are
+/// flagged as shared.
+
+#[ derive (Clone, Copy, Debug, Eq, PartialEq) ]
+pub struct FileExtent {
+	pub logical: u64,
+	pub physical: u64,
+	pub length: u64,
+	pub flags: u32,
+}
+
+impl FileExtent {
+
+	pub fn is_shared (
+		& self,
+	) -> bool {
+		self.flags & FIEMAP_EXTENT_SHARED != 0
+	}
+
+	pub fn is_encoded (
+		& self,
+	) -> bool {
+		self.flags & FIEMAP_EXTENT_ENCODED != 0
+	}
+
+}
+
+#[ repr (C) ]
+#[ derive (Clone, Copy) ]
+struct IoctlFiemapExtent {
+	fe_logical: u64,
+	fe_physical: u64,
+	fe_length: u64,
+	fe_reserved64: [u64; 2],
+	fe_flags: u32,
+	fe_reserved: [u32; 3],
+}
+
+#[ repr (C) ]
+struct IoctlFiemap {
+	fm_start: u64,
+	fm_length: u64,
+	fm_flags: u32,
+	fm_mapped_extents: u32,
+	fm_extent_count: u32,
+	fm_reserved: u32,
+}
+
+#[ repr (C) ]
+struct IoctlFiemapBuffer {
+	fiemap: IoctlFiemap,
+	extents: [IoctlFiemapExtent; FIEMAP_BATCH_SIZE],
+}
+
+/// Reads the complete extent map for a file, making as many calls as needed.
+
+pub fn get_file_extent_map (
+	file_path: & Path,
+) -> Result <Vec <FileExtent>, String> {
+
+	let file =
+		try! (
+			File::open (
+				file_path,
+			).map_err (
+				|error|
+
+				format! (
+					"Error opening file: {}",
+					error)
+
+			));
+
+	let empty_extent =
+		IoctlFiemapExtent {
+			fe_logical: 0,
+			fe_physical: 0,
+			fe_length: 0,
+			fe_reserved64: [0; 2],
+			fe_flags: 0,
+			fe_reserved: [0; 3],
+		};
+
+	let mut fiemap_buffer =
+		Box::new (
+			IoctlFiemapBuffer {
+				fiemap: IoctlFiemap {
+					fm_start: 0,
+					fm_length: 0,
+					fm_flags: 0,
+					fm_mapped_extents: 0,
+					fm_extent_count: 0,
+					fm_reserved: 0,
+				},
+				extents: [empty_extent; FIEMAP_BATCH_SIZE],
+			});
+
+	let mut file_extents: Vec <FileExtent> =
+		Vec::new ();
+
+	let mut start: u64 = 0;
+
+	loop {
+
+		fiemap_buffer.fiemap = IoctlFiemap {
+			fm_start: start,
+			fm_length: -1_i64 as u64 - start,
+			fm_flags: FIEMAP_FLAG_SYNC,
+			fm_mapped_extents: 0,
+			fm_extent_count: FIEMAP_BATCH_SIZE as u32,
+			fm_reserved: 0,
+		};
+
+		try! (
+			ioctl_with_argument (
+				file.as_raw_fd (),
+				ioctl_read_write::<IoctlFiemap> (
+					'f' as u64,
+					11),
+				& mut * fiemap_buffer,
+				"Fiemap"));
+
+		let num_mapped_extents =
+			fiemap_buffer.fiemap.fm_mapped_extents as usize;
+
+		if num_mapped_extents == 0 {
+			break;
+		}
+
+		for ioctl_extent in fiemap_buffer.extents [
+			0 .. num_mapped_extents
+		].iter () {
+
+			file_extents.push (
+				FileExtent {
+					logical: ioctl_extent.fe_logical,
+					physical: ioctl_extent.fe_physical,
+					length: ioctl_extent.fe_length,
+					flags: ioctl_extent.fe_flags,
+				});
+
+		}
+
+		let last_extent =
+			file_extents.last ().unwrap ();
+
+		if last_extent.flags & FIEMAP_EXTENT_LAST != 0 {
+			break;
+		}
+
+		start =
+			last_extent.logical + last_extent.length;
+
+	}
+
+	Ok (file_extents)
+
+}
+
+// ex: noet ts=4 filetype=rust
@@ -0,0 +1,303 @@
+use std::ffi::CString;
+use std::ffi::OsStr;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::RawFd;
+use std::path::Path;
+use std::time::Duration;
+
+use libc;
+
+pub const IN_ATTRIB: u32 = 0x0000_0004;
+pub const IN_CLOSE_WRITE: u32 = 0x0000_0008;
+pub const IN_MOVED_FROM: u32 = 0x0000_0040;
+pub const IN_MOVED_TO: u32 = 0x0000_0080;
+pub const IN_CREATE: u32 = 0x0000_0100;
+pub const IN_DELETE: u32 = 0x0000_0200;
+pub const IN_Q_OVERFLOW: u32 = 0x0000_4000;
+pub const IN_IGNORED: u32 = 0x0000_8000;
+pub const IN_ONLYDIR: u32 = 0x0100_0000;
+pub const IN_DONT_FOLLOW: u32 = 0x0200_0000;
+pub const IN_ISDIR: u32 = 0x4000_0000;
+
+const EVENT_HEADER_SIZE: usize = 16;
+const EVENT_BUFFER_SIZE: usize = 0x10000;
+
+/// An inotify instance, which reports changes to the directories it is
+/// watching. Watches aren't recursive, so every directory needs its own.
+
+pub struct Inotify {
+	file_descriptor: RawFd,
+}
+
+/// A single change reported by inotify. The name is that of the entry in the
+/// watched directory which changed, or None if the change was to the directory
+/// itself.
+
+pub struct InotifyEvent {
+	pub watch_descriptor: i32,
+	pub mask: u32,
+	name: Option <Vec <u8>>,
+}
+
+impl Inotify {
+
+	pub fn new (
+	) -> Result <Inotify, String> {
+
+		let file_descriptor =
+			unsafe {
+				libc::inotify_init1 (
+					libc::IN_CLOEXEC)
+			};
+
+		if file_descriptor < 0 {
+
+			return Err (
+				format! (
+					"Error creating inotify instance: {}",
+					io::Error::last_os_error ()));
+
+		}
+
+		Ok (Inotify {
+			file_descriptor: file_descriptor,
+		})
+
+	}
+
+	/// Starts watching a directory, without following symlinks, returning the
+	/// watch descriptor which its events will carry. Watching the same
+	/// directory again returns the same descriptor.
+
+	pub fn add_watch (
+		& self,
+		path: & Path,
+		mask: u32,
+	) -> Result <i32, String> {
+
+		let path_c_string =
+			try! (
+				CString::new (
+					path.as_os_str ().as_bytes (),
+				).map_err (
+					|_|
+
+					format! (
+						"Invalid path: {}",
+						path.to_string_lossy ())
+
+				));
+
+		let watch_descriptor =
+			unsafe {
+				libc::inotify_add_watch (
+					self.file_descriptor,
+					path_c_string.as_ptr (),
+					mask | IN_ONLYDIR | IN_DONT_FOLLOW)
+			};
+
+		if watch_descriptor < 0 {
+
+			return Err (
+				format! (
+					"Error watching {}: {}",
+					path.to_string_lossy (),
+					io::Error::last_os_error ()));
+
+		}
+
+		Ok (watch_descriptor)
+
+	}
+
+	/// Stops watching a directory. The directory may already have gone, which
+	/// removes its watch anyway, so failures are ignored.
+
+	pub fn remove_watch (
+		& self,
+		watch_descriptor: i32,
+	) {
+
+		unsafe {
+			libc::inotify_rm_watch (
+				self.file_descriptor,
+				watch_descriptor);
+		}
+
+	}
+
+	/// Waits for events, returning as soon as any are available, or with none
+	/// once the timeout has passed. Without a timeout this waits indefinitely.
+
+	pub fn read_events (
+		& self,
+		timeout: Option <Duration>,
+	) -> Result <Vec <InotifyEvent>, String> {
+
+		let timeout_milliseconds =
+			timeout.map (
+				|timeout|
+
+				(
+					timeout.as_secs () * 1000
+					+ timeout.subsec_nanos () as u64 / 1_000_000
+				) as libc::c_int
+
+			).unwrap_or (-1);
+
+		let mut poll_fd =
+			libc::pollfd {
+				fd: self.file_descriptor,
+				events: libc::POLLIN,
+				revents: 0,
+			};
+
+		let poll_result =
+			unsafe {
+				libc::poll (
+					& mut poll_fd,
+					1,
+					timeout_milliseconds)
+			};
+
+		if poll_result < 0 {
+
+			let error =
+				io::Error::last_os_error ();
+
+			if error.kind () == io::ErrorKind::Interrupted {
+				return Ok (Vec::new ());
+			}
+
+			return Err (
+				format! (
+					"Error waiting for inotify events: {}",
+					error));
+
+		}
+
+		if poll_result == 0 {
+			return Ok (Vec::new ());
+		}
+
+		let mut buffer: Vec <u8> =
+			vec! [0; EVENT_BUFFER_SIZE];
+
+		let read_result =
+			unsafe {
+				libc::read (
+					self.file_descriptor,
+					buffer.as_mut_ptr () as * mut libc::c_void,
+					buffer.len ())
+			};
+
+		if read_result < 0 {
+
+			return Err (
+				format! (
+					"Error reading inotify events: {}",
+					io::Error::last_os_error ()));
+
+		}
+
+		let bytes =
+			& buffer [0 .. read_result as usize];
+
+		// each event is a fixed header followed by a name, padded with nuls
+
+		let mut events: Vec <InotifyEvent> =
+			Vec::new ();
+
+		let mut position: usize = 0;
+
+		while position + EVENT_HEADER_SIZE <= bytes.len () {
+
+			let watch_descriptor =
+				read_u32 (bytes, position) as i32;
+
+			let mask =
+				read_u32 (bytes, position + 4);
+
+			let name_length =
+				read_u32 (bytes, position + 12) as usize;
+
+			let name_start =
+				position + EVENT_HEADER_SIZE;
+
+			let name_bytes =
+				& bytes [name_start .. name_start + name_length];
+
+			let name_bytes =
+				& name_bytes [0 .. name_bytes.iter ().position (
+					|& byte|
+					byte == 0
+				).unwrap_or (name_length)];
+
+			events.push (
+				InotifyEvent {
+					watch_descriptor: watch_descriptor,
+					mask: mask,
+					name: if name_bytes.is_empty () {
+						None
+					} else {
+						Some (name_bytes.to_owned ())
+					},
+				});
+
+			position =
+				name_start + name_length;
+
+		}
+
+		Ok (events)
+
+	}
+
+}
+
+impl InotifyEvent {
+
+	pub fn name (
+		& self,
+	) -> Option <& OsStr> {
+
+		self.name.as_ref ().map (
+			|name|
+			OsStr::from_bytes (name)
+		)
+
+	}
+
+}
+
+impl Drop for Inotify {
+
+	fn drop (
+		& mut self,
+	) {
+
+		unsafe {
+			libc::close (
+				self.file_descriptor);
+		}
+
+	}
+
+}
+
+fn read_u32 (
+	bytes: & [u8],
+	offset: usize,
+) -> u32 {
+
+	u32::from_ne_bytes ([
+		bytes [offset],
+		bytes [offset + 1],
+		bytes [offset + 2],
+		bytes [offset + 3],
+	])
+
+}
+
+// ex: noet ts=4 filetype=rust
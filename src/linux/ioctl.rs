@@ -0,0 +1,107 @@
+use std::io;
+use std::mem;
+
+use libc;
+
+pub const BTRFS_IOCTL_MAGIC: u64 = 0x94;
+
+const IOC_NRBITS: u64 = 8;
+const IOC_TYPEBITS: u64 = 8;
+const IOC_SIZEBITS: u64 = 14;
+
+const IOC_NRSHIFT: u64 = 0;
+const IOC_TYPESHIFT: u64 = IOC_NRSHIFT + IOC_NRBITS;
+const IOC_SIZESHIFT: u64 = IOC_TYPESHIFT + IOC_TYPEBITS;
+const IOC_DIRSHIFT: u64 = IOC_SIZESHIFT + IOC_SIZEBITS;
+
+const IOC_WRITE: u64 = 1;
+const IOC_READ: u64 = 2;
+
+/// Equivalent to the kernel's `_IOR` macro.
+
+pub fn ioctl_read <Argument> (
+	ioctl_type: u64,
+	ioctl_number: u64,
+) -> u64 {
+
+	ioctl_request::<Argument> (
+		IOC_READ,
+		ioctl_type,
+		ioctl_number)
+
+}
+
+/// Equivalent to the kernel's `_IOW` macro.
+
+pub fn ioctl_write <Argument> (
+	ioctl_type: u64,
+	ioctl_number: u64,
+) -> u64 {
+
+	ioctl_request::<Argument> (
+		IOC_WRITE,
+		ioctl_type,
+		ioctl_number)
+
+}
+
+/// Equivalent to the kernel's `_IOWR` macro.
+
+pub fn ioctl_read_write <Argument> (
+	ioctl_type: u64,
+	ioctl_number: u64,
+) -> u64 {
+
+	ioctl_request::<Argument> (
+		IOC_READ | IOC_WRITE,
+		ioctl_type,
+		ioctl_number)
+
+}
+
+fn ioctl_request <Argument> (
+	direction: u64,
+	ioctl_type: u64,
+	ioctl_number: u64,
+) -> u64 {
+
+	(direction << IOC_DIRSHIFT)
+	| (ioctl_type << IOC_TYPESHIFT)
+	| (ioctl_number << IOC_NRSHIFT)
+	| ((mem::size_of::<Argument> () as u64) << IOC_SIZESHIFT)
+
+}
+
+/// Calls an ioctl with a pointer to the provided argument, converting a failure
+/// into an error message.
+
+pub fn ioctl_with_argument <Argument> (
+	file_descriptor: libc::c_int,
+	request: u64,
+	argument: & mut Argument,
+	description: & str,
+) -> Result <(), String> {
+
+	let ioctl_result =
+		unsafe {
+			libc::ioctl (
+				file_descriptor,
+				request as libc::c_ulong,
+				argument as * mut Argument)
+		};
+
+	if ioctl_result != 0 {
+
+		return Err (
+			format! (
+				"{} IOCTL failed: {}",
+				description,
+				io::Error::last_os_error ()));
+
+	}
+
+	Ok (())
+
+}
+
+// ex: noet ts=4 filetype=rust
@@ -0,0 +1,19 @@
+//! Wrappers for the kernel interfaces we use, which are mostly btrfs ioctls.
+
+mod deduplicate;
+mod defragment;
+mod fiemap;
+mod inotify;
+mod ioctl;
+mod subvolume;
+mod tree_search;
+
+pub use self::deduplicate::*;
+pub use self::defragment::*;
+pub use self::fiemap::*;
+pub use self::inotify::*;
+pub use self::ioctl::*;
+pub use self::subvolume::*;
+pub use self::tree_search::*;
+
+// ex: noet ts=4 filetype=rust
@@ -0,0 +1,138 @@
+use std::fs::File;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+use linux::*;
+use types::*;
+
+/// The inode number of the top directory of every btrfs subvolume.
+
+pub const BTRFS_FIRST_FREE_OBJECTID: u64 = 256;
+
+const BTRFS_SUBVOL_RDONLY: u64 = 1 << 1;
+
+#[ repr (C) ]
+struct IoctlFsInfoArgs {
+	max_id: u64,
+	num_devices: u64,
+	fsid: Uuid,
+	reserved: [u64; 124],
+}
+
+#[ repr (C) ]
+struct IoctlInoLookupArgs {
+	treeid: u64,
+	objectid: u64,
+	name: [u8; 4080],
+}
+
+/// Gets the UUID of the btrfs filesystem which a path is on.
+
+pub fn get_filesystem_uuid (
+	path: & Path,
+) -> Result <Uuid, String> {
+
+	let file =
+		try! (
+			open_for_ioctl (
+				path));
+
+	let mut fs_info_args =
+		IoctlFsInfoArgs {
+			max_id: 0,
+			num_devices: 0,
+			fsid: ZERO_UUID,
+			reserved: [0; 124],
+		};
+
+	try! (
+		ioctl_with_argument (
+			file.as_raw_fd (),
+			ioctl_read::<IoctlFsInfoArgs> (
+				BTRFS_IOCTL_MAGIC,
+				31),
+			& mut fs_info_args,
+			"Filesystem info"));
+
+	Ok (fs_info_args.fsid)
+
+}
+
+/// Gets the ID of the btrfs subvolume which a path is in. Looking up the top
+/// directory of the subvolume is allowed without any special privileges.
+
+pub fn get_subvolume_id (
+	path: & Path,
+) -> Result <u64, String> {
+
+	let file =
+		try! (
+			open_for_ioctl (
+				path));
+
+	let mut ino_lookup_args =
+		IoctlInoLookupArgs {
+			treeid: 0,
+			objectid: BTRFS_FIRST_FREE_OBJECTID,
+			name: [0; 4080],
+		};
+
+	try! (
+		ioctl_with_argument (
+			file.as_raw_fd (),
+			ioctl_read_write::<IoctlInoLookupArgs> (
+				BTRFS_IOCTL_MAGIC,
+				18),
+			& mut ino_lookup_args,
+			"Inode lookup"));
+
+	Ok (ino_lookup_args.treeid)
+
+}
+
+/// Finds out whether the btrfs subvolume which a path is in is read only, as
+/// snapshots usually are.
+
+pub fn get_subvolume_read_only (
+	path: & Path,
+) -> Result <bool, String> {
+
+	let file =
+		try! (
+			open_for_ioctl (
+				path));
+
+	let mut flags: u64 = 0;
+
+	try! (
+		ioctl_with_argument (
+			file.as_raw_fd (),
+			ioctl_read::<u64> (
+				BTRFS_IOCTL_MAGIC,
+				25),
+			& mut flags,
+			"Get subvolume flags"));
+
+	Ok (flags & BTRFS_SUBVOL_RDONLY != 0)
+
+}
+
+fn open_for_ioctl (
+	path: & Path,
+) -> Result <File, String> {
+
+	File::open (
+		path,
+	).map_err (
+		|error|
+
+		format! (
+			"Error opening {}: {}",
+			path.to_string_lossy (),
+			error)
+
+	)
+
+}
+
+// ex: noet ts=4 filetype=rust
@@ -0,0 +1,427 @@
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::path::PathBuf;
+
+use linux::*;
+
+const BTRFS_ROOT_TREE_OBJECTID: u64 = 1;
+const BTRFS_LAST_FREE_OBJECTID: u64 = -256_i64 as u64;
+
+const BTRFS_INODE_REF_KEY: u32 = 12;
+const BTRFS_INODE_EXTREF_KEY: u32 = 13;
+const BTRFS_ROOT_ITEM_KEY: u32 = 132;
+
+/// The generation follows the inode item at the start of a root item.
+
+const ROOT_ITEM_GENERATION_OFFSET: usize = 160;
+
+const SEARCH_HEADER_SIZE: usize = 32;
+const SEARCH_BUFFER_SIZE: usize = 3992;
+
+const INO_PATHS_BUFFER_SIZE: usize = 0x10000;
+
+#[ repr (C) ]
+struct IoctlSearchKey {
+	tree_id: u64,
+	min_objectid: u64,
+	max_objectid: u64,
+	min_offset: u64,
+	max_offset: u64,
+	min_transid: u64,
+	max_transid: u64,
+	min_type: u32,
+	max_type: u32,
+	nr_items: u32,
+	unused: u32,
+	unused1: u64,
+	unused2: u64,
+	unused3: u64,
+	unused4: u64,
+}
+
+#[ repr (C) ]
+struct IoctlSearchArgs {
+	key: IoctlSearchKey,
+	buf: [u8; SEARCH_BUFFER_SIZE],
+}
+
+#[ repr (C) ]
+struct IoctlInoPathArgs {
+	inum: u64,
+	size: u64,
+	reserved: [u64; 4],
+	fspath: u64,
+}
+
+/// An item found by a tree search, along with the generation of the leaf it
+/// was found in.
+
+struct SearchItem <'a> {
+	transid: u64,
+	objectid: u64,
+	item_type: u32,
+	data: & 'a [u8],
+}
+
+/// Gets the generation of a subvolume, which is the transaction which last
+/// changed it. Searching the trees needs the same privileges as `btrfs
+/// subvolume find-new`.
+
+pub fn get_subvolume_generation (
+	path: & Path,
+	subvolume_id: u64,
+) -> Result <u64, String> {
+
+	let mut generation: Option <u64> =
+		None;
+
+	try! (
+		search_tree (
+			path,
+			BTRFS_ROOT_TREE_OBJECTID,
+			subvolume_id,
+			subvolume_id,
+			BTRFS_ROOT_ITEM_KEY,
+			BTRFS_ROOT_ITEM_KEY,
+			0,
+			|search_item| {
+
+			if (
+				generation.is_none ()
+				&& search_item.data.len ()
+					>= ROOT_ITEM_GENERATION_OFFSET + 8
+			) {
+
+				generation =
+					Some (
+						read_u64 (
+							search_item.data,
+							ROOT_ITEM_GENERATION_OFFSET));
+
+			}
+
+		}));
+
+	generation.ok_or_else (
+		||
+
+		format! (
+			"Subvolume {} not found",
+			subvolume_id)
+
+	)
+
+}
+
+/// Finds the inodes in a subvolume which have changed since a generation, in
+/// the same way as `btrfs subvolume find-new`. This works a leaf of the tree at
+/// a time, so some inodes which haven't changed are included along with those
+/// which have. Each inode is returned along with whether its links were among
+/// the changes, which is the case for any inode which has been renamed.
+
+pub fn find_changed_inodes (
+	path: & Path,
+	subvolume_id: u64,
+	min_generation: u64,
+) -> Result <BTreeMap <u64, bool>, String> {
+
+	let mut changed_inodes: BTreeMap <u64, bool> =
+		BTreeMap::new ();
+
+	try! (
+		search_tree (
+			path,
+			subvolume_id,
+			BTRFS_FIRST_FREE_OBJECTID,
+			BTRFS_LAST_FREE_OBJECTID,
+			0,
+			255,
+			min_generation,
+			|search_item| {
+
+			if search_item.transid < min_generation {
+				return;
+			}
+
+			let links_changed =
+				search_item.item_type == BTRFS_INODE_REF_KEY
+				|| search_item.item_type == BTRFS_INODE_EXTREF_KEY;
+
+			* changed_inodes.entry (
+				search_item.objectid,
+			).or_insert (false) |= links_changed;
+
+		}));
+
+	Ok (changed_inodes)
+
+}
+
+/// Lists every path of an inode, relative to the top of the subvolume which
+/// contains the path given.
+
+pub fn get_inode_paths (
+	path: & Path,
+	inode: u64,
+) -> Result <Vec <PathBuf>, String> {
+
+	let file =
+		try! (
+			open_for_search (
+				path));
+
+	let mut buffer: Vec <u8> =
+		vec! [0; INO_PATHS_BUFFER_SIZE];
+
+	let mut ino_path_args =
+		IoctlInoPathArgs {
+			inum: inode,
+			size: INO_PATHS_BUFFER_SIZE as u64,
+			reserved: [0; 4],
+			fspath: buffer.as_mut_ptr () as u64,
+		};
+
+	try! (
+		ioctl_with_argument (
+			file.as_raw_fd (),
+			ioctl_read_write::<IoctlInoPathArgs> (
+				BTRFS_IOCTL_MAGIC,
+				35),
+			& mut ino_path_args,
+			"Inode paths"));
+
+	// a header of four 32 bit counts is followed by the offset of each path,
+	// from the start of the offsets
+
+	let bytes =
+		& buffer [..];
+
+	let num_paths =
+		read_u32 (bytes, 8) as usize;
+
+	let values_start: usize = 16;
+
+	let mut inode_paths: Vec <PathBuf> =
+		Vec::new ();
+
+	for index in 0 .. num_paths {
+
+		let path_start =
+			values_start + read_u64 (
+				bytes,
+				values_start + index * 8) as usize;
+
+		if path_start >= bytes.len () {
+			break;
+		}
+
+		let path_length =
+			bytes [path_start .. ].iter ().position (
+				|& byte|
+				byte == 0
+			).unwrap_or (
+				bytes.len () - path_start);
+
+		inode_paths.push (
+			PathBuf::from (
+				OsStr::from_bytes (
+					& bytes [path_start .. path_start + path_length])));
+
+	}
+
+	Ok (inode_paths)
+
+}
+
+/// Calls the tree search ioctl repeatedly, passing every item with a key in the
+/// range given to a closure.
+
+fn search_tree <ItemFunction: FnMut (& SearchItem)> (
+	path: & Path,
+	tree_id: u64,
+	min_objectid: u64,
+	max_objectid: u64,
+	min_type: u32,
+	max_type: u32,
+	min_transid: u64,
+	mut item_function: ItemFunction,
+) -> Result <(), String> {
+
+	let file =
+		try! (
+			open_for_search (
+				path));
+
+	let mut search_args =
+		Box::new (
+			IoctlSearchArgs {
+				key: IoctlSearchKey {
+					tree_id: tree_id,
+					min_objectid: min_objectid,
+					max_objectid: max_objectid,
+					min_offset: 0,
+					max_offset: -1_i64 as u64,
+					min_transid: min_transid,
+					max_transid: -1_i64 as u64,
+					min_type: min_type,
+					max_type: max_type,
+					nr_items: 0,
+					unused: 0,
+					unused1: 0,
+					unused2: 0,
+					unused3: 0,
+					unused4: 0,
+				},
+				buf: [0; SEARCH_BUFFER_SIZE],
+			});
+
+	loop {
+
+		search_args.key.nr_items = 4096;
+
+		try! (
+			ioctl_with_argument (
+				file.as_raw_fd (),
+				ioctl_read_write::<IoctlSearchArgs> (
+					BTRFS_IOCTL_MAGIC,
+					17),
+				& mut * search_args,
+				"Tree search"));
+
+		let num_items =
+			search_args.key.nr_items as usize;
+
+		if num_items == 0 {
+			break;
+		}
+
+		let mut position: usize = 0;
+		let mut last_key: (u64, u32, u64) = (0, 0, 0);
+
+		for _ in 0 .. num_items {
+
+			let ref buf =
+				search_args.buf;
+
+			let transid = read_u64 (buf, position);
+			let objectid = read_u64 (buf, position + 8);
+			let offset = read_u64 (buf, position + 16);
+			let item_type = read_u32 (buf, position + 24);
+			let item_length = read_u32 (buf, position + 28) as usize;
+
+			let data_start =
+				position + SEARCH_HEADER_SIZE;
+
+			item_function (
+				& SearchItem {
+					transid: transid,
+					objectid: objectid,
+					item_type: item_type,
+					data: & buf [data_start .. data_start + item_length],
+				});
+
+			last_key = (objectid, item_type, offset);
+
+			position =
+				data_start + item_length;
+
+		}
+
+		// carry on from the key after the last one found
+
+		let (objectid, item_type, offset) =
+			last_key;
+
+		search_args.key.min_objectid = objectid;
+		search_args.key.min_type = item_type;
+		search_args.key.min_offset = offset;
+
+		if offset < -1_i64 as u64 {
+
+			search_args.key.min_offset += 1;
+
+		} else if item_type < 255 {
+
+			search_args.key.min_type += 1;
+			search_args.key.min_offset = 0;
+
+		} else if objectid < max_objectid {
+
+			search_args.key.min_objectid += 1;
+			search_args.key.min_type = 0;
+			search_args.key.min_offset = 0;
+
+		} else {
+
+			break;
+
+		}
+
+	}
+
+	Ok (())
+
+}
+
+fn open_for_search (
+	path: & Path,
+) -> Result <File, String> {
+
+	File::open (
+		path,
+	).map_err (
+		|error|
+
+		format! (
+			"Error opening {}: {}",
+			path.to_string_lossy (),
+			error)
+
+	)
+
+}
+
+fn read_u64 (
+	bytes: & [u8],
+	offset: usize,
+) -> u64 {
+
+	read_little_endian (
+		bytes,
+		offset,
+		mem::size_of::<u64> ())
+
+}
+
+fn read_u32 (
+	bytes: & [u8],
+	offset: usize,
+) -> u32 {
+
+	read_little_endian (
+		bytes,
+		offset,
+		mem::size_of::<u32> ()) as u32
+
+}
+
+fn read_little_endian (
+	bytes: & [u8],
+	offset: usize,
+	size: usize,
+) -> u64 {
+
+	bytes [offset .. offset + size].iter ().rev ().fold (
+		0u64,
+		|value, & byte|
+		(value << 8) | byte as u64
+	)
+
+}
+
+// ex: noet ts=4 filetype=rust
@@ -1,15 +1,37 @@
 #![ allow (unused_parens) ]
 
+#![ allow (bare_trait_objects) ]
+#![ allow (deprecated) ]
+#![ allow (clippy::empty_line_after_doc_comments) ]
+#![ allow (clippy::large_enum_variant) ]
+#![ allow (clippy::manual_is_multiple_of) ]
+#![ allow (clippy::map_clone) ]
+#![ allow (clippy::needless_borrow) ]
+#![ allow (clippy::needless_borrowed_reference) ]
+#![ allow (clippy::needless_borrows_for_generic_args) ]
+#![ allow (clippy::redundant_closure) ]
+#![ allow (clippy::redundant_field_names) ]
+#![ allow (clippy::redundant_static_lifetimes) ]
+#![ allow (clippy::too_many_arguments) ]
+#![ allow (clippy::toplevel_ref_arg) ]
+
 extern crate clap;
 
 extern crate output;
 
-extern crate btrfs;
+#[ macro_use ]
+extern crate serde_derive;
+
+extern crate blake3;
 extern crate flate2;
+extern crate glob;
+extern crate libc;
+extern crate rusqlite;
 extern crate rustc_serialize;
 extern crate serde_json;
 extern crate sha2;
 extern crate time;
+extern crate xxhash_rust;
 
 #[ doc (hidden) ]
 #[ macro_use ]
@@ -18,6 +40,8 @@ mod misc;
 mod commands;
 mod arguments;
 mod database;
+mod hash_algorithm;
+mod linux;
 mod operations;
 mod types;
 
@@ -60,11 +84,29 @@ fn main_real (
 				arguments,
 			),
 
+		Command::Watch =>
+			watch_command (
+				& output,
+				arguments,
+			),
+
 		Command::PrintExtents =>
 			print_extents_command (
 				& output,
 				arguments,
-			)
+			),
+
+		Command::ConvertDatabase =>
+			convert_database_command (
+				& output,
+				arguments,
+			),
+
+		Command::CheckDatabase =>
+			check_database_command (
+				& output,
+				arguments,
+			),
 
 	};
 
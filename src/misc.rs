@@ -1,7 +1,9 @@
 use std::error::Error;
 use std::io;
+use std::io::Read;
 
 use rustc_serialize::hex::FromHex;
+use rustc_serialize::hex::ToHex;
 
 use types::*;
 
@@ -16,30 +18,116 @@ pub fn io_result <Type> (
 
 }
 
+/// Reads until the buffer is full or the end of the file is reached, and
+/// returns the number of bytes read.
+
+pub fn read_fully (
+	source: & mut Read,
+	buffer: & mut [u8],
+) -> Result <usize, io::Error> {
+
+	let mut bytes_read: usize = 0;
+
+	while bytes_read < buffer.len () {
+
+		match source.read (
+			& mut buffer [bytes_read .. ]) {
+
+			Ok (0) =>
+				break,
+
+			Ok (bytes) =>
+				bytes_read += bytes,
+
+			Err (ref error)
+			if error.kind () == io::ErrorKind::Interrupted =>
+				continue,
+
+			Err (error) =>
+				return Err (error),
+
+		}
+
+	}
+
+	Ok (bytes_read)
+
+}
+
+/// Formats a size for humans, using the same binary units accepted by
+/// `parse_size`.
+
+pub fn format_size (
+	size: u64,
+) -> String {
+
+	let units: [& 'static str; 4] =
+		[ "KiB", "MiB", "GiB", "TiB" ];
+
+	if size < 1024 {
+		return format! ("{}B", size);
+	}
+
+	let mut scaled_size =
+		size as f64 / 1024.0;
+
+	let mut unit_index: usize = 0;
+
+	while scaled_size >= 1024.0 && unit_index + 1 < units.len () {
+
+		scaled_size /= 1024.0;
+		unit_index += 1;
+
+	}
+
+	format! (
+		"{:.1}{}",
+		scaled_size,
+		units [unit_index])
+
+}
+
+/// Decodes a hash written as hex, where a missing hash stands for the zero
+/// hash. Anything which isn't exactly the right length is an error.
+
 pub fn decode_hash (
 	hash_option: & Option <String>,
-) -> Hash {
+) -> Result <Hash, String> {
 
 	match * hash_option {
 
 		None =>
-			ZERO_HASH,
+			Ok (ZERO_HASH),
 
 		Some (ref hash_string) => {
 
-			let hash =
-				hash_string.from_hex ().unwrap ();
+			let hash_bytes =
+				try! (
+					hash_string.from_hex ().map_err (
+						|_|
+
+						format! (
+							"Invalid hash: {}",
+							hash_string)
+
+					));
+
+			if hash_bytes.len () != HASH_SIZE {
 
-			[
-				hash [ 0], hash [ 1], hash [ 2], hash [ 3],
-				hash [ 4], hash [ 5], hash [ 6], hash [ 7],
-				hash [ 8], hash [ 9], hash [10], hash [11],
-				hash [12], hash [13], hash [14], hash [15],
-				hash [16], hash [17], hash [18], hash [19],
-				hash [20], hash [21], hash [22], hash [23],
-				hash [24], hash [25], hash [26], hash [27],
-				hash [28], hash [29], hash [30], hash [21],
-			]
+				return Err (
+					format! (
+						"Invalid hash length: {}",
+						hash_string));
+
+			}
+
+			let mut hash =
+				ZERO_HASH;
+
+			hash.copy_from_slice (
+				& hash_bytes);
+
+			Ok (hash)
 
 		},
 
@@ -47,4 +135,132 @@ pub fn decode_hash (
 
 }
 
+/// Formats a UUID the usual way, as hex digits in groups of eight, four, four,
+/// four and twelve.
+
+pub fn format_uuid (
+	uuid: & Uuid,
+) -> String {
+
+	let uuid_hex =
+		uuid.to_hex ();
+
+	format! (
+		"{}-{}-{}-{}-{}",
+		& uuid_hex [0 .. 8],
+		& uuid_hex [8 .. 12],
+		& uuid_hex [12 .. 16],
+		& uuid_hex [16 .. 20],
+		& uuid_hex [20 .. 32])
+
+}
+
+/// Decodes a UUID formatted by `format_uuid`.
+
+pub fn decode_uuid (
+	uuid_string: & str,
+) -> Result <Uuid, String> {
+
+	let uuid_bytes =
+		try! (
+			uuid_string.replace (
+				"-", "",
+			).from_hex ().map_err (
+				|_|
+
+				format! (
+					"Invalid UUID: {}",
+					uuid_string)
+
+			));
+
+	if uuid_bytes.len () != UUID_SIZE {
+
+		return Err (
+			format! (
+				"Invalid UUID length: {}",
+				uuid_string));
+
+	}
+
+	let mut uuid =
+		ZERO_UUID;
+
+	uuid.copy_from_slice (
+		& uuid_bytes);
+
+	Ok (uuid)
+
+}
+
+#[ cfg (test) ]
+mod tests {
+
+	use rustc_serialize::hex::ToHex;
+
+	use super::*;
+
+	#[ test ]
+	fn decode_hash_round_trip () {
+
+		let mut hash =
+			ZERO_HASH;
+
+		for (index, byte) in hash.iter_mut ().enumerate () {
+			* byte = index as u8 * 7 + 1;
+		}
+
+		assert_eq! (
+			decode_hash (& Some (hash.to_hex ())),
+			Ok (hash));
+
+		// the last byte used to be copied from the 22nd
+
+		assert! (
+			hash [HASH_SIZE - 1] != hash [21]);
+
+	}
+
+	#[ test ]
+	fn decode_hash_missing () {
+
+		assert_eq! (
+			decode_hash (& None),
+			Ok (ZERO_HASH));
+
+	}
+
+	#[ test ]
+	fn decode_hash_wrong_length () {
+
+		let hash =
+			[0x12u8; HASH_SIZE];
+
+		assert! (
+			decode_hash (
+				& Some (hash [0 .. HASH_SIZE - 1].to_hex ()),
+			).is_err ());
+
+		assert! (
+			decode_hash (
+				& Some (format! ("{}00", hash.to_hex ())),
+			).is_err ());
+
+	}
+
+	#[ test ]
+	fn decode_hash_bad_hex () {
+
+		let hash_string =
+			"zz".repeat (HASH_SIZE);
+
+		assert! (
+			decode_hash (
+				& Some (hash_string),
+			).is_err ());
+
+	}
+
+}
+
 // ex: noet ts=4 filetype=rust
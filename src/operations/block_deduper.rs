@@ -0,0 +1,575 @@
+use std::cmp;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fs::File;
+
+use output::Output;
+
+use time;
+
+use arguments::*;
+use database::*;
+use linux;
+use linux::DedupeRangeResult;
+use misc::*;
+use operations::*;
+use types::*;
+
+/// The kernel won't deduplicate more than this in a single call.
+
+const MAX_DEDUPE_LENGTH: u64 = 0x1000000;
+
+/// A range of one file which is identical to a range of another, found by
+/// comparing chunk tables.
+
+#[ derive (Clone, Copy, Debug, Eq, PartialEq) ]
+pub struct BlockRange {
+	pub source_index: usize,
+	pub source_offset: u64,
+	pub target_offset: u64,
+	pub length: u64,
+}
+
+/// Matching ranges for each file which needs block deduplication, keyed by the
+/// index of the target file in the database.
+
+pub type BlockDedupeMap = BTreeMap <usize, Vec <BlockRange>>;
+
+pub struct BlockDeduper {
+	num_updated: u64,
+	num_remaining: u64,
+	num_errors: u64,
+	size_deduped: u64,
+}
+
+impl BlockDeduper {
+
+	pub fn new (
+	) -> BlockDeduper {
+
+		BlockDeduper {
+			num_updated: 0,
+			num_remaining: 0,
+			num_errors: 0,
+			size_deduped: 0,
+		}
+
+	}
+
+	pub fn dedupe_ranges (
+		& mut self,
+		output: & Output,
+		arguments: & Arguments,
+		file_database: & mut FileDatabase,
+		block_dedupe_map: & mut BlockDedupeMap,
+	) -> Result <(), String> {
+
+		let mut num_remaining = 0;
+		let mut num_updated = 0;
+		let mut num_errors = 0;
+
+		let mut size_processed: u64 = 0;
+
+		let target_indices: Vec <usize> =
+			block_dedupe_map.keys ().cloned ().collect ();
+
+		for target_index in target_indices {
+
+			let target_size: u64 =
+				block_dedupe_map [& target_index].iter ().map (
+					|block_range|
+					block_range.length
+				).sum ();
+
+			if (
+				num_updated > 0
+				&& size_processed + target_size > arguments.dedupe_batch_size
+			) {
+
+				num_remaining += 1;
+
+				continue;
+
+			}
+
+			let block_ranges =
+				block_dedupe_map.remove (
+					& target_index,
+				).unwrap ();
+
+			let deduplicate_time =
+				time::get_time ();
+
+			let success =
+				match dedupe_target_ranges (
+					output,
+					file_database,
+					target_index,
+					& block_ranges,
+				) {
+
+				Ok (size_deduped) => {
+
+					self.size_deduped += size_deduped;
+
+					num_updated += 1;
+
+					true
+
+				},
+
+				Err (error) => {
+
+					output.message_format (
+						format_args! (
+							"Error deduplicating ranges of {}: {}",
+							file_database [target_index].path
+								.to_string_lossy (),
+							error));
+
+					num_errors += 1;
+
+					false
+
+				},
+
+			};
+
+			// some ranges may have been done even if others failed, but only
+			// a file which was done completely is left alone next time
+
+			let ref mut file_data =
+				file_database [target_index];
+
+			file_data.extent_hash = ZERO_HASH;
+			file_data.extent_hash_time = 0;
+
+			if success {
+
+				file_data.chunk_table.as_mut ().unwrap ().deduplicate_time =
+					deduplicate_time.sec;
+
+			}
+
+			size_processed += target_size;
+
+		}
+
+		self.num_remaining = num_remaining;
+		self.num_updated += num_updated;
+		self.num_errors += num_errors;
+
+		output.clear_status ();
+
+		Ok (())
+
+	}
+
+	pub fn num_updated (& self) -> u64 {
+		self.num_updated
+	}
+
+	pub fn num_remaining (& self) -> u64 {
+		self.num_remaining
+	}
+
+	pub fn num_errors (& self) -> u64 {
+		self.num_errors
+	}
+
+	pub fn size_deduped (& self) -> u64 {
+		self.size_deduped
+	}
+
+}
+
+/// Maps the hash of every chunk on each filesystem to the first file and offset
+/// it was found at there.
+
+pub type ChunkIndex = HashMap <(FilesystemId, Hash), (usize, u64)>;
+
+/// Finds ranges which are shared between files with chunk tables. Each chunk
+/// is matched against the first chunk with the same hash in another file on the
+/// same filesystem, and adjacent matches against the same file are merged into
+/// a single range. Ranges are then trimmed to filesystem block boundaries, and
+/// dropped if the source and target offsets aren't aligned in the same way,
+/// since shifted data can only be shared if it is shifted by a whole number of
+/// blocks.
+///
+/// Files which are identical to an earlier file are left out entirely, since
+/// whole file deduplication takes care of them, as are files whose filesystem
+/// can't be identified.
+
+pub fn build_block_dedupe_map (
+	root_paths: & [PathRef],
+	file_database: & FileDatabase,
+) -> BlockDedupeMap {
+
+	let root_paths_set: HashSet <PathRef> =
+		root_paths.iter ().cloned ().collect ();
+
+	let root_devices =
+		root_devices (
+			root_paths);
+
+	let mut content_hashes_seen: HashSet <(FilesystemId, Hash)> =
+		HashSet::new ();
+
+	let candidate_indices: Vec <usize> =
+		file_database.iter ().enumerate ().filter (
+			|& (_file_index, file_data)|
+
+			file_data.chunk_table.is_some ()
+
+			&& file_data.root_path.is_some ()
+
+			&& root_paths_set.contains (
+				file_data.root_path.as_ref ().unwrap ())
+
+			&& file_data.filesystem_id (
+				& root_devices,
+			).is_some ()
+
+		).filter (
+			|& (_file_index, file_data)|
+
+			file_data.content_hash == ZERO_HASH
+
+			|| content_hashes_seen.insert (
+				(
+					file_data.filesystem_id (& root_devices).unwrap (),
+					file_data.content_hash,
+				))
+
+		).map (
+			|(file_index, _file_data)|
+			file_index
+		).collect ();
+
+	let chunk_index =
+		build_chunk_index (
+			file_database,
+			& root_devices,
+			& candidate_indices);
+
+	// find matching ranges for files which haven't been done yet
+
+	let mut block_dedupe_map: BlockDedupeMap =
+		BTreeMap::new ();
+
+	for & file_index in candidate_indices.iter () {
+
+		let ref file_data =
+			file_database [file_index];
+
+		let chunk_table =
+			file_data.chunk_table.as_ref ().unwrap ();
+
+		let filesystem_id =
+			file_data.filesystem_id (
+				& root_devices,
+			).unwrap ();
+
+		// files in read only subvolumes can still be used as sources
+
+		if chunk_table.deduplicate_time != 0 || file_data.is_read_only () {
+			continue;
+		}
+
+		let mut block_ranges: Vec <BlockRange> =
+			Vec::new ();
+
+		for chunk in chunk_table.chunks.iter () {
+
+			let (source_index, source_offset) =
+				chunk_index [& (filesystem_id, chunk.hash)];
+
+			if source_index == file_index {
+				continue;
+			}
+
+			if let Some (last_range) = block_ranges.last_mut () {
+
+				if (
+					last_range.source_index == source_index
+					&& last_range.source_offset + last_range.length
+						== source_offset
+					&& last_range.target_offset + last_range.length
+						== chunk.offset
+				) {
+
+					last_range.length += chunk.length;
+
+					continue;
+
+				}
+
+			}
+
+			block_ranges.push (
+				BlockRange {
+					source_index: source_index,
+					source_offset: source_offset,
+					target_offset: chunk.offset,
+					length: chunk.length,
+				});
+
+		}
+
+		let block_ranges: Vec <BlockRange> =
+			block_ranges.into_iter ().filter_map (
+				align_block_range,
+			).collect ();
+
+		if ! block_ranges.is_empty () {
+
+			block_dedupe_map.insert (
+				file_index,
+				block_ranges);
+
+		}
+
+	}
+
+	block_dedupe_map
+
+}
+
+/// Returns the total number of bytes covered by the ranges in a map.
+
+pub fn block_dedupe_map_size (
+	block_dedupe_map: & BlockDedupeMap,
+) -> u64 {
+
+	block_dedupe_map.values ().flat_map (
+		|block_ranges|
+		block_ranges.iter ()
+	).map (
+		|block_range|
+		block_range.length
+	).sum ()
+
+}
+
+fn build_chunk_index (
+	file_database: & FileDatabase,
+	root_devices: & HashMap <PathRef, u64>,
+	file_indices: & [usize],
+) -> ChunkIndex {
+
+	let mut chunk_index: ChunkIndex =
+		HashMap::new ();
+
+	for & file_index in file_indices.iter () {
+
+		let ref file_data =
+			file_database [file_index];
+
+		let chunk_table =
+			file_data.chunk_table.as_ref ().unwrap ();
+
+		let filesystem_id =
+			file_data.filesystem_id (
+				root_devices,
+			).unwrap ();
+
+		for chunk in chunk_table.chunks.iter () {
+
+			chunk_index.entry (
+				(filesystem_id, chunk.hash),
+			).or_insert (
+				(file_index, chunk.offset),
+			);
+
+		}
+
+	}
+
+	chunk_index
+
+}
+
+fn align_block_range (
+	block_range: BlockRange,
+) -> Option <BlockRange> {
+
+	if (
+		block_range.source_offset % CHUNK_ALIGNMENT
+			!= block_range.target_offset % CHUNK_ALIGNMENT
+	) {
+		return None;
+	}
+
+	let start =
+		block_range.target_offset.div_ceil (CHUNK_ALIGNMENT)
+			* CHUNK_ALIGNMENT;
+
+	let end =
+		(block_range.target_offset + block_range.length)
+			/ CHUNK_ALIGNMENT * CHUNK_ALIGNMENT;
+
+	if end <= start {
+		return None;
+	}
+
+	Some (BlockRange {
+		source_index: block_range.source_index,
+		source_offset:
+			block_range.source_offset + start - block_range.target_offset,
+		target_offset: start,
+		length: end - start,
+	})
+
+}
+
+fn dedupe_target_ranges (
+	output: & Output,
+	file_database: & FileDatabase,
+	target_index: usize,
+	block_ranges: & [BlockRange],
+) -> Result <u64, String> {
+
+	let target_path =
+		file_database [target_index].path.to_path ();
+
+	// the target is only opened for reading, since closing a file opened for
+	// writing is reported as a change to anything watching it
+
+	let target_file =
+		io_result (
+			File::open (
+				& target_path),
+		) ?;
+
+	let mut size_deduped: u64 = 0;
+
+	for block_range in block_ranges.iter () {
+
+		let source_path =
+			file_database [block_range.source_index].path.to_path ();
+
+		output.status_format (
+			format_args! (
+				"Deduplicate range: {} -> {} ({} at {})",
+				target_path.to_string_lossy (),
+				source_path.to_string_lossy (),
+				format_size (block_range.length),
+				block_range.target_offset));
+
+		let source_file =
+			io_result (
+				File::open (
+					& source_path),
+			) ?;
+
+		let mut position: u64 = 0;
+
+		while position < block_range.length {
+
+			let length =
+				cmp::min (
+					block_range.length - position,
+					MAX_DEDUPE_LENGTH);
+
+			match linux::deduplicate_range (
+				& source_file,
+				block_range.source_offset + position,
+				length,
+				& target_file,
+				block_range.target_offset + position,
+			) ? {
+
+				DedupeRangeResult::Same (bytes_deduped) =>
+					size_deduped += bytes_deduped,
+
+				DedupeRangeResult::Differs =>
+					return Err (
+						format! (
+							"Contents differ from {} at offset {}",
+							source_path.to_string_lossy (),
+							block_range.target_offset + position)),
+
+			}
+
+			position += length;
+
+		}
+
+	}
+
+	Ok (size_deduped)
+
+}
+
+#[ cfg (test) ]
+mod tests {
+
+	use super::*;
+
+	fn block_range (
+		source_offset: u64,
+		target_offset: u64,
+		length: u64,
+	) -> BlockRange {
+
+		BlockRange {
+			source_index: 1,
+			source_offset: source_offset,
+			target_offset: target_offset,
+			length: length,
+		}
+
+	}
+
+	#[ test ]
+	fn aligned_range_is_unchanged () {
+
+		assert_eq! (
+			align_block_range (
+				block_range (0x3000, 0x1000, 0x2000)),
+			Some (block_range (0x3000, 0x1000, 0x2000)));
+
+	}
+
+	#[ test ]
+	fn unaligned_range_is_trimmed () {
+
+		// both ends move inwards to the next block boundary, and the source
+		// moves with the target
+
+		assert_eq! (
+			align_block_range (
+				block_range (0x1064, 0x64, 0x2710)),
+			Some (block_range (0x2000, 0x1000, 0x1000)));
+
+	}
+
+	#[ test ]
+	fn misaligned_source_is_rejected () {
+
+		assert_eq! (
+			align_block_range (
+				block_range (0x1000, 0x1200, 0x4000)),
+			None);
+
+	}
+
+	#[ test ]
+	fn range_within_one_block_is_rejected () {
+
+		assert_eq! (
+			align_block_range (
+				block_range (0x1100, 0x100, 0xf00)),
+			None);
+
+		assert_eq! (
+			align_block_range (
+				block_range (0x1100, 0x100, 0x1000)),
+			None);
+
+	}
+
+}
+
+// ex: noet ts=4 filetype=rust
@@ -0,0 +1,385 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::path::Path;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use output::Output;
+
+use database::*;
+use hash_algorithm::*;
+use misc::*;
+use operations::*;
+use types::*;
+
+/// Offsets and lengths passed to the range deduplication ioctl must be
+/// multiples of this, so chunk sizes must be as well. Content defined chunks
+/// can end anywhere, so matching ranges are trimmed to fit.
+
+pub const CHUNK_ALIGNMENT: u64 = 0x1000;
+
+pub struct ChunkHasher <'a> {
+
+	root_paths_set: HashSet <PathRef>,
+
+	hash_algorithm: HashAlgorithm,
+	minimum_file_size: u64,
+	chunking_method: ChunkingMethod,
+	chunk_size: u64,
+	batch_size: u64,
+
+	file_database: & 'a mut FileDatabase,
+
+	num_ignored: u64,
+	num_fresh: u64,
+	num_updated: u64,
+	num_remaining: u64,
+	num_errors: u64,
+
+}
+
+impl <'a> ChunkHasher <'a> {
+
+	pub fn new (
+		root_paths: & 'a [PathRef],
+		hash_algorithm: HashAlgorithm,
+		minimum_file_size: u64,
+		chunking_method: ChunkingMethod,
+		chunk_size: u64,
+		batch_size: u64,
+		file_database: & 'a mut FileDatabase,
+	) -> ChunkHasher <'a> {
+
+		let root_paths_set: HashSet <Rc <PathBuf>> =
+			root_paths.iter ().map (
+				|root_path|
+				root_path.clone ()
+			).collect ();
+
+		ChunkHasher {
+
+			root_paths_set: root_paths_set,
+
+			hash_algorithm: hash_algorithm,
+			minimum_file_size: minimum_file_size,
+			chunking_method: chunking_method,
+			chunk_size: chunk_size,
+			batch_size: batch_size,
+
+			file_database: file_database,
+
+			num_ignored: 0,
+			num_fresh: 0,
+			num_updated: 0,
+			num_remaining: 0,
+			num_errors: 0,
+
+		}
+
+	}
+
+	pub fn calculate_chunk_tables (
+		& mut self,
+		output: & Output,
+	) {
+
+		let mut num_ignored = 0;
+		let mut num_fresh = 0;
+		let mut num_remaining = 0;
+		let mut num_updated = 0;
+		let mut num_errors = 0;
+
+		let mut size_hashed: u64 = 0;
+
+		for ref mut file_data
+		in self.file_database.iter_mut () {
+
+			if (
+
+				(
+
+					file_data.root_path.is_none ()
+
+				) || (
+
+					file_data.root_path.is_some ()
+
+					&& ! self.root_paths_set.contains (
+						& file_data.root_path.as_ref ().unwrap ().clone ())
+
+				) || (
+
+					file_data.size < self.minimum_file_size
+
+				)
+
+			) {
+
+				num_ignored += 1;
+
+				continue;
+
+			} else if (
+
+				file_data.chunk_table.is_some ()
+
+				&& file_data.chunk_table.as_ref ().unwrap ().chunking_method
+					== self.chunking_method
+
+				&& file_data.chunk_table.as_ref ().unwrap ().chunk_size
+					== self.chunk_size
+
+			) {
+
+				num_fresh += 1;
+
+				continue;
+
+			} else if (
+				num_updated > 0
+				&& size_hashed + file_data.size > self.batch_size
+			) {
+
+				num_remaining += 1;
+
+				continue;
+
+			} else {
+
+				output.status_format (
+					format_args! (
+						"Chunk hash: {}",
+						file_data.path.to_string_lossy ()));
+
+				match calculate_chunks_for_file (
+					& file_data.path.to_path (),
+					self.chunking_method,
+					self.chunk_size,
+					self.hash_algorithm,
+				) {
+
+					Ok (chunks) => {
+
+						file_data.chunk_table =
+							Some (ChunkTable {
+								chunking_method: self.chunking_method,
+								chunk_size: self.chunk_size,
+								deduplicate_time: 0,
+								chunks: chunks,
+							});
+
+						num_updated += 1;
+
+					},
+
+					Err (_) => {
+
+						file_data.chunk_table = None;
+
+						num_errors += 1;
+
+					},
+
+				}
+
+				size_hashed += file_data.size;
+
+			}
+
+		}
+
+		self.num_ignored = num_ignored;
+		self.num_fresh = num_fresh;
+		self.num_remaining = num_remaining;
+		self.num_updated += num_updated;
+		self.num_errors += num_errors;
+
+		output.clear_status ();
+
+	}
+
+	pub fn file_database (& self) -> & FileDatabase {
+		self.file_database
+	}
+
+	pub fn num_fresh (& self) -> u64 {
+		self.num_fresh
+	}
+
+	pub fn num_remaining (& self) -> u64 {
+		self.num_remaining
+	}
+
+	pub fn num_updated (& self) -> u64 {
+		self.num_updated
+	}
+
+	pub fn num_errors (& self) -> u64 {
+		self.num_errors
+	}
+
+}
+
+pub fn calculate_chunks_for_file (
+	path: & Path,
+	chunking_method: ChunkingMethod,
+	chunk_size: u64,
+	hash_algorithm: HashAlgorithm,
+) -> Result <Vec <Chunk>, String> {
+
+	let mut file =
+		io_result (
+			File::open (
+				path),
+		) ?;
+
+	match chunking_method {
+
+		ChunkingMethod::Fixed =>
+			calculate_fixed_chunks (
+				& mut file,
+				chunk_size,
+				hash_algorithm),
+
+		ChunkingMethod::ContentDefined =>
+			calculate_content_defined_chunks (
+				& mut file,
+				chunk_size,
+				hash_algorithm),
+
+	}
+
+}
+
+/// Splits a file into fixed size chunks and hashes each one. The last chunk is
+/// shorter unless the file size is an exact multiple of the chunk size.
+
+fn calculate_fixed_chunks (
+	file: & mut File,
+	chunk_size: u64,
+	hash_algorithm: HashAlgorithm,
+) -> Result <Vec <Chunk>, String> {
+
+	let mut buffer: Vec <u8> =
+		vec! [0u8; chunk_size as usize];
+
+	let mut chunks: Vec <Chunk> =
+		Vec::new ();
+
+	let mut offset: u64 = 0;
+
+	loop {
+
+		let bytes_read =
+			io_result (
+				read_fully (
+					file,
+					& mut buffer),
+			) ?;
+
+		if bytes_read == 0 {
+			break;
+		}
+
+		chunks.push (
+			hash_chunk (
+				offset,
+				& buffer [0 .. bytes_read],
+				hash_algorithm));
+
+		offset += bytes_read as u64;
+
+	}
+
+	Ok (chunks)
+
+}
+
+/// Splits a file into content defined chunks and hashes each one. The buffer
+/// is kept topped up to the maximum chunk size, so that every cut point is
+/// found from the same amount of data, regardless of where reads end.
+
+fn calculate_content_defined_chunks (
+	file: & mut File,
+	average_chunk_size: u64,
+	hash_algorithm: HashAlgorithm,
+) -> Result <Vec <Chunk>, String> {
+
+	let chunker =
+		ContentDefinedChunker::new (
+			average_chunk_size);
+
+	let mut buffer: Vec <u8> =
+		Vec::with_capacity (
+			chunker.maximum_size ());
+
+	let mut chunks: Vec <Chunk> =
+		Vec::new ();
+
+	let mut offset: u64 = 0;
+
+	loop {
+
+		let buffer_used =
+			buffer.len ();
+
+		buffer.resize (
+			chunker.maximum_size (),
+			0);
+
+		let bytes_read =
+			io_result (
+				read_fully (
+					file,
+					& mut buffer [buffer_used .. ]),
+			) ?;
+
+		buffer.truncate (
+			buffer_used + bytes_read);
+
+		if buffer.is_empty () {
+			break;
+		}
+
+		let chunk_length =
+			chunker.cut_point (
+				& buffer);
+
+		chunks.push (
+			hash_chunk (
+				offset,
+				& buffer [0 .. chunk_length],
+				hash_algorithm));
+
+		buffer.drain (
+			0 .. chunk_length);
+
+		offset += chunk_length as u64;
+
+	}
+
+	Ok (chunks)
+
+}
+
+fn hash_chunk (
+	offset: u64,
+	data: & [u8],
+	hash_algorithm: HashAlgorithm,
+) -> Chunk {
+
+	let mut hasher =
+		hash_algorithm.new_hash_function ();
+
+	hasher.input (
+		data);
+
+	Chunk {
+		offset: offset,
+		length: data.len () as u64,
+		hash: hasher.result (),
+	}
+
+}
+
+// ex: noet ts=4 filetype=rust
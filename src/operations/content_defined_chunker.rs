@@ -0,0 +1,337 @@
+use std::cmp;
+
+/// Seed for the gear table. Changing this, or the way the table is generated,
+/// moves every chunk boundary, so existing chunk tables would no longer match
+/// new ones.
+
+const GEAR_SEED: u64 = 0x62747266732d6464;
+
+/// Finds chunk boundaries with the FastCDC algorithm. A gear hash is rolled
+/// over the data, and a chunk ends where its top bits are all zero. Between
+/// the minimum size and the average size a stricter mask is used, and after it
+/// a looser one, which keeps chunk sizes close to the average.
+
+pub struct ContentDefinedChunker {
+	gear_table: [u64; 256],
+	minimum_size: usize,
+	average_size: usize,
+	maximum_size: usize,
+	strict_mask: u64,
+	loose_mask: u64,
+}
+
+impl ContentDefinedChunker {
+
+	pub fn new (
+		average_size: u64,
+	) -> ContentDefinedChunker {
+
+		let average_bits =
+			63 - average_size.leading_zeros () as u64;
+
+		ContentDefinedChunker {
+			gear_table: gear_table (),
+			minimum_size: (average_size / 4) as usize,
+			average_size: average_size as usize,
+			maximum_size: (average_size * 8) as usize,
+			strict_mask: high_bits_mask (average_bits + 1),
+			loose_mask: high_bits_mask (average_bits - 1),
+		}
+
+	}
+
+	pub fn maximum_size (
+		& self,
+	) -> usize {
+		self.maximum_size
+	}
+
+	/// Returns the length of the chunk at the start of the data. The data
+	/// should contain at least the maximum chunk size, unless it is the end of
+	/// the file.
+
+	pub fn cut_point (
+		& self,
+		data: & [u8],
+	) -> usize {
+
+		if data.len () <= self.minimum_size {
+			return data.len ();
+		}
+
+		let limit =
+			cmp::min (
+				data.len (),
+				self.maximum_size);
+
+		let normal_limit =
+			cmp::min (
+				limit,
+				self.average_size);
+
+		let mut fingerprint: u64 = 0;
+
+		for (index, & byte)
+		in data.iter ().enumerate ().take (normal_limit).skip (self.minimum_size) {
+
+			fingerprint =
+				(fingerprint << 1).wrapping_add (
+					self.gear_table [byte as usize]);
+
+			if fingerprint & self.strict_mask == 0 {
+				return index + 1;
+			}
+
+		}
+
+		for (index, & byte)
+		in data.iter ().enumerate ().take (limit).skip (normal_limit) {
+
+			fingerprint =
+				(fingerprint << 1).wrapping_add (
+					self.gear_table [byte as usize]);
+
+			if fingerprint & self.loose_mask == 0 {
+				return index + 1;
+			}
+
+		}
+
+		limit
+
+	}
+
+}
+
+fn high_bits_mask (
+	num_bits: u64,
+) -> u64 {
+
+	! 0u64 << (64 - num_bits)
+
+}
+
+/// Generates the gear table with splitmix64, so that it is the same on every
+/// run without having to list it here.
+
+fn gear_table (
+) -> [u64; 256] {
+
+	let mut gear_table =
+		[0u64; 256];
+
+	let mut state =
+		GEAR_SEED;
+
+	for entry in gear_table.iter_mut () {
+
+		state =
+			state.wrapping_add (0x9e3779b97f4a7c15);
+
+		let mut value =
+			state;
+
+		value =
+			(value ^ (value >> 30)).wrapping_mul (0xbf58476d1ce4e5b9);
+
+		value =
+			(value ^ (value >> 27)).wrapping_mul (0x94d049bb133111eb);
+
+		* entry =
+			value ^ (value >> 31);
+
+	}
+
+	gear_table
+
+}
+
+#[ cfg (test) ]
+mod tests {
+
+	use super::*;
+
+	const AVERAGE_SIZE: u64 = 0x1000;
+
+	/// Pseudo-random data from xorshift, so that the tests are repeatable.
+
+	fn random_data (
+		length: usize,
+		seed: u64,
+	) -> Vec <u8> {
+
+		let mut state =
+			seed;
+
+		(0 .. length).map (
+			|_| {
+
+			state ^= state << 13;
+			state ^= state >> 7;
+			state ^= state << 17;
+
+			(state >> 56) as u8
+
+		}).collect ()
+
+	}
+
+	/// Splits all of the data into chunks, returning the offset at which each
+	/// chunk ends.
+
+	fn split_data (
+		chunker: & ContentDefinedChunker,
+		data: & [u8],
+	) -> Vec <usize> {
+
+		let mut chunk_ends =
+			Vec::new ();
+
+		let mut position: usize = 0;
+
+		while position < data.len () {
+
+			position +=
+				chunker.cut_point (
+					& data [position ..]);
+
+			chunk_ends.push (
+				position);
+
+		}
+
+		chunk_ends
+
+	}
+
+	#[ test ]
+	fn sizes () {
+
+		let chunker =
+			ContentDefinedChunker::new (AVERAGE_SIZE);
+
+		assert_eq! (chunker.minimum_size, 0x400);
+		assert_eq! (chunker.average_size, 0x1000);
+		assert_eq! (chunker.maximum_size (), 0x8000);
+
+		// the strict mask has one more bit than the average size, and the
+		// loose mask one fewer
+
+		assert_eq! (chunker.strict_mask.count_ones (), 13);
+		assert_eq! (chunker.loose_mask.count_ones (), 11);
+		assert_eq! (chunker.strict_mask.leading_zeros (), 0);
+
+	}
+
+	#[ test ]
+	fn short_data_is_one_chunk () {
+
+		let chunker =
+			ContentDefinedChunker::new (AVERAGE_SIZE);
+
+		let data =
+			random_data (0x400, 1);
+
+		assert_eq! (chunker.cut_point (& data), 0x400);
+		assert_eq! (chunker.cut_point (& data [0 .. 10]), 10);
+		assert_eq! (chunker.cut_point (& []), 0);
+
+	}
+
+	#[ test ]
+	fn chunk_lengths_within_limits () {
+
+		let chunker =
+			ContentDefinedChunker::new (AVERAGE_SIZE);
+
+		let data =
+			random_data (0x100000, 2);
+
+		let chunk_ends =
+			split_data (& chunker, & data);
+
+		assert_eq! (
+			* chunk_ends.last ().unwrap (),
+			data.len ());
+
+		let chunk_lengths: Vec <usize> =
+			chunk_ends.iter ().scan (
+				0,
+				|last_end, & chunk_end| {
+
+				let chunk_length =
+					chunk_end - * last_end;
+
+				* last_end = chunk_end;
+
+				Some (chunk_length)
+
+			}).collect ();
+
+		// only the last chunk may be shorter than the minimum
+
+		for & chunk_length
+		in chunk_lengths [0 .. chunk_lengths.len () - 1].iter () {
+
+			assert! (chunk_length > 0x400);
+			assert! (chunk_length <= 0x8000);
+
+		}
+
+		let average_length =
+			data.len () / chunk_lengths.len ();
+
+		assert! (average_length > AVERAGE_SIZE as usize / 2);
+		assert! (average_length < AVERAGE_SIZE as usize * 2);
+
+	}
+
+	#[ test ]
+	fn boundaries_follow_content () {
+
+		let chunker =
+			ContentDefinedChunker::new (AVERAGE_SIZE);
+
+		let data =
+			random_data (0x40000, 3);
+
+		// insert some bytes at the start, which should only move the first
+		// few boundaries
+
+		let mut shifted_data =
+			random_data (100, 4);
+
+		shifted_data.extend_from_slice (
+			& data);
+
+		let chunk_ends =
+			split_data (& chunker, & data);
+
+		let shifted_chunk_ends: Vec <usize> =
+			split_data (& chunker, & shifted_data).into_iter ().filter (
+				|& chunk_end|
+				chunk_end > 100
+			).map (
+				|chunk_end|
+				chunk_end - 100
+			).collect ();
+
+		let tail_start =
+			chunk_ends.len () - 20;
+
+		assert_eq! (
+			& shifted_chunk_ends [
+				shifted_chunk_ends.len () - 20 .. ],
+			& chunk_ends [tail_start .. ]);
+
+		// the same data always gives the same boundaries
+
+		assert_eq! (
+			split_data (& chunker, & data),
+			chunk_ends);
+
+	}
+
+}
+
+// ex: noet ts=4 filetype=rust
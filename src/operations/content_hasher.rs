@@ -1,28 +1,82 @@
+use std::cmp;
+use std::collections::HashMap;
 use std::collections::HashSet;
 use std::fs::File;
 use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::path::Path;
 
 use output::Output;
 
-use sha2::Digest;
-use sha2::Sha256;
-
 use time;
 
+use hash_algorithm::*;
 use misc::*;
 use database::*;
+use operations::*;
 use types::*;
 
+/// Amount of data hashed from each end of a file for the partial hash.
+
+pub const PARTIAL_HASH_BLOCK_SIZE: u64 = 0x4000;
+
+const HASH_BUFFER_SIZE: usize = 0x40000;
+
+#[ derive (Clone, Copy, Debug, Eq, PartialEq) ]
+pub enum ContentHashStage {
+	Partial,
+	Full,
+}
+
+impl ContentHashStage {
+
+	pub fn description (
+		& self,
+	) -> & 'static str {
+
+		match * self {
+			ContentHashStage::Partial => "partial contents",
+			ContentHashStage::Full => "contents",
+		}
+
+	}
+
+	fn hash_size (
+		& self,
+		file_size: u64,
+	) -> u64 {
+
+		match * self {
+
+			ContentHashStage::Partial =>
+				cmp::min (
+					file_size,
+					PARTIAL_HASH_BLOCK_SIZE * 2),
+
+			ContentHashStage::Full =>
+				file_size,
+
+		}
+
+	}
+
+}
+
 pub struct ContentHasher <'a> {
 
 	root_paths_set: HashSet <PathRef>,
 
+	stage: ContentHashStage,
+	hash_algorithm: HashAlgorithm,
 	batch_size: u64,
 
 	file_database: & 'a mut FileDatabase,
+	worker_pool: HashWorkerPool,
 
 	num_ignored: u64,
 	num_fresh: u64,
+	num_unneeded: u64,
 	num_updated: u64,
 	num_remaining: u64,
 	num_errors: u64,
@@ -33,7 +87,10 @@ impl <'a> ContentHasher <'a> {
 
 	pub fn new (
 		root_paths: & 'a [PathRef],
+		stage: ContentHashStage,
+		hash_algorithm: HashAlgorithm,
 		batch_size: u64,
+		num_threads: usize,
 		file_database: & 'a mut FileDatabase,
 	) -> ContentHasher <'a> {
 
@@ -43,16 +100,42 @@ impl <'a> ContentHasher <'a> {
 				root_path.clone ()
 			).collect ();
 
+		match stage {
+
+			ContentHashStage::Partial => {
+
+				invalidate_other_hash_algorithms (
+					& root_paths_set,
+					hash_algorithm,
+					file_database);
+
+				mark_size_collisions (
+					& root_paths_set,
+					file_database);
+
+			},
+
+			ContentHashStage::Full =>
+				mark_partial_hash_collisions (
+					& root_paths_set,
+					file_database),
+
+		}
+
 		ContentHasher {
 
 			root_paths_set: root_paths_set,
 
+			stage: stage,
+			hash_algorithm: hash_algorithm,
 			batch_size: batch_size,
 
 			file_database: file_database,
+			worker_pool: HashWorkerPool::new (num_threads),
 
 			num_ignored: 0,
 			num_fresh: 0,
+			num_unneeded: 0,
 			num_updated: 0,
 			num_remaining: 0,
 			num_errors: 0,
@@ -68,14 +151,81 @@ impl <'a> ContentHasher <'a> {
 
 		let mut num_ignored: u64 = 0;
 		let mut num_fresh: u64 = 0;
+		let mut num_unneeded: u64 = 0;
 		let mut num_remaining: u64 = 0;
 		let mut num_updated: u64 = 0;
 		let mut num_errors: u64 = 0;
 
 		let mut size_hashed: u64 = 0;
 
-		for ref mut file_data
-		in self.file_database.iter_mut () {
+		let stage =
+			self.stage;
+
+		// hardlinks share their contents, so each inode is only hashed once,
+		// and the hash is copied to its other links
+
+		let mut hashed_inodes: HashMap <InodeId, usize> =
+			HashMap::new ();
+
+		for (file_index, file_data)
+		in self.file_database.iter ().enumerate () {
+
+			let existing_hash =
+				match stage {
+					ContentHashStage::Partial => file_data.partial_hash,
+					ContentHashStage::Full => file_data.content_hash,
+				};
+
+			if let Some (inode_id) = file_data.inode_id () {
+
+				if existing_hash != ZERO_HASH {
+
+					hashed_inodes.entry (
+						inode_id,
+					).or_insert (
+						file_index);
+
+				}
+
+			}
+
+		}
+
+		let mut queued_inodes: HashMap <InodeId, usize> =
+			HashMap::new ();
+
+		let mut linked_indices: HashMap <usize, Vec <usize>> =
+			HashMap::new ();
+
+		let mut copied_indices: Vec <(usize, usize)> =
+			Vec::new ();
+
+		// select a batch of files to hash
+
+		let mut jobs: Vec <HashJob> =
+			Vec::new ();
+
+		for (file_index, file_data)
+		in self.file_database.iter ().enumerate () {
+
+			let (existing_hash, hash_needed) =
+				match stage {
+
+				ContentHashStage::Partial => (
+					file_data.partial_hash,
+					file_data.partial_hash_needed,
+				),
+
+				ContentHashStage::Full => (
+					file_data.content_hash,
+					file_data.content_hash_needed,
+				),
+
+			};
+
+			let hash_size =
+				stage.hash_size (
+					file_data.size);
 
 			if (
 
@@ -99,7 +249,7 @@ impl <'a> ContentHasher <'a> {
 				continue;
 
 			} else if (
-				file_data.content_hash != ZERO_HASH
+				existing_hash != ZERO_HASH
 				|| file_data.size == 0
 			) {
 
@@ -107,9 +257,37 @@ impl <'a> ContentHasher <'a> {
 
 				continue;
 
+			} else if ! hash_needed {
+
+				num_unneeded += 1;
+
+				continue;
+
+			} else if let Some (& source_index) =
+				file_data.inode_id ().and_then (
+					|inode_id|
+					hashed_inodes.get (& inode_id)) {
+
+				copied_indices.push (
+					(file_index, source_index));
+
+				continue;
+
+			} else if let Some (& job_index) =
+				file_data.inode_id ().and_then (
+					|inode_id|
+					queued_inodes.get (& inode_id)) {
+
+				linked_indices.entry (
+					job_index,
+				).or_default ().push (
+					file_index);
+
+				continue;
+
 			} else if (
-				num_updated > 0
-				&& size_hashed + file_data.size > self.batch_size
+				! jobs.is_empty ()
+				&& size_hashed + hash_size > self.batch_size
 			) {
 
 				num_remaining += 1;
@@ -118,48 +296,149 @@ impl <'a> ContentHasher <'a> {
 
 			} else {
 
-				output.status_format (
-					format_args! (
-						"Content hash: {}",
-						file_data.path.to_string_lossy ()));
+				jobs.push (
+					HashJob {
 
-				let content_hash_time =
-					time::get_time ();
+					index: file_index,
+					path: file_data.path.to_path (),
+					size: file_data.size,
+					hash_algorithm: self.hash_algorithm,
 
-				if let Ok (content_hash) = (
-					calculate_hash_for_file (
-						file_data.path.clone ())
-				) {
+					hash_function: match stage {
 
-					if content_hash != file_data.content_hash {
+						ContentHashStage::Partial =>
+							calculate_partial_hash_for_file,
 
-						file_data.content_hash = content_hash;
-						file_data.content_hash_time = content_hash_time.sec;
+						ContentHashStage::Full =>
+							calculate_hash_for_file,
 
-						file_data.extent_hash = ZERO_HASH;
-						file_data.extent_hash_time = 0;
+					},
 
-						file_data.defragment_time = 0;
-						file_data.deduplicate_time = 0;
+				});
 
-					}
+				if let Some (inode_id) = file_data.inode_id () {
 
-					num_updated += 1;
+					queued_inodes.insert (
+						inode_id,
+						file_index);
 
-				} else {
+				}
 
-					num_errors += 1;
+				size_hashed += hash_size;
 
-				}
+			}
+
+		}
+
+		// hash them on the worker threads
+
+		let hash_time =
+			time::get_time ();
+
+		let file_database =
+			& mut self.file_database;
+
+		self.worker_pool.hash_files (
+			jobs,
+			|job_result| {
+
+			output.status_format (
+				format_args! (
+					"{}: {}",
+					match stage {
+						ContentHashStage::Partial => "Partial content hash",
+						ContentHashStage::Full => "Content hash",
+					},
+					job_result.path.to_string_lossy ()));
+
+			let num_links =
+				1 + linked_indices.get (
+					& job_result.index,
+				).map (
+					|linked_indices|
+					linked_indices.len ()
+				).unwrap_or (0) as u64;
+
+			let hash =
+				match job_result.result {
 
-				size_hashed += file_data.size;
+				Ok (hash) =>
+					hash,
+
+				Err (_) => {
+
+					num_errors += num_links;
+
+					return;
+
+				},
+
+			};
+
+			update_content_hash (
+				stage,
+				& mut file_database [job_result.index],
+				hash,
+				hash_time.sec);
+
+			if let Some (linked_indices) =
+				linked_indices.get (
+					& job_result.index) {
+
+				for & linked_index in linked_indices.iter () {
+
+					update_content_hash (
+						stage,
+						& mut file_database [linked_index],
+						hash,
+						hash_time.sec);
+
+				}
 
 			}
 
+			num_updated += num_links;
+
+		});
+
+		// and copy hashes calculated earlier to links which don't have them
+
+		for (file_index, source_index) in copied_indices {
+
+			let (hash, hash_time) = {
+
+				let ref source_file_data =
+					self.file_database [source_index];
+
+				match stage {
+
+					ContentHashStage::Partial => (
+						source_file_data.partial_hash,
+						source_file_data.partial_hash_time,
+					),
+
+					ContentHashStage::Full => (
+						source_file_data.content_hash,
+						source_file_data.content_hash_time,
+					),
+
+				}
+
+			};
+
+			update_content_hash (
+				stage,
+				& mut self.file_database [file_index],
+				hash,
+				hash_time);
+
+			num_updated += 1;
+
 		}
 
 		self.num_ignored = num_ignored;
 		self.num_fresh = num_fresh;
+		self.num_unneeded = num_unneeded;
 		self.num_remaining = num_remaining;
 		self.num_updated += num_updated;
 		self.num_errors += num_errors;
@@ -176,6 +455,10 @@ impl <'a> ContentHasher <'a> {
 		self.num_fresh
 	}
 
+	pub fn num_unneeded (& self) -> u64 {
+		self.num_unneeded
+	}
+
 	pub fn num_updated (& self) -> u64 {
 		self.num_updated
 	}
@@ -198,21 +481,323 @@ impl <'a> ContentHasher <'a> {
 
 }
 
+fn update_content_hash (
+	stage: ContentHashStage,
+	file_data: & mut FileData,
+	hash: Hash,
+	hash_time: i64,
+) {
+
+	match stage {
+
+		ContentHashStage::Partial => {
+
+			// only files without a partial hash are hashed, since the scan
+			// forgets the hashes of any file which has changed
+
+			if hash != file_data.partial_hash {
+
+				file_data.partial_hash = hash;
+				file_data.partial_hash_time = hash_time;
+
+			}
+
+		},
+
+		ContentHashStage::Full => {
+
+			if hash != file_data.content_hash {
+
+				file_data.content_hash = hash;
+				file_data.content_hash_time = hash_time;
+
+				file_data.extent_hash = ZERO_HASH;
+				file_data.extent_hash_time = 0;
+
+				file_data.defragment_time = 0;
+				file_data.deduplicate_time = 0;
+				file_data.verify_time = 0;
+
+			}
+
+		},
+
+	}
+
+}
+
+fn invalidate_other_hash_algorithms (
+	root_paths_set: & HashSet <PathRef>,
+	hash_algorithm: HashAlgorithm,
+	file_database: & mut FileDatabase,
+) {
+
+	// hashes from different algorithms can't be compared, so forget them
+
+	for file_data in file_database.iter_mut () {
+
+		if (
+
+			(
+
+				file_data.root_path.is_none ()
+
+			) || (
+
+				file_data.root_path.is_some ()
+
+				&& ! root_paths_set.contains (
+					& file_data.root_path.as_ref ().unwrap ().clone ())
+
+			) || (
+
+				file_data.content_hash_algorithm == hash_algorithm
+
+			)
+
+		) {
+			continue;
+		}
+
+		file_data.content_hash_algorithm = hash_algorithm;
+
+		file_data.partial_hash = ZERO_HASH;
+		file_data.partial_hash_time = 0;
+
+		file_data.content_hash = ZERO_HASH;
+		file_data.content_hash_time = 0;
+
+		file_data.extent_hash = ZERO_HASH;
+		file_data.extent_hash_time = 0;
+
+		file_data.defragment_time = 0;
+		file_data.deduplicate_time = 0;
+		file_data.verify_time = 0;
+		file_data.chunk_table = None;
+
+	}
+
+}
+
+fn mark_size_collisions (
+	root_paths_set: & HashSet <PathRef>,
+	file_database: & mut FileDatabase,
+) {
+
+	// count files of each size, only a file which shares its size with another
+	// can possibly be a duplicate, so there is no point hashing the rest
+
+	let mut size_counts: HashMap <u64, u64> =
+		HashMap::new ();
+
+	let mut inodes_seen: HashSet <InodeId> =
+		HashSet::new ();
+
+	for file_data in file_database.iter () {
+
+		if (
+
+			(
+
+				file_data.root_path.is_none ()
+
+			) || (
+
+				file_data.root_path.is_some ()
+
+				&& ! root_paths_set.contains (
+					& file_data.root_path.as_ref ().unwrap ().clone ())
+
+			) || (
+
+				file_data.size == 0
+
+			)
+
+		) {
+			continue;
+		}
+
+		// hardlinks of the same inode are only counted once
+
+		if let Some (inode_id) = file_data.inode_id () {
+
+			if ! inodes_seen.insert (inode_id) {
+				continue;
+			}
+
+		}
+
+		* size_counts.entry (
+			file_data.size,
+		).or_insert (0) += 1;
+
+	}
+
+	for file_data in file_database.iter_mut () {
+
+		file_data.partial_hash_needed =
+			size_counts.get (
+				& file_data.size,
+			).map (
+				|& size_count|
+				size_count > 1
+			).unwrap_or (false);
+
+	}
+
+}
+
+fn mark_partial_hash_collisions (
+	root_paths_set: & HashSet <PathRef>,
+	file_database: & mut FileDatabase,
+) {
+
+	// same again, but now files must also share their partial hash, which has
+	// been calculated for every file with a colliding size
+
+	let mut partial_hash_counts: HashMap <(u64, Hash), u64> =
+		HashMap::new ();
+
+	let mut inodes_seen: HashSet <InodeId> =
+		HashSet::new ();
+
+	for file_data in file_database.iter () {
+
+		if (
+
+			(
+
+				file_data.root_path.is_none ()
+
+			) || (
+
+				file_data.root_path.is_some ()
+
+				&& ! root_paths_set.contains (
+					& file_data.root_path.as_ref ().unwrap ().clone ())
+
+			) || (
+
+				file_data.size == 0
+
+			) || (
+
+				file_data.partial_hash == ZERO_HASH
+
+			)
+
+		) {
+			continue;
+		}
+
+		if let Some (inode_id) = file_data.inode_id () {
+
+			if ! inodes_seen.insert (inode_id) {
+				continue;
+			}
+
+		}
+
+		* partial_hash_counts.entry (
+			(file_data.size, file_data.partial_hash),
+		).or_insert (0) += 1;
+
+	}
+
+	for file_data in file_database.iter_mut () {
+
+		file_data.content_hash_needed =
+			file_data.partial_hash != ZERO_HASH
+
+			&& partial_hash_counts.get (
+				& (file_data.size, file_data.partial_hash),
+			).map (
+				|& partial_hash_count|
+				partial_hash_count > 1
+			).unwrap_or (false);
+
+	}
+
+}
+
+fn calculate_partial_hash_for_file (
+	path: & Path,
+	size: u64,
+	hash_algorithm: HashAlgorithm,
+) -> Result <Hash, String> {
+
+	let mut file =
+		io_result (
+			File::open (
+				path),
+		) ?;
+
+	let mut hasher =
+		hash_algorithm.new_hash_function ();
+
+	let mut buffer: [u8; PARTIAL_HASH_BLOCK_SIZE as usize] =
+		[0u8; PARTIAL_HASH_BLOCK_SIZE as usize];
+
+	// hash the first block
+
+	let bytes_read =
+		io_result (
+			read_fully (
+				& mut file,
+				& mut buffer),
+		) ?;
+
+	hasher.input (
+		& buffer [
+			0 .. bytes_read]);
+
+	// hash the last block, without overlapping the first
+
+	if size > PARTIAL_HASH_BLOCK_SIZE {
+
+		io_result (
+			file.seek (
+				SeekFrom::Start (
+					cmp::max (
+						PARTIAL_HASH_BLOCK_SIZE,
+						size - PARTIAL_HASH_BLOCK_SIZE))),
+		) ?;
+
+		let bytes_read =
+			io_result (
+				read_fully (
+					& mut file,
+					& mut buffer),
+			) ?;
+
+		hasher.input (
+			& buffer [
+				0 .. bytes_read]);
+
+	}
+
+	Ok (hasher.result ())
+
+}
+
 fn calculate_hash_for_file (
-	path: RecursivePathRef,
+	path: & Path,
+	_size: u64,
+	hash_algorithm: HashAlgorithm,
 ) -> Result <Hash, String> {
 
 	let mut file =
 		io_result (
 			File::open (
-				path.to_path ()),
+				path),
 		) ?;
 
 	let mut hasher =
-		Sha256::new ();
+		hash_algorithm.new_hash_function ();
 
-	let mut buffer: [u8; 0x1000] =
-		[0u8; 0x1000];
+	let mut buffer: Vec <u8> =
+		vec! [0u8; HASH_BUFFER_SIZE];
 
 	loop {
 
@@ -232,13 +817,7 @@ fn calculate_hash_for_file (
 
 	}
 
-	let mut result: Hash =
-		[0u8; HASH_SIZE];
-
-	result.copy_from_slice (
-		& hasher.result ());
-
-	Ok (result)
+	Ok (hasher.result ())
 
 }
 
@@ -0,0 +1,650 @@
+use std::collections::HashMap;
+use std::fs;
+
+use database::*;
+use operations::*;
+use types::*;
+
+/// How far in the future a timestamp can be before it is treated as damaged,
+/// to allow for clocks which disagree a little.
+
+pub const TIMESTAMP_SLACK: i64 = 24 * 60 * 60;
+
+/// Something wrong with a file's entry in the database.
+
+#[ derive (Clone, Copy, Debug, Eq, PartialEq) ]
+pub enum DatabaseProblem {
+	OutOfOrder,
+	Duplicate,
+	InvalidPath,
+	MissingFile,
+	SubvolumeNotMounted,
+	InconsistentHash,
+	DamagedHash,
+	InvalidTimestamp,
+	InvalidChunkTable,
+	InvalidExtentHashVersion,
+	InvalidGroup,
+}
+
+impl DatabaseProblem {
+
+	pub fn description (
+		& self,
+	) -> & 'static str {
+
+		match * self {
+
+			DatabaseProblem::OutOfOrder =>
+				"entry is out of order",
+
+			DatabaseProblem::Duplicate =>
+				"entry is duplicated later in the database",
+
+			DatabaseProblem::InvalidPath =>
+				"path is not a valid file name",
+
+			DatabaseProblem::MissingFile =>
+				"file no longer exists",
+
+			DatabaseProblem::SubvolumeNotMounted =>
+				"subvolume is not currently mounted",
+
+			DatabaseProblem::InconsistentHash =>
+				"hashes and their timestamps don't agree",
+
+			DatabaseProblem::DamagedHash =>
+				"hash may have been damaged by an older version",
+
+			DatabaseProblem::InvalidTimestamp =>
+				"timestamp is negative or in the future",
+
+			DatabaseProblem::InvalidChunkTable =>
+				"chunk table doesn't cover the file exactly",
+
+			DatabaseProblem::InvalidExtentHashVersion =>
+				"extent hash version is newer than this version understands",
+
+			DatabaseProblem::InvalidGroup =>
+				"more files are shared than are in the group",
+
+		}
+
+	}
+
+	/// Whether this problem is repaired by removing the entry altogether,
+	/// rather than by forgetting what is known about the file so that it is
+	/// worked out again on the next run.
+
+	pub fn needs_removal (
+		& self,
+	) -> bool {
+
+		* self == DatabaseProblem::Duplicate
+			|| * self == DatabaseProblem::InvalidPath
+			|| * self == DatabaseProblem::MissingFile
+
+	}
+
+	/// Entries which are out of order are put back in order whenever the
+	/// database is read, so there is nothing about them to invalidate. Files
+	/// in a subvolume which isn't mounted are left alone, since they may well
+	/// still be there when it is mounted again.
+
+	pub fn needs_invalidation (
+		& self,
+	) -> bool {
+
+		match * self {
+			DatabaseProblem::OutOfOrder => false,
+			DatabaseProblem::SubvolumeNotMounted => false,
+			_ => ! self.needs_removal (),
+		}
+
+	}
+
+}
+
+/// Checks a list of files as stored in a database, returning the problems with
+/// each one, in the same order. Duplicates are reported for every copy except
+/// the last, which is the one which is used when the database is read.
+
+pub fn check_file_data_list (
+	file_data_list: & [FileData],
+	file_data_ordered: bool,
+	database_header: & DatabaseHeader,
+	now: i64,
+	recursive_path_database: & mut RecursivePathDatabase,
+	subvolume_locator: & mut SubvolumeLocator,
+) -> Vec <Vec <DatabaseProblem>> {
+
+	let mut last_indexes: HashMap <RecursivePathRef, usize> =
+		HashMap::new ();
+
+	for (index, file_data) in file_data_list.iter ().enumerate () {
+
+		last_indexes.insert (
+			file_data.path.clone (),
+			index);
+
+	}
+
+	file_data_list.iter ().enumerate ().map (
+		|(index, file_data)| {
+
+		let mut problems: Vec <DatabaseProblem> =
+			Vec::new ();
+
+		// files are only in order within the section for their filesystem
+
+		if (
+			file_data_ordered
+			&& index > 0
+			&& file_data_list [index - 1].subvolume.as_ref ().map (
+				|subvolume|
+				subvolume.filesystem_uuid
+			) == file_data.subvolume.as_ref ().map (
+				|subvolume|
+				subvolume.filesystem_uuid
+			)
+			&& file_data_list [index - 1].path > file_data.path
+		) {
+			problems.push (DatabaseProblem::OutOfOrder);
+		}
+
+		if last_indexes [& file_data.path] != index {
+			problems.push (DatabaseProblem::Duplicate);
+		}
+
+		problems.extend (
+			check_file_data (
+				file_data,
+				database_header,
+				now,
+				recursive_path_database,
+				subvolume_locator));
+
+		problems
+
+	}).collect ()
+
+}
+
+/// Checks everything about a single file which can be checked on its own,
+/// including that it still exists, wherever its subvolume is now.
+
+pub fn check_file_data (
+	file_data: & FileData,
+	database_header: & DatabaseHeader,
+	now: i64,
+	recursive_path_database: & mut RecursivePathDatabase,
+	subvolume_locator: & mut SubvolumeLocator,
+) -> Vec <DatabaseProblem> {
+
+	let mut problems: Vec <DatabaseProblem> =
+		Vec::new ();
+
+	let path =
+		file_data.path.to_path ();
+
+	if (
+		! path.is_absolute ()
+		|| path.file_name ().is_none ()
+	) {
+
+		problems.push (
+			DatabaseProblem::InvalidPath);
+
+	} else {
+
+		match subvolume_locator.current_path (
+			recursive_path_database,
+			file_data) {
+
+			Some (current_path) => {
+
+				if ! fs::symlink_metadata (
+					current_path.to_path (),
+				).map (
+					|metadata|
+					metadata.file_type ().is_file ()
+				).unwrap_or (false) {
+
+					problems.push (
+						DatabaseProblem::MissingFile);
+
+				}
+
+			},
+
+			None =>
+				problems.push (
+					DatabaseProblem::SubvolumeNotMounted),
+
+		}
+
+	}
+
+	// every hash is recorded along with when it was calculated, although an
+	// extent hash can legitimately be zero, for a file with no extents, and a
+	// content hash without a partial hash is only wrong for databases written
+	// since the header was added, since older versions didn't always keep both
+
+	if (
+		(file_data.partial_hash != ZERO_HASH)
+			!= (file_data.partial_hash_time != 0)
+		|| (file_data.content_hash != ZERO_HASH)
+			!= (file_data.content_hash_time != 0)
+		|| (
+			file_data.extent_hash != ZERO_HASH
+			&& file_data.extent_hash_time == 0
+		)
+		|| (
+			database_header.schema_version >= 2
+			&& file_data.content_hash != ZERO_HASH
+			&& file_data.partial_hash == ZERO_HASH
+		)
+	) {
+
+		problems.push (
+			DatabaseProblem::InconsistentHash);
+
+	}
+
+	if (
+		database_header.schema_version < 3
+		&& (
+			is_damaged_hash (& file_data.partial_hash)
+			|| is_damaged_hash (& file_data.content_hash)
+			|| is_damaged_hash (& file_data.extent_hash)
+			|| file_data.chunk_table.as_ref ().map (
+				|chunk_table|
+
+				chunk_table.chunks.iter ().any (
+					|chunk|
+					is_damaged_hash (& chunk.hash))
+
+			).unwrap_or (false)
+		)
+	) {
+
+		problems.push (
+			DatabaseProblem::DamagedHash);
+
+	}
+
+	let timestamps = [
+		file_data.partial_hash_time,
+		file_data.content_hash_time,
+		file_data.extent_hash_time,
+		file_data.defragment_time,
+		file_data.deduplicate_time,
+		file_data.verify_time,
+		file_data.chunk_table.as_ref ().map (
+			|chunk_table|
+			chunk_table.deduplicate_time
+		).unwrap_or (0),
+	];
+
+	if timestamps.iter ().any (
+		|& timestamp|
+		timestamp < 0 || timestamp > now + TIMESTAMP_SLACK
+	) {
+
+		problems.push (
+			DatabaseProblem::InvalidTimestamp);
+
+	}
+
+	if let Some (ref chunk_table) = file_data.chunk_table {
+
+		let mut next_offset: u64 = 0;
+		let mut chunks_valid = true;
+
+		for chunk in chunk_table.chunks.iter () {
+
+			if chunk.offset != next_offset || chunk.length == 0 {
+				chunks_valid = false;
+				break;
+			}
+
+			next_offset += chunk.length;
+
+		}
+
+		if ! chunks_valid || next_offset != file_data.size {
+
+			problems.push (
+				DatabaseProblem::InvalidChunkTable);
+
+		}
+
+	}
+
+	if file_data.extent_hash_version > EXTENT_HASH_VERSION {
+
+		problems.push (
+			DatabaseProblem::InvalidExtentHashVersion);
+
+	}
+
+	if file_data.group_shared > file_data.group_size {
+
+		problems.push (
+			DatabaseProblem::InvalidGroup);
+
+	}
+
+	problems
+
+}
+
+/// Repairs a file according to the problems found with it, returning None if
+/// it should be removed from the database.
+
+pub fn repair_file_data (
+	mut file_data: FileData,
+	problems: & [DatabaseProblem],
+) -> Option <FileData> {
+
+	if problems.iter ().any (
+		|problem|
+		problem.needs_removal ()
+	) {
+		return None;
+	}
+
+	if problems.iter ().any (
+		|problem|
+		problem.needs_invalidation ()
+	) {
+
+		invalidate_file_data (
+			& mut file_data);
+
+	}
+
+	Some (file_data)
+
+}
+
+/// Forgets everything which was worked out about a file, so that it will be
+/// hashed and deduplicated again from scratch.
+
+pub fn invalidate_file_data (
+	file_data: & mut FileData,
+) {
+
+	file_data.partial_hash = ZERO_HASH;
+	file_data.partial_hash_time = 0;
+
+	file_data.content_hash = ZERO_HASH;
+	file_data.content_hash_time = 0;
+
+	file_data.extent_hash = ZERO_HASH;
+	file_data.extent_hash_time = 0;
+	file_data.extent_hash_version = 0;
+
+	file_data.defragment_time = 0;
+	file_data.deduplicate_time = 0;
+	file_data.verify_time = 0;
+
+	file_data.group_size = 0;
+	file_data.group_shared = 0;
+
+	file_data.chunk_table = None;
+
+}
+
+#[ cfg (test) ]
+mod tests {
+
+	use std::env;
+
+	use serde_json;
+
+	use super::*;
+
+	const NOW: i64 = 1500000000;
+
+	const GOOD_HASH: & str =
+		"0102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f20";
+
+	const DAMAGED_HASH: & str =
+		"0102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f16";
+
+	/// Decodes a file from its JSON record, with the given fields added. The
+	/// test binary itself stands in for a file which exists.
+
+	fn file_data (
+		path: & str,
+		fields: & str,
+	) -> FileData {
+
+		let path =
+			if path.is_empty () {
+				env::current_exe ().unwrap ().to_string_lossy ().to_string ()
+			} else {
+				path.to_string ()
+			};
+
+		let file_data_record: FileDataRecord =
+			serde_json::from_str (
+				& format! (
+					"{{\"path\":\"{}\",\"size\":1,\"mtime\":0,\"ctime\":0,\
+					\"mode\":33188,\"uid\":0,\"gid\":0{}}}",
+					path,
+					fields),
+			).unwrap ();
+
+		decode_file_data_record (
+			& mut RecursivePathDatabase::new (),
+			file_data_record,
+		).unwrap ()
+
+	}
+
+	fn check (
+		file_data: & FileData,
+		schema_version: u32,
+	) -> Vec <DatabaseProblem> {
+
+		check_file_data (
+			file_data,
+			& DatabaseHeader {
+				schema_version: schema_version,
+				tool_version: "0.0.0".to_owned (),
+			},
+			NOW,
+			& mut RecursivePathDatabase::new (),
+			& mut SubvolumeLocator::new ())
+
+	}
+
+	#[ test ]
+	fn valid_file () {
+
+		let file_data =
+			file_data (
+				"",
+				& format! (
+					",\"partial_hash\":\"{}\",\"partial_hash_time\":{},\
+					\"content_hash\":\"{}\",\"content_hash_time\":{},\
+					\"group_size\":2,\"group_shared\":1",
+					GOOD_HASH,
+					NOW,
+					GOOD_HASH,
+					NOW));
+
+		assert_eq! (
+			check (& file_data, DATABASE_SCHEMA_VERSION),
+			vec! []);
+
+	}
+
+	#[ test ]
+	fn missing_and_invalid_paths () {
+
+		assert_eq! (
+			check (
+				& file_data ("/nonexistent/btrfs-dedupe-test", ""),
+				DATABASE_SCHEMA_VERSION),
+			vec! [ DatabaseProblem::MissingFile ]);
+
+		// a path with no file name can't be a file
+
+		assert_eq! (
+			check (
+				& file_data ("/", ""),
+				DATABASE_SCHEMA_VERSION),
+			vec! [ DatabaseProblem::InvalidPath ]);
+
+	}
+
+	#[ test ]
+	fn unmounted_subvolume () {
+
+		// a file in a subvolume which can't be found may still exist, so it
+		// is neither removed nor invalidated
+
+		let file_data =
+			file_data (
+				"/nonexistent/btrfs-dedupe-test",
+				& format! (
+					",\"filesystem_uuid\":\"{}\",\"subvolume_id\":256,\
+					\"subvolume_path\":\"btrfs-dedupe-test\",\
+					\"partial_hash\":\"{}\",\"partial_hash_time\":{}",
+					"01234567-89ab-cdef-0123-456789abcdef",
+					GOOD_HASH,
+					NOW));
+
+		assert_eq! (
+			check (& file_data, DATABASE_SCHEMA_VERSION),
+			vec! [ DatabaseProblem::SubvolumeNotMounted ]);
+
+		let repaired_file =
+			repair_file_data (
+				file_data,
+				& [ DatabaseProblem::SubvolumeNotMounted ],
+			).unwrap ();
+
+		assert_eq! (repaired_file.partial_hash_time, NOW);
+
+	}
+
+	#[ test ]
+	fn inconsistent_hashes () {
+
+		// a hash without a time
+
+		assert_eq! (
+			check (
+				& file_data (
+					"",
+					& format! (
+						",\"partial_hash\":\"{}\"",
+						GOOD_HASH)),
+				DATABASE_SCHEMA_VERSION),
+			vec! [ DatabaseProblem::InconsistentHash ]);
+
+		// a content hash without a partial hash is only a problem in newer
+		// databases
+
+		let content_only =
+			file_data (
+				"",
+				& format! (
+					",\"content_hash\":\"{}\",\"content_hash_time\":{}",
+					GOOD_HASH,
+					NOW));
+
+		assert_eq! (
+			check (& content_only, 1),
+			vec! []);
+
+		assert_eq! (
+			check (& content_only, 2),
+			vec! [ DatabaseProblem::InconsistentHash ]);
+
+	}
+
+	#[ test ]
+	fn damaged_hashes () {
+
+		let file_data =
+			file_data (
+				"",
+				& format! (
+					",\"partial_hash\":\"{}\",\"partial_hash_time\":{}",
+					DAMAGED_HASH,
+					NOW));
+
+		assert_eq! (
+			check (& file_data, 2),
+			vec! [ DatabaseProblem::DamagedHash ]);
+
+		// newer databases were written after the bug was fixed
+
+		assert_eq! (
+			check (& file_data, 3),
+			vec! []);
+
+	}
+
+	#[ test ]
+	fn invalid_timestamps_and_groups () {
+
+		assert_eq! (
+			check (
+				& file_data (
+					"",
+					& format! (
+						",\"defragment_time\":{}",
+						NOW + TIMESTAMP_SLACK + 1)),
+				DATABASE_SCHEMA_VERSION),
+			vec! [ DatabaseProblem::InvalidTimestamp ]);
+
+		assert_eq! (
+			check (
+				& file_data ("", ",\"deduplicate_time\":-1"),
+				DATABASE_SCHEMA_VERSION),
+			vec! [ DatabaseProblem::InvalidTimestamp ]);
+
+		assert_eq! (
+			check (
+				& file_data ("", ",\"group_size\":2,\"group_shared\":3"),
+				DATABASE_SCHEMA_VERSION),
+			vec! [ DatabaseProblem::InvalidGroup ]);
+
+	}
+
+	#[ test ]
+	fn repair () {
+
+		let missing_file =
+			file_data ("/nonexistent/btrfs-dedupe-test", "");
+
+		assert! (
+			repair_file_data (
+				missing_file,
+				& [ DatabaseProblem::MissingFile ],
+			).is_none ());
+
+		let repaired_file =
+			repair_file_data (
+				file_data (
+					"",
+					& format! (
+						",\"partial_hash\":\"{}\",\"group_size\":2",
+						GOOD_HASH)),
+				& [ DatabaseProblem::InconsistentHash ],
+			).unwrap ();
+
+		assert_eq! (repaired_file.partial_hash, ZERO_HASH);
+		assert_eq! (repaired_file.group_size, 0);
+
+	}
+
+}
+
+// ex: noet ts=4 filetype=rust
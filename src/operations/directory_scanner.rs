@@ -1,21 +1,42 @@
 use std::collections::HashSet;
+use std::ffi::OsStr;
 use std::fs;
 use std::fs::DirEntry;
 use std::io;
 use std::iter::Peekable;
 use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::path::PathBuf;
 use std::rc::Rc;
 use std::vec;
 
 use output::Output;
 
+use hash_algorithm::*;
+use linux;
 use misc::*;
 use database::*;
+use operations::*;
 use types::*;
 
+/// Walks the root paths, bringing the database up to date with the files found
+/// there. The generation of each subvolume is recorded as it is scanned, and an
+/// incremental scan only descends into directories where btrfs reports that
+/// something has changed since then.
+///
+/// Scans stop at the top of other subvolumes, as they do at other filesystems,
+/// unless asked to cross them, and subvolumes which are read only can be left
+/// out altogether.
+
 pub struct DirectoryScanner <'a> {
 
 	root_paths: & 'a [PathRef],
+	path_filter: & 'a PathFilter,
+	use_ignore_files: bool,
+	filter_fingerprint: u64,
+	incremental: bool,
+	cross_subvolumes: bool,
+	skip_readonly_subvolumes: bool,
 
 	in_iterator: Peekable <vec::IntoIter <FileData>>,
 	out_builder: FileDatabaseBuilder,
@@ -23,16 +44,40 @@ pub struct DirectoryScanner <'a> {
 	root_paths_unordered: HashSet <PathRef>,
 	root_paths_scanned: HashSet <PathRef>,
 
+	ignore_files: Vec <IgnoreFile>,
+
+	content_hash_algorithm: HashAlgorithm,
+
+	scan_generations: Vec <ScanGeneration>,
+	new_scan_generations: Vec <ScanGeneration>,
+	incremental_changes: Option <IncrementalChanges>,
+	subvolume_paths: Vec <PathBuf>,
+
 	progress: u64,
 
 }
 
+/// What has changed beneath a root since it was last scanned. Every ancestor
+/// of a changed path is dirty, and everything beneath a moved path is new to
+/// the database.
+
+struct IncrementalChanges {
+	dirty_paths: HashSet <PathBuf>,
+	moved_paths: HashSet <PathBuf>,
+}
+
 impl <'a> DirectoryScanner <'a> {
 
 	pub fn new (
-		root_paths: & [PathRef],
+		root_paths: & 'a [PathRef],
+		path_filter: & 'a PathFilter,
+		use_ignore_files: bool,
+		content_hash_algorithm: HashAlgorithm,
+		incremental: bool,
+		cross_subvolumes: bool,
+		skip_readonly_subvolumes: bool,
 		file_database: FileDatabase,
-	) -> DirectoryScanner {
+	) -> DirectoryScanner <'a> {
 
 		let root_paths_set: HashSet <PathRef> =
 			root_paths.iter ().map (
@@ -42,6 +87,37 @@ impl <'a> DirectoryScanner <'a> {
 
 			).collect ();
 
+		let scan_generations =
+			file_database.scan_generations ().to_vec ();
+
+		// changes in a nested subvolume don't show up in the one above it, so
+		// an incremental scan needs to know where they were last time
+
+		let subvolume_paths: Vec <PathBuf> =
+			if cross_subvolumes {
+
+			let subvolume_root_paths: HashSet <RecursivePathRef> =
+				file_database.iter ().filter_map (
+					|file_data|
+
+					file_data.subvolume.as_ref ().map (
+						|subvolume|
+						subvolume.root_path.clone ()
+					)
+
+				).collect ();
+
+			subvolume_root_paths.iter ().map (
+				|subvolume_root_path|
+				subvolume_root_path.to_path ()
+			).collect ()
+
+		} else {
+
+			Vec::new ()
+
+		};
+
 		let previous_database_iterator =
 			file_database.into_iter ();
 
@@ -54,6 +130,13 @@ impl <'a> DirectoryScanner <'a> {
 		DirectoryScanner {
 
 			root_paths: root_paths,
+			path_filter: path_filter,
+			use_ignore_files: use_ignore_files,
+			filter_fingerprint: path_filter.fingerprint (
+				use_ignore_files),
+			incremental: incremental,
+			cross_subvolumes: cross_subvolumes,
+			skip_readonly_subvolumes: skip_readonly_subvolumes,
 
 			in_iterator: previous_database_iterator,
 			out_builder: new_database_builder,
@@ -61,6 +144,15 @@ impl <'a> DirectoryScanner <'a> {
 			root_paths_unordered: root_paths_set,
 			root_paths_scanned: HashSet::new (),
 
+			ignore_files: Vec::new (),
+
+			content_hash_algorithm: content_hash_algorithm,
+
+			scan_generations: scan_generations,
+			new_scan_generations: Vec::new (),
+			incremental_changes: None,
+			subvolume_paths: subvolume_paths,
+
 			progress: 0,
 
 		}
@@ -79,11 +171,6 @@ impl <'a> DirectoryScanner <'a> {
 				continue;
 			}
 
-			output.message_format (
-				format_args! (
-					"Scanning {}",
-					root_path.to_string_lossy ()));
-
 			let root_recursive_path =
 				recursive_path_database.for_path (
 					root_path.as_ref (),
@@ -106,33 +193,42 @@ impl <'a> DirectoryScanner <'a> {
 
 			);
 
-			loop {
+			self.keep_before (
+				& root_recursive_path);
 
-				{
+			let subvolume =
+				find_subvolume (
+					recursive_path_database,
+					root_path);
 
-					let existing_file_data_option =
-						self.in_iterator.peek ();
+			if self.is_skipped (
+				subvolume.as_ref ()) {
 
-					if existing_file_data_option.is_none () {
-						break;
-					}
+				output.message_format (
+					format_args! (
+						"Skipping {}, which is in a read only subvolume",
+						root_path.to_string_lossy ()));
 
-					let existing_file_data =
-						existing_file_data_option.unwrap ();
+				self.drop_existing (
+					root_path);
 
-					let existing_file_path =
-						& existing_file_data.path;
+				continue;
 
-					if * existing_file_path >= root_recursive_path {
-						break;
-					}
+			}
 
-				}
+			output.message_format (
+				format_args! (
+					"Scanning {}",
+					root_path.to_string_lossy ()));
 
-				self.out_builder.insert (
-					self.in_iterator.next ().unwrap ());
+			let scan_generation =
+				self.prepare_scan_generation (
+					output,
+					root_path,
+					subvolume.as_ref ());
 
-			}
+			let scan_all =
+				self.incremental_changes.is_none ();
 
 			self.scan_directory_internal (
 				output,
@@ -140,10 +236,115 @@ impl <'a> DirectoryScanner <'a> {
 				root_path.clone (),
 				root_path.clone (),
 				metadata.dev (),
+				subvolume,
+				scan_all,
+			) ?;
+
+			self.incremental_changes = None;
+
+			if let Some (scan_generation) = scan_generation {
+
+				self.new_scan_generations.push (
+					scan_generation);
+
+			}
+
+		}
+
+		self.finish (
+			output)
+
+	}
+
+	/// Brings the database up to date with a list of paths which are known to
+	/// have changed, rather than walking the root paths in full. Changed
+	/// directories are scanned along with everything beneath them, and paths
+	/// which no longer exist are removed.
+
+	pub fn scan_changes (
+		mut self,
+		output: & Output,
+		recursive_path_database: & mut RecursivePathDatabase,
+		changed_paths: & [PathBuf],
+	) -> Result <FileDatabase, String> {
+
+		let mut changed_recursive_paths: Vec <RecursivePathRef> =
+			changed_paths.iter ().filter_map (
+				|changed_path|
+
+				recursive_path_database.for_path (
+					changed_path)
+
+			).collect ();
+
+		changed_recursive_paths.sort ();
+		changed_recursive_paths.dedup ();
+
+		let mut last_changed_path: Option <PathBuf> =
+			None;
+
+		for changed_recursive_path in changed_recursive_paths {
+
+			let changed_path =
+				changed_recursive_path.to_path ();
+
+			// anything beneath a changed directory was scanned along with it
+
+			if last_changed_path.as_ref ().map (
+				|last_changed_path|
+
+				changed_path.starts_with (
+					last_changed_path)
+
+			).unwrap_or (false) {
+				continue;
+			}
+
+			let root_path =
+				match self.root_paths.iter ().filter (
+					|root_path|
+
+					changed_path.starts_with (
+						root_path.as_ref ())
+
+				).max_by_key (
+					|root_path|
+					root_path.components ().count ()
+				) {
+
+				Some (root_path) => root_path.clone (),
+				None => continue,
+
+			};
+
+			self.keep_before (
+				& changed_recursive_path);
+
+			self.scan_changed_path (
+				output,
+				recursive_path_database,
+				changed_recursive_path,
+				root_path,
 			) ?;
 
+			last_changed_path =
+				Some (changed_path);
+
 		}
 
+		self.finish (
+			output)
+
+	}
+
+	/// Carries over everything which is left, and builds the new database,
+	/// keeping the scan generations of the old one along with any new ones.
+
+	fn finish (
+		mut self,
+		output: & Output,
+	) -> Result <FileDatabase, String> {
+
 		for existing_file_data_ref
 		in self.in_iterator {
 
@@ -164,7 +365,127 @@ impl <'a> DirectoryScanner <'a> {
 				"Total {} files in database",
 				self.out_builder.len ()));
 
-		Ok (self.out_builder.build ())
+		let mut file_database =
+			self.out_builder.build ();
+
+		file_database.set_scan_generations (
+			self.scan_generations);
+
+		for scan_generation in self.new_scan_generations {
+
+			file_database.record_scan_generation (
+				scan_generation);
+
+		}
+
+		Ok (file_database)
+
+	}
+
+	/// Reads the generation of the subvolume a root is in, before it is
+	/// scanned, so it can be recorded once the scan succeeds. For an
+	/// incremental scan, this also finds what has changed since the root was
+	/// last scanned, if it has been.
+
+	fn prepare_scan_generation (
+		& mut self,
+		output: & Output,
+		root_path: & PathRef,
+		subvolume: Option <& Subvolume>,
+	) -> Option <ScanGeneration> {
+
+		let subvolume =
+			subvolume ?;
+
+		let subvolume_path =
+			subvolume.root_path.to_path ();
+
+		let relative_path =
+			match root_path.strip_prefix (
+				& subvolume_path) {
+
+			Ok (relative_path) =>
+				relative_path.to_owned (),
+
+			Err (_) =>
+				return None,
+
+		};
+
+		let generation =
+			match linux::get_subvolume_generation (
+				root_path,
+				subvolume.subvolume_id) {
+
+			Ok (generation) =>
+				generation,
+
+			Err (error) => {
+
+				if self.incremental {
+
+					output.message_format (
+						format_args! (
+							"Scanning all of {}, because its generation can't \
+							be read: {}",
+							root_path.to_string_lossy (),
+							error));
+
+				}
+
+				return None;
+
+			},
+
+		};
+
+		let previous_generation =
+			find_scan_generation (
+				& self.scan_generations,
+				subvolume.filesystem_uuid,
+				subvolume.subvolume_id,
+				& relative_path,
+				self.filter_fingerprint);
+
+		if let (true, Some (previous_generation)) =
+			(self.incremental, previous_generation) {
+
+			match find_incremental_changes (
+				root_path,
+				subvolume,
+				previous_generation) {
+
+				Ok (incremental_changes) => {
+
+					output.message_format (
+						format_args! (
+							"Looking for changes since generation {}",
+							previous_generation));
+
+					self.incremental_changes =
+						Some (incremental_changes);
+
+				},
+
+				Err (error) =>
+					output.message_format (
+						format_args! (
+							"Scanning all of {}, because its changes can't be \
+							found: {}",
+							root_path.to_string_lossy (),
+							error)),
+
+			}
+
+		}
+
+		Some (ScanGeneration {
+			filesystem_uuid: subvolume.filesystem_uuid,
+			subvolume_id: subvolume.subvolume_id,
+			path: relative_path,
+			generation: generation,
+			filter_fingerprint: self.filter_fingerprint,
+		})
 
 	}
 
@@ -175,6 +496,8 @@ impl <'a> DirectoryScanner <'a> {
 		directory: PathRef,
 		root_path: PathRef,
 		device_id: u64,
+		subvolume: Option <Subvolume>,
+		scan_all: bool,
 	) -> Result <(), String> {
 
 		if (
@@ -188,6 +511,31 @@ impl <'a> DirectoryScanner <'a> {
 		let directory =
 			directory.as_ref ();
 
+		// rules from an ignore file apply until we leave this directory
+
+		let ignore_file =
+			if self.use_ignore_files {
+
+			IgnoreFile::read (
+				directory,
+			) ?
+
+		} else {
+
+			None
+
+		};
+
+		let ignore_file_pushed =
+			ignore_file.is_some ();
+
+		if let Some (ignore_file) = ignore_file {
+
+			self.ignore_files.push (
+				ignore_file);
+
+		}
+
 		let entry_results: Vec <io::Result <DirEntry>> =
 			try! (
 
@@ -315,13 +663,16 @@ impl <'a> DirectoryScanner <'a> {
 			let entry_file_type =
 				entry_metadata.file_type ();
 
-			let (temp_root_path, temp_device_id) =
+			let (temp_root_path, temp_device_id, temp_subvolume) =
 				if self.root_paths_unordered.contains (
 					& entry.path ()) {
 
 				(
 					Rc::new (entry.path ()),
 					entry_metadata.dev (),
+					find_subvolume (
+						recursive_path_database,
+						& entry.path ()),
 				)
 
 			} else {
@@ -329,143 +680,875 @@ impl <'a> DirectoryScanner <'a> {
 				(
 					root_path.clone (),
 					device_id,
+					subvolume.clone (),
 				)
 
 			};
 
+			let excluded =
+				self.is_excluded (
+					& entry.path (),
+					entry_file_type.is_dir ());
+
+			let other_device =
+				entry_metadata.dev () != temp_device_id;
+
 			if (
 				entry_file_type.is_symlink ()
-				|| entry_metadata.dev () != temp_device_id
+				|| (
+					other_device
+					&& ! (
+						self.cross_subvolumes
+						&& entry_file_type.is_dir ()
+						&& is_nested_subvolume (
+							directory,
+							& entry.path ())
+					)
+				)
 			) {
 
 				// ignore
 
-			} else if entry_file_type.is_dir () {
+			} else if (
+				excluded
+				|| (
+					temp_root_path != root_path
+					&& self.is_skipped (
+						temp_subvolume.as_ref ())
+				)
+			) {
+
+				// forget anything we knew about it, and don't descend
+
+				self.drop_existing (
+					& entry.path ());
+
+			} else if other_device {
+
+				// the top of another subvolume, where a directory which was
+				// moved here is new to the database, just as it would be in
+				// this subvolume
 
-				self.scan_directory_internal (
+				let entry_scan_all =
+					scan_all
+					|| self.is_moved (
+						& entry.path ());
+
+				self.scan_nested_subvolume (
 					output,
 					recursive_path_database,
 					Rc::new (entry.path ()),
 					temp_root_path,
-					temp_device_id,
+					entry_metadata.dev (),
+					entry_scan_all,
 				) ?;
 
+			} else if entry_file_type.is_dir () {
+
+				// a nested root is in another subvolume, or was last scanned
+				// separately, so the changes found for this one don't apply
+
+				let entry_scan_all =
+					scan_all
+					|| temp_root_path != root_path
+					|| self.is_moved (
+						& entry.path ());
+
+				if (
+					! entry_scan_all
+					&& ! self.is_dirty (
+						& entry.path ())
+				) {
+
+					// nothing beneath it has changed since the last scan
+
+					self.keep_existing (
+						& entry.path (),
+						temp_subvolume.as_ref ());
+
+				} else {
+
+					self.scan_directory_internal (
+						output,
+						recursive_path_database,
+						Rc::new (entry.path ()),
+						temp_root_path,
+						temp_device_id,
+						temp_subvolume,
+						entry_scan_all,
+					) ?;
+
+				}
+
 			} else if entry_file_type.is_file () {
 
-				let exists = {
+				self.scan_file (
+					entry_recursive_path,
+					& entry_metadata,
+					& root_path,
+					temp_subvolume);
 
-					let in_next_option =
-						self.in_iterator.peek ();
+			};
 
-					if in_next_option.is_some () {
+			if self.progress % 0x1000 == 0 {
 
-						let in_next =
-							in_next_option.unwrap ();
+				output.status_format (
+					format_args! (
+						"Scanning filesystem: {}",
+						entry.path ().to_string_lossy ()));
 
-						let in_next_path =
-							in_next.path.clone ();
+			}
 
-						in_next_path == entry_recursive_path
+			self.progress += 1;
 
-					} else {
+		}
 
-						false
+		if ignore_file_pushed {
 
-					}
+			self.ignore_files.pop ();
 
-				};
+		}
 
-				if exists {
+		Ok (())
 
-					let mut file_data =
-						self.in_iterator.next ().unwrap ();
+	}
 
-					let changed = (
+	/// Scans the top of another subvolume beneath a root, unless it is read
+	/// only and those are being skipped. The changes found for the subvolume
+	/// above don't apply here, so it gets its own, along with its own scan
+	/// generation.
 
-						entry_metadata.len () !=
-							file_data.size
+	fn scan_nested_subvolume (
+		& mut self,
+		output: & Output,
+		recursive_path_database: & mut RecursivePathDatabase,
+		directory: PathRef,
+		root_path: PathRef,
+		device_id: u64,
+		scan_all: bool,
+	) -> Result <(), String> {
 
-					||
+		let subvolume =
+			find_subvolume (
+				recursive_path_database,
+				& directory);
 
-						entry_metadata.mtime () !=
-							file_data.mtime
+		if self.is_skipped (
+			subvolume.as_ref ()) {
 
-					);
+			self.drop_existing (
+				& directory);
 
-					if changed {
+			return Ok (());
 
-						file_data.size = entry_metadata.len ();
+		}
 
-						file_data.content_hash = ZERO_HASH;
-						file_data.content_hash_time = 0;
+		let outer_incremental_changes =
+			self.incremental_changes.take ();
 
-						file_data.extent_hash = ZERO_HASH;
-						file_data.extent_hash_time = 0;
+		let scan_generation =
+			self.prepare_scan_generation (
+				output,
+				& directory,
+				subvolume.as_ref ());
 
-						file_data.defragment_time = 0;
-						file_data.deduplicate_time = 0;
+		let scan_all =
+			scan_all
+			|| self.incremental_changes.is_none ();
 
-						file_data.mtime = entry_metadata.mtime ();
-						file_data.ctime = entry_metadata.ctime ();
+		let result =
+			self.scan_directory_internal (
+				output,
+				recursive_path_database,
+				directory,
+				root_path,
+				device_id,
+				subvolume,
+				scan_all);
 
-						file_data.mode = entry_metadata.mode ();
-						file_data.uid = entry_metadata.uid ();
-						file_data.gid = entry_metadata.gid ();
+		self.incremental_changes =
+			outer_incremental_changes;
 
-					}
+		result ?;
 
-					self.out_builder.insert (
-						file_data);
+		if let Some (scan_generation) = scan_generation {
 
-				} else {
+			self.new_scan_generations.push (
+				scan_generation);
 
-					self.out_builder.insert (
-						FileData {
+		}
+
+		Ok (())
 
-						path: entry_recursive_path,
-						root_path: Some (root_path.clone ()),
+	}
 
-						size: entry_metadata.len (),
+	/// Brings the database up to date with a single file, forgetting what we
+	/// knew about it if it has changed.
 
-						content_hash: ZERO_HASH,
-						content_hash_time: 0,
+	fn scan_file (
+		& mut self,
+		entry_recursive_path: RecursivePathRef,
+		entry_metadata: & fs::Metadata,
+		root_path: & PathRef,
+		subvolume: Option <Subvolume>,
+	) {
 
-						extent_hash: ZERO_HASH,
-						extent_hash_time: 0,
+		let exists = {
 
-						defragment_time: 0,
-						deduplicate_time: 0,
+			let in_next_option =
+				self.in_iterator.peek ();
 
-						mtime: entry_metadata.mtime (),
-						ctime: entry_metadata.ctime (),
+			if let Some (in_next) = in_next_option {
 
-						mode: entry_metadata.mode (),
-						uid: entry_metadata.uid (),
-						gid: entry_metadata.gid (),
+				let in_next_path =
+					in_next.path.clone ();
 
-					});
+				in_next_path == entry_recursive_path
 
-				}
+			} else {
+
+				false
+
+			}
+
+		};
+
+		if exists {
+
+			let mut file_data =
+				self.in_iterator.next ().unwrap ();
+
+			// inode numbers can only be compared within the same subvolume
+
+			let same_subvolume =
+				match (& file_data.subvolume, & subvolume) {
+
+				(& Some (ref old_subvolume), & Some (ref new_subvolume)) =>
+					old_subvolume.is_same_subvolume (new_subvolume),
+
+				_ =>
+					false,
 
 			};
 
-			if self.progress % 0x1000 == 0 {
+			let changed = (
 
-				output.status_format (
-					format_args! (
-						"Scanning filesystem: {}",
-						entry.path ().to_string_lossy ()));
+				entry_metadata.len () !=
+					file_data.size
+
+			||
+
+				entry_metadata.mtime () !=
+					file_data.mtime
+
+			||
+
+				// replaced by a different file
+
+				(
+					same_subvolume
+					&& file_data.ino != 0
+					&& entry_metadata.ino () != file_data.ino
+				)
+
+			);
+
+			if changed {
+
+				file_data.size = entry_metadata.len ();
+
+				file_data.partial_hash = ZERO_HASH;
+				file_data.partial_hash_time = 0;
+
+				file_data.content_hash = ZERO_HASH;
+				file_data.content_hash_time = 0;
+
+				file_data.extent_hash = ZERO_HASH;
+				file_data.extent_hash_time = 0;
+
+				file_data.defragment_time = 0;
+				file_data.deduplicate_time = 0;
+				file_data.verify_time = 0;
+				file_data.chunk_table = None;
+
+				file_data.mtime = entry_metadata.mtime ();
+				file_data.ctime = entry_metadata.ctime ();
+
+				file_data.mode = entry_metadata.mode ();
+				file_data.uid = entry_metadata.uid ();
+				file_data.gid = entry_metadata.gid ();
+
+			}
+
+			file_data.subvolume =
+				subvolume;
+
+			file_data.ino = entry_metadata.ino ();
+			file_data.nlink = entry_metadata.nlink ();
+
+			self.out_builder.insert (
+				file_data);
+
+		} else {
+
+			self.out_builder.insert (
+				FileData {
+
+				path: entry_recursive_path,
+				root_path: Some (root_path.clone ()),
+				subvolume: subvolume,
+
+				size: entry_metadata.len (),
+
+				content_hash_algorithm: self.content_hash_algorithm,
+
+				partial_hash: ZERO_HASH,
+				partial_hash_time: 0,
+				partial_hash_needed: false,
+
+				content_hash: ZERO_HASH,
+				content_hash_time: 0,
+				content_hash_needed: false,
+
+				extent_hash: ZERO_HASH,
+				extent_hash_time: 0,
+				extent_hash_version: 0,
+
+				defragment_time: 0,
+				deduplicate_time: 0,
+				verify_time: 0,
+
+				group_size: 0,
+				group_shared: 0,
+
+				chunk_table: None,
+
+				mtime: entry_metadata.mtime (),
+				ctime: entry_metadata.ctime (),
+
+				mode: entry_metadata.mode (),
+				uid: entry_metadata.uid (),
+				gid: entry_metadata.gid (),
+
+				ino: entry_metadata.ino (),
+				nlink: entry_metadata.nlink (),
+
+			});
+
+		}
+
+	}
+
+	fn is_moved (
+		& self,
+		path: & Path,
+	) -> bool {
+
+		self.incremental_changes.as_ref ().map (
+			|incremental_changes|
+
+			incremental_changes.moved_paths.contains (
+				path)
+
+		).unwrap_or (true)
+
+	}
+
+	/// Whether anything beneath a directory has changed, including another
+	/// root, or another subvolume when crossing them, which will need to be
+	/// scanned.
+
+	fn is_dirty (
+		& self,
+		path: & Path,
+	) -> bool {
+
+		self.incremental_changes.as_ref ().map (
+			|incremental_changes|
+
+			incremental_changes.dirty_paths.contains (
+				path)
+
+		).unwrap_or (true)
+
+		|| self.root_paths.iter ().any (
+			|root_path|
+
+			root_path.starts_with (
+				path)
+
+		)
+
+		|| self.subvolume_paths.iter ().any (
+			|subvolume_path|
+
+			subvolume_path.starts_with (
+				path)
+
+		)
+
+	}
+
+	/// Whether everything in a subvolume should be left out, because it is read
+	/// only and those are being skipped.
+
+	fn is_skipped (
+		& self,
+		subvolume: Option <& Subvolume>,
+	) -> bool {
+
+		self.skip_readonly_subvolumes
+		&& subvolume.map (
+			|subvolume|
+			subvolume.read_only
+		).unwrap_or (false)
+
+	}
+
+	/// Scans a single path which has changed, as a directory scan would have
+	/// if it had come across it, applying the ignore files in the directories
+	/// above it.
+
+	fn scan_changed_path (
+		& mut self,
+		output: & Output,
+		recursive_path_database: & mut RecursivePathDatabase,
+		changed_recursive_path: RecursivePathRef,
+		root_path: PathRef,
+	) -> Result <(), String> {
+
+		let changed_path =
+			changed_recursive_path.to_path ();
+
+		let metadata =
+			match fs::symlink_metadata (
+				& changed_path) {
+
+			Ok (metadata) =>
+				metadata,
+
+			Err (ref error)
+			if error.kind () == io::ErrorKind::NotFound => {
+
+				self.drop_existing (
+					& changed_path);
+
+				return Ok (());
+
+			},
+
+			Err (error) =>
+				return Err (
+					format! (
+						"Error reading metadata for: {}: {}",
+						changed_path.to_string_lossy (),
+						error)),
+
+		};
+
+		let root_metadata =
+			try! (
+
+			fs::symlink_metadata (
+				root_path.as_ref (),
+			).map_err (
+				|error|
+
+				format! (
+					"Error reading metadata for: {}: {}",
+					root_path.to_string_lossy (),
+					error)
+
+			)
+
+		);
+
+		// paths in other subvolumes are only scanned when crossing them, and
+		// never those on other filesystems
+
+		let subvolume =
+			find_subvolume (
+				recursive_path_database,
+				& changed_path);
+
+		if metadata.dev () != root_metadata.dev () {
+
+			let root_subvolume =
+				find_subvolume (
+					recursive_path_database,
+					& root_path);
+
+			let nested =
+				match (subvolume.as_ref (), root_subvolume) {
+
+				(Some (subvolume), Some (root_subvolume)) =>
+					subvolume.filesystem_uuid == root_subvolume.filesystem_uuid
+					&& subvolume.root_path.to_path ().starts_with (
+						root_path.as_ref ()),
+
+				_ =>
+					false,
+
+			};
+
+			if ! self.cross_subvolumes || ! nested {
+				return Ok (());
+			}
+
+		}
+
+		// work down from the root, as a directory scan would have, reading
+		// ignore files and checking that nothing above the path is excluded
+
+		let num_ignore_files =
+			self.ignore_files.len ();
+
+		let mut directories: Vec <& Path> =
+			changed_path.parent ().into_iter ().flat_map (
+				|parent|
+				parent.ancestors ()
+			).take_while (
+				|directory|
+
+				directory.starts_with (
+					root_path.as_ref ())
+
+			).collect ();
+
+		directories.reverse ();
+
+		let mut excluded = false;
+
+		for directory in directories {
+
+			if self.is_excluded (
+				directory,
+				true) {
+
+				excluded = true;
+				break;
 
 			}
 
+			if self.use_ignore_files {
+
+				if let Some (ignore_file) =
+					IgnoreFile::read (
+						directory,
+					) ? {
+
+					self.ignore_files.push (
+						ignore_file);
+
+				}
+
+			}
+
+		}
+
+		let file_type =
+			metadata.file_type ();
+
+		let excluded =
+			excluded
+			|| self.is_excluded (
+				& changed_path,
+				file_type.is_dir ());
+
+		let result =
+			if (
+				excluded
+				|| file_type.is_symlink ()
+				|| self.is_skipped (
+					subvolume.as_ref ())
+			) {
+
+			self.drop_existing (
+				& changed_path);
+
+			Ok (())
+
+		} else if file_type.is_dir () {
+
+			self.scan_directory_internal (
+				output,
+				recursive_path_database,
+				Rc::new (changed_path),
+				root_path,
+				metadata.dev (),
+				subvolume,
+				true)
+
+		} else if file_type.is_file () {
+
+			self.scan_file (
+				changed_recursive_path,
+				& metadata,
+				& root_path,
+				subvolume);
+
+			// it may have replaced a directory
+
+			self.drop_existing (
+				& changed_path);
+
 			self.progress += 1;
 
+			Ok (())
+
+		} else {
+
+			Ok (())
+
+		};
+
+		self.ignore_files.truncate (
+			num_ignore_files);
+
+		result
+
+	}
+
+	/// Whether a path is excluded by the filter or an ignore file. Root paths
+	/// are never excluded.
+
+	fn is_excluded (
+		& self,
+		path: & Path,
+		is_directory: bool,
+	) -> bool {
+
+		! self.root_paths_unordered.contains (
+			& path.to_path_buf ())
+
+		&& (
+
+			self.path_filter.is_excluded (
+				path,
+				is_directory)
+
+			|| is_ignored (
+				& self.ignore_files,
+				path,
+				is_directory)
+
+		)
+
+	}
+
+	/// Carries everything we knew about before a path over unchanged.
+
+	fn keep_before (
+		& mut self,
+		path: & RecursivePathRef,
+	) {
+
+		loop {
+
+			let matches = {
+
+				let in_next_option =
+					self.in_iterator.peek ();
+
+				if in_next_option.is_none () {
+					break;
+				}
+
+				in_next_option.unwrap ().path < * path
+
+			};
+
+			if ! matches {
+				break;
+			}
+
+			self.out_builder.insert (
+				self.in_iterator.next ().unwrap ());
+
 		}
 
-		Ok (())
+	}
+
+	/// Carries everything we knew about beneath a directory over unchanged,
+	/// apart from whether its subvolume is read only, which can change without
+	/// anything in it changing.
+
+	fn keep_existing (
+		& mut self,
+		path: & Path,
+		subvolume: Option <& Subvolume>,
+	) {
+
+		loop {
+
+			let matches = {
+
+				let in_next_option =
+					self.in_iterator.peek ();
+
+				if in_next_option.is_none () {
+					break;
+				}
+
+				in_next_option.unwrap ().path.to_path ().starts_with (
+					path)
+
+			};
+
+			if ! matches {
+				break;
+			}
+
+			let mut file_data =
+				self.in_iterator.next ().unwrap ();
+
+			if let (Some (existing_subvolume), Some (subvolume)) =
+				(file_data.subvolume.as_mut (), subvolume) {
+
+				if (
+					existing_subvolume.filesystem_uuid
+						== subvolume.filesystem_uuid
+					&& existing_subvolume.subvolume_id
+						== subvolume.subvolume_id
+				) {
+
+					existing_subvolume.read_only =
+						subvolume.read_only;
+
+				}
+
+			}
+
+			self.out_builder.insert (
+				file_data);
+
+		}
+
+	}
+
+	fn drop_existing (
+		& mut self,
+		path: & Path,
+	) {
+
+		loop {
+
+			let matches = {
+
+				let in_next_option =
+					self.in_iterator.peek ();
+
+				if in_next_option.is_none () {
+					break;
+				}
+
+				in_next_option.unwrap ().path.to_path ().starts_with (
+					path)
+
+			};
+
+			if ! matches {
+				break;
+			}
+
+			self.in_iterator.next ();
+
+		}
+
+	}
+
+}
+
+/// Finds the paths of the inodes in a subvolume which have changed since a
+/// generation. Inodes which have been removed since can't be found, but their
+/// directories will have changed too.
+
+fn find_incremental_changes (
+	root_path: & Path,
+	subvolume: & Subvolume,
+	min_generation: u64,
+) -> Result <IncrementalChanges, String> {
+
+	let subvolume_path =
+		subvolume.root_path.to_path ();
+
+	let changed_inodes =
+		linux::find_changed_inodes (
+			root_path,
+			subvolume.subvolume_id,
+			min_generation,
+		) ?;
+
+	let mut dirty_paths: HashSet <PathBuf> =
+		HashSet::new ();
+
+	let mut moved_paths: HashSet <PathBuf> =
+		HashSet::new ();
+
+	for (inode, links_changed) in changed_inodes {
+
+		let inode_paths =
+			match linux::get_inode_paths (
+				& subvolume_path,
+				inode) {
+
+			Ok (inode_paths) => inode_paths,
+			Err (_) => continue,
+
+		};
+
+		for inode_path in inode_paths {
+
+			let path =
+				subvolume_path.join (
+					inode_path);
+
+			// nothing beneath a directory which was moved or created is in
+			// the database there yet, and a changed ignore file can change
+			// what is excluded anywhere beneath its directory
+
+			if links_changed {
+
+				moved_paths.insert (
+					path.clone ());
+
+			}
+
+			if path.file_name () == Some (OsStr::new (IGNORE_FILE_NAME)) {
+
+				if let Some (parent) = path.parent () {
+
+					moved_paths.insert (
+						parent.to_owned ());
+
+				}
+
+			}
+
+			let mut ancestor =
+				Some (path.as_path ());
+
+			while let Some (ancestor_path) = ancestor {
+
+				if ! dirty_paths.insert (
+					ancestor_path.to_owned ()) {
+					break;
+				}
+
+				ancestor =
+					ancestor_path.parent ();
+
+			}
+
+		}
 
 	}
 
+	Ok (IncrementalChanges {
+		dirty_paths: dirty_paths,
+		moved_paths: moved_paths,
+	})
+
 }
 
 // ex: noet ts=4 filetype=rust
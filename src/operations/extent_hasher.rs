@@ -1,26 +1,30 @@
+use std::collections::HashMap;
 use std::collections::HashSet;
-use std::mem;
 use std::path::PathBuf;
 use std::rc::Rc;
-use std::slice;
-
-use btrfs;
-use btrfs::FileExtent;
 
 use output::Output;
 
-use sha2::Digest;
-use sha2::Sha256;
-
 use time;
 
 use database::*;
+use hash_algorithm::*;
+use linux;
+use linux::FileExtent;
 use types::*;
 
+/// Identifies the way extent hashes are calculated. This is stored with each
+/// hash, and any hash with a different version is calculated again. Version
+/// zero is the original format, which hashed the in-memory representation of
+/// each extent.
+
+pub const EXTENT_HASH_VERSION: u32 = 1;
+
 pub struct ExtentHasher <'a> {
 
 	root_paths_set: HashSet <PathRef>,
 
+	hash_algorithm: HashAlgorithm,
 	batch_size: u64,
 
 	file_database: & 'a mut FileDatabase,
@@ -37,6 +41,7 @@ impl <'a> ExtentHasher <'a> {
 
 	pub fn new (
 		root_paths: & 'a [PathRef],
+		hash_algorithm: HashAlgorithm,
 		batch_size: u64,
 		file_database: & 'a mut FileDatabase,
 	) -> ExtentHasher <'a> {
@@ -51,6 +56,7 @@ impl <'a> ExtentHasher <'a> {
 
 			root_paths_set: root_paths_set,
 
+			hash_algorithm: hash_algorithm,
 			batch_size: batch_size,
 
 			file_database: file_database,
@@ -78,6 +84,29 @@ impl <'a> ExtentHasher <'a> {
 
 		let mut size_hashed: u64 = 0;
 
+		// hardlinks share their extents, so each inode is only hashed once,
+		// and the hash is copied to its other links
+
+		let mut hashed_inodes: HashMap <InodeId, (Hash, i64)> =
+			self.file_database.iter ().filter (
+				|file_data|
+
+				file_data.extent_hash_time != 0
+				&& file_data.extent_hash_version == EXTENT_HASH_VERSION
+
+			).filter_map (
+				|file_data|
+
+				file_data.inode_id ().map (
+					|inode_id|
+					(
+						inode_id,
+						(file_data.extent_hash, file_data.extent_hash_time),
+					)
+				)
+
+			).collect ();
+
 		for ref mut file_data
 		in self.file_database.iter_mut () {
 
@@ -102,12 +131,29 @@ impl <'a> ExtentHasher <'a> {
 
 				continue;
 
-			} else if file_data.extent_hash_time != 0 {
+			} else if (
+				file_data.extent_hash_time != 0
+				&& file_data.extent_hash_version == EXTENT_HASH_VERSION
+			) {
 
 				num_fresh += 1;
 
 				continue;
 
+			} else if let Some (& (extent_hash, extent_hash_time)) =
+				file_data.inode_id ().and_then (
+					|inode_id|
+					hashed_inodes.get (& inode_id)) {
+
+				update_extent_hash (
+					file_data,
+					extent_hash,
+					extent_hash_time);
+
+				num_updated += 1;
+
+				continue;
+
 			} else if (
 				num_updated > 0
 				&& size_hashed + file_data.size > self.batch_size
@@ -129,20 +175,24 @@ impl <'a> ExtentHasher <'a> {
 
 				if let Ok (extent_hash) = (
 					calculate_extent_hash_for_file (
-						file_data.path.clone ())
+						file_data.path.clone (),
+						self.hash_algorithm)
 				) {
 
 					let extent_hash =
 						extent_hash.unwrap_or (
 							ZERO_HASH);
 
-					if extent_hash != file_data.extent_hash {
+					update_extent_hash (
+						file_data,
+						extent_hash,
+						extent_hash_time.sec);
 
-						file_data.extent_hash = extent_hash;
-						file_data.extent_hash_time = extent_hash_time.sec;
+					if let Some (inode_id) = file_data.inode_id () {
 
-						file_data.defragment_time = 0;
-						file_data.deduplicate_time = 0;
+						hashed_inodes.insert (
+							inode_id,
+							(file_data.extent_hash, file_data.extent_hash_time));
 
 					}
 
@@ -192,17 +242,51 @@ impl <'a> ExtentHasher <'a> {
 
 }
 
+fn update_extent_hash (
+	file_data: & mut FileData,
+	extent_hash: Hash,
+	extent_hash_time: i64,
+) {
+
+	if file_data.extent_hash_version != EXTENT_HASH_VERSION {
+
+		// the hash is bound to differ, but the extents haven't necessarily
+		// changed
+
+		file_data.extent_hash = extent_hash;
+		file_data.extent_hash_time = extent_hash_time;
+		file_data.extent_hash_version = EXTENT_HASH_VERSION;
+
+	} else if extent_hash != file_data.extent_hash {
+
+		file_data.extent_hash = extent_hash;
+		file_data.extent_hash_time = extent_hash_time;
+
+		file_data.defragment_time = 0;
+		file_data.deduplicate_time = 0;
+
+	}
+
+}
+
+/// Hashes the extent map of a file. Each extent with a physical location is
+/// serialized explicitly, as its logical offset, physical offset and length,
+/// as little endian 64 bit integers, followed by its flags as a little endian
+/// 32 bit integer. The shared flag is left out, since it changes whenever
+/// another file or snapshot starts or stops referring to the same data.
+
 pub fn calculate_extent_hash_for_file (
 	path: RecursivePathRef,
+	hash_algorithm: HashAlgorithm,
 ) -> Result <Option <Hash>, String> {
 
 	let file_extents =
-		btrfs::get_file_extent_map_for_path (
-			path.to_path (),
+		linux::get_file_extent_map (
+			& path.to_path (),
 		) ?;
 
 	let mut hasher =
-		Sha256::new ();
+		hash_algorithm.new_hash_function ();
 
 	let mut physical_extents: u64 = 0;
 
@@ -212,16 +296,9 @@ pub fn calculate_extent_hash_for_file (
 			continue;
 		}
 
-		let file_extent_pointer =
-			file_extent as * const FileExtent;
-
 		hasher.input (
-			unsafe {
-				slice::from_raw_parts (
-					file_extent_pointer as * const u8,
-					mem::size_of::<FileExtent> ())
-			}
-		);
+			& encode_extent (
+				file_extent));
 
 		physical_extents += 1;
 
@@ -229,13 +306,7 @@ pub fn calculate_extent_hash_for_file (
 
 	if physical_extents > 0 {
 
-		let mut result: Hash =
-			[0u8; HASH_SIZE];
-
-		result.copy_from_slice (
-			& hasher.result ());
-
-		Ok (Some (result))
+		Ok (Some (hasher.result ()))
 
 	} else {
 
@@ -245,4 +316,99 @@ pub fn calculate_extent_hash_for_file (
 
 }
 
+/// Encodes an extent for hashing, as little-endian logical, physical and
+/// length, followed by the flags. The shared flag is left out, since it
+/// changes whenever some other file shares the extent.
+
+fn encode_extent (
+	file_extent: & FileExtent,
+) -> [u8; 28] {
+
+	let mut encoded =
+		[0u8; 28];
+
+	let flags =
+		file_extent.flags & ! linux::FIEMAP_EXTENT_SHARED;
+
+	for index in 0 .. 8 {
+
+		encoded [index] =
+			(file_extent.logical >> (index * 8)) as u8;
+
+		encoded [8 + index] =
+			(file_extent.physical >> (index * 8)) as u8;
+
+		encoded [16 + index] =
+			(file_extent.length >> (index * 8)) as u8;
+
+	}
+
+	for index in 0 .. 4 {
+
+		encoded [24 + index] =
+			(flags >> (index * 8)) as u8;
+
+	}
+
+	encoded
+
+}
+
+#[ cfg (test) ]
+mod tests {
+
+	use super::*;
+
+	#[ test ]
+	fn encode_extent_layout () {
+
+		let file_extent =
+			FileExtent {
+				logical: 0x0807060504030201,
+				physical: 0x1817161514131211,
+				length: 0x2827262524232221,
+				flags: linux::FIEMAP_EXTENT_ENCODED | 0x31000000,
+			};
+
+		assert_eq! (
+			& encode_extent (& file_extent) [..],
+			& [
+				0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+				0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18,
+				0x21, 0x22, 0x23, 0x24, 0x25, 0x26, 0x27, 0x28,
+				0x08, 0x00, 0x00, 0x31,
+			] [..]);
+
+	}
+
+	#[ test ]
+	fn encode_extent_ignores_shared () {
+
+		let file_extent =
+			FileExtent {
+				logical: 0,
+				physical: 0x100000,
+				length: 0x1000,
+				flags: linux::FIEMAP_EXTENT_LAST,
+			};
+
+		let shared_file_extent =
+			FileExtent {
+				flags: linux::FIEMAP_EXTENT_LAST
+					| linux::FIEMAP_EXTENT_SHARED,
+				.. file_extent
+			};
+
+		assert_eq! (
+			encode_extent (& file_extent),
+			encode_extent (& shared_file_extent));
+
+		assert_eq! (
+			encode_extent (& file_extent) [24],
+			linux::FIEMAP_EXTENT_LAST as u8);
+
+	}
+
+}
+
 // ex: noet ts=4 filetype=rust
@@ -0,0 +1,52 @@
+use std::path::Path;
+
+use linux;
+use linux::FileExtent;
+
+/// Reads the extents of a file which have data on disk, leaving out holes and
+/// anything else without a physical location.
+
+pub fn get_data_extents (
+	path: & Path,
+) -> Result <Vec <FileExtent>, String> {
+
+	Ok (
+		linux::get_file_extent_map (
+			path,
+		) ?.into_iter ().filter (
+			|file_extent|
+			file_extent.physical != 0
+		).collect ()
+	)
+
+}
+
+/// Works out if a file already refers to exactly the same data on disk as the
+/// source it would be deduplicated against. Every extent must be flagged as
+/// shared, and must match the source's extent at the same logical offset in
+/// both physical location and length.
+
+pub fn shares_extents (
+	source_extents: & [FileExtent],
+	file_extents: & [FileExtent],
+) -> bool {
+
+	! file_extents.is_empty ()
+
+	&& file_extents.len () == source_extents.len ()
+
+	&& file_extents.iter ().zip (
+		source_extents.iter (),
+	).all (
+		|(file_extent, source_extent)|
+
+		file_extent.is_shared ()
+		&& file_extent.logical == source_extent.logical
+		&& file_extent.physical == source_extent.physical
+		&& file_extent.length == source_extent.length
+
+	)
+
+}
+
+// ex: noet ts=4 filetype=rust
@@ -1,7 +1,8 @@
+use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::collections::HashSet;
-
-use btrfs;
+use std::fs::File;
+use std::path::Path;
 
 use output::Output;
 
@@ -9,6 +10,8 @@ use time;
 
 use arguments::*;
 use database::*;
+use linux;
+use misc::*;
 use types::*;
 
 pub struct FileDeduper {
@@ -17,6 +20,7 @@ pub struct FileDeduper {
 	num_updated: u64,
 	num_remaining: u64,
 	num_errors: u64,
+	num_mismatched: u64,
 }
 
 impl FileDeduper {
@@ -30,10 +34,16 @@ impl FileDeduper {
 			num_updated: 0,
 			num_remaining: 0,
 			num_errors: 0,
+			num_mismatched: 0,
 		}
 
 	}
 
+	/// Deduplicates a batch of files from the dedupe map, removing them from it
+	/// as they are done. Each group is done as a whole, with its source first,
+	/// since defragmenting the source afterwards would undo the sharing with
+	/// its targets, and groups are never split across batches.
+
 	pub fn dedupe_files (
 		& mut self,
 		output: & Output,
@@ -47,6 +57,7 @@ impl FileDeduper {
 		let mut num_remaining = 0;
 		let mut num_updated = 0;
 		let mut num_errors = 0;
+		let mut num_mismatched = 0;
 
 		let mut size_deduped: u64 = 0;
 
@@ -56,8 +67,13 @@ impl FileDeduper {
 				root_path.clone ()
 			).collect ();
 
-		for ref mut file_data
-		in file_database.iter_mut () {
+		// find the files which need deduplicating
+
+		let mut file_indices: HashMap <RecursivePathRef, usize> =
+			HashMap::new ();
+
+		for (file_index, file_data)
+		in file_database.iter ().enumerate () {
 
 			if (
 
@@ -78,81 +94,104 @@ impl FileDeduper {
 
 				num_ignored += 1;
 
-				continue;
-
 			} else if ! dedupe_map.contains_key (
 				file_data.path.as_ref ()) {
 
 				num_fresh += 1;
 
-				continue;
+			} else {
 
-			} else if (
-				num_updated > 0
-				&& size_deduped + file_data.size > arguments.dedupe_batch_size
-			) {
+				file_indices.insert (
+					file_data.path.clone (),
+					file_index);
+
+			}
+
+		}
+
+		// group them by their source
 
-				num_remaining += 1;
+		let mut dedupe_groups: BTreeMap <
+			RecursivePathRef,
+			Vec <RecursivePathRef>,
+		> = BTreeMap::new ();
+
+		for (target_path, source_path) in dedupe_map.iter () {
+
+			if ! file_indices.contains_key (
+				target_path) {
 
 				continue;
 
-			} else {
+			}
+
+			dedupe_groups.entry (
+				source_path.clone (),
+			).or_insert_with (
+				|| Vec::new (),
+			).push (
+				target_path.clone ());
+
+		}
 
-				let deduplicate_time =
-					time::get_time ();
+		for (source_path, mut target_paths) in dedupe_groups {
 
-				let target_path =
-					dedupe_map.get (
-						file_data.path.as_ref (),
-					).unwrap ().clone ();
+			// the source comes first, then the targets in path order
 
-				let success =
-					if * target_path == * file_data.path {
+			target_paths.sort_by_key (
+				|target_path|
 
-					output.status_format (
-						format_args! (
-							"Defragment: {}",
-							file_data.path.to_string_lossy ()));
+				(
+					* target_path != source_path,
+					target_path.clone (),
+				)
+
+			);
+
+			let group_size: u64 =
+				target_paths.iter ().map (
+					|target_path|
+					file_database [file_indices [target_path]].size
+				).sum ();
+
+			if (
+				size_deduped > 0
+				&& size_deduped + group_size > arguments.dedupe_batch_size
+			) {
 
-					btrfs::defragment_file (
-						file_data.path.to_path (),
-						1,
-						btrfs::CompressionType::Lzo,
-						true,
-					).is_ok ()
+				num_remaining += target_paths.len () as u64;
 
-				} else {
+				continue;
 
-					output.status_format (
-						format_args! (
-							"Deduplicate: {} -> {}",
-							file_data.path.to_string_lossy (),
-							target_path.to_string_lossy ()));
+			}
 
-					btrfs::deduplicate_files_with_source (
-						target_path.to_path (),
-						& vec! [ file_data.path.to_path () ],
-					).is_ok ()
+			for target_path in target_paths {
 
-				};
+				let file_data =
+					& mut file_database [file_indices [& target_path]];
 
 				dedupe_map.remove (
-					file_data.path.as_ref ());
+					& target_path);
 
-				file_data.extent_hash = ZERO_HASH;
-				file_data.extent_hash_time = 0;
+				match dedupe_file (
+					output,
+					arguments,
+					file_data,
+					& source_path) {
 
-				file_data.defragment_time = 0;
-				file_data.deduplicate_time = deduplicate_time.sec;
+					DedupeResult::Updated =>
+						num_updated += 1,
 
-				size_deduped += file_data.size;
+					DedupeResult::Error =>
+						num_errors += 1,
+
+					DedupeResult::Mismatched =>
+						num_mismatched += 1,
 
-				if success {
-					num_updated += 1;
-				} else {
-					num_errors += 1;
 				}
 
+				size_deduped += file_data.size;
+
 			}
 
 		}
@@ -162,6 +201,7 @@ impl FileDeduper {
 		self.num_remaining = num_remaining;
 		self.num_updated += num_updated;
 		self.num_errors += num_errors;
+		self.num_mismatched += num_mismatched;
 
 		output.clear_status ();
 
@@ -185,6 +225,276 @@ impl FileDeduper {
 		self.num_errors
 	}
 
+	pub fn num_mismatched (& self) -> u64 {
+		self.num_mismatched
+	}
+
+}
+
+enum DedupeResult {
+	Updated,
+	Error,
+	Mismatched,
+}
+
+/// Deduplicates a single file against its source, or defragments it if it is
+/// the source, and records what was done.
+
+fn dedupe_file (
+	output: & Output,
+	arguments: & Arguments,
+	file_data: & mut FileData,
+	source_path: & RecursivePathRef,
+) -> DedupeResult {
+
+	let deduplicate_time =
+		time::get_time ();
+
+	let mut defragmented = false;
+	let mut verified = false;
+	let mut mismatched = false;
+
+	let success =
+		if * source_path == file_data.path {
+
+		match defragment_source (
+			output,
+			arguments,
+			& file_data.path.to_path (),
+		) {
+
+			Ok (performed) => {
+				defragmented = performed;
+				true
+			},
+
+			Err (_) =>
+				false,
+
+		}
+
+	} else {
+
+		// weak hashes always need verifying, strong ones only if
+		// it was asked for
+
+		let verify_result =
+			if (
+				arguments.verify_before_dedupe
+				|| ! arguments.content_hash_algorithm
+					.is_cryptographic ()
+			) {
+
+			output.status_format (
+				format_args! (
+					"Verify: {} -> {}",
+					file_data.path.to_string_lossy (),
+					source_path.to_string_lossy ()));
+
+			compare_file_contents (
+				& source_path.to_path (),
+				& file_data.path.to_path (),
+			).map (
+				|identical|
+				Some (identical)
+			)
+
+		} else {
+
+			Ok (None)
+
+		};
+
+		match verify_result {
+
+			Ok (Some (false)) => {
+
+				output.message_format (
+					format_args! (
+						"Contents differ despite matching hashes: \
+						{} -> {}",
+						file_data.path.to_string_lossy (),
+						source_path.to_string_lossy ()));
+
+				mismatched = true;
+
+				false
+
+			},
+
+			Err (error) => {
+
+				output.message_format (
+					format_args! (
+						"Error verifying {} -> {}: {}",
+						file_data.path.to_string_lossy (),
+						source_path.to_string_lossy (),
+						error));
+
+				false
+
+			},
+
+			Ok (identical) => {
+
+				verified = identical.is_some ();
+
+				output.status_format (
+					format_args! (
+						"Deduplicate: {} -> {}",
+						file_data.path.to_string_lossy (),
+						source_path.to_string_lossy ()));
+
+				linux::deduplicate_files_with_source (
+					source_path.to_path (),
+					& [ file_data.path.to_path () ],
+				).is_ok ()
+
+			},
+
+		}
+
+	};
+
+	file_data.extent_hash = ZERO_HASH;
+	file_data.extent_hash_time = 0;
+
+	file_data.defragment_time =
+		if defragmented {
+			deduplicate_time.sec
+		} else {
+			0
+		};
+
+	file_data.deduplicate_time = deduplicate_time.sec;
+
+	if verified {
+		file_data.verify_time = deduplicate_time.sec;
+	} else if mismatched {
+		file_data.verify_time = 0;
+	}
+
+	if mismatched {
+		DedupeResult::Mismatched
+	} else if success {
+		DedupeResult::Updated
+	} else {
+		DedupeResult::Error
+	}
+
+}
+
+/// Defragments the source file of a group, according to the mode chosen on
+/// the command line. Returns true if the file was actually defragmented.
+
+fn defragment_source (
+	output: & Output,
+	arguments: & Arguments,
+	path: & Path,
+) -> Result <bool, String> {
+
+	match arguments.defragment_mode {
+
+		DefragmentMode::Never =>
+			return Ok (false),
+
+		DefragmentMode::Always =>
+			(),
+
+		DefragmentMode::IfFragmented => {
+
+			let extents =
+				linux::get_file_extent_map (
+					path,
+				) ?;
+
+			let num_small_extents =
+				extents.iter ().filter (
+					|extent|
+					extent.length < arguments.defragment_extent_threshold as u64
+				).count ();
+
+			if num_small_extents < 2 {
+				return Ok (false);
+			}
+
+		},
+
+	}
+
+	output.status_format (
+		format_args! (
+			"Defragment: {}",
+			path.to_string_lossy ()));
+
+	linux::defragment_file (
+		path,
+		arguments.defragment_extent_threshold,
+		arguments.defragment_compression,
+		true,
+	) ?;
+
+	Ok (true)
+
+}
+
+/// Compares the contents of two files byte for byte.
+
+pub fn compare_file_contents (
+	left_path: & Path,
+	right_path: & Path,
+) -> Result <bool, String> {
+
+	let mut left_file =
+		io_result (
+			File::open (
+				left_path),
+		) ?;
+
+	let mut right_file =
+		io_result (
+			File::open (
+				right_path),
+		) ?;
+
+	let mut left_buffer: Vec <u8> =
+		vec! [0u8; COMPARE_BUFFER_SIZE];
+
+	let mut right_buffer: Vec <u8> =
+		vec! [0u8; COMPARE_BUFFER_SIZE];
+
+	loop {
+
+		let left_bytes_read =
+			io_result (
+				read_fully (
+					& mut left_file,
+					& mut left_buffer),
+			) ?;
+
+		let right_bytes_read =
+			io_result (
+				read_fully (
+					& mut right_file,
+					& mut right_buffer),
+			) ?;
+
+		if (
+			left_bytes_read != right_bytes_read
+			|| left_buffer [0 .. left_bytes_read]
+				!= right_buffer [0 .. right_bytes_read]
+		) {
+			return Ok (false);
+		}
+
+		if left_bytes_read == 0 {
+			return Ok (true);
+		}
+
+	}
+
 }
 
+const COMPARE_BUFFER_SIZE: usize = 0x40000;
+
 // ex: noet ts=4 filetype=rust
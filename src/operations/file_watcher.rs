@@ -0,0 +1,365 @@
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::Instant;
+
+use linux::*;
+use operations::*;
+use types::*;
+
+const WATCH_MASK: u32 =
+	IN_ATTRIB | IN_CLOSE_WRITE | IN_CREATE | IN_DELETE
+	| IN_MOVED_FROM | IN_MOVED_TO;
+
+/// Changes are collected for at most this many times the debounce time, so
+/// that files which never stop changing don't hold up everything else.
+
+const MAXIMUM_BATCH_DEBOUNCES: u32 = 6;
+
+/// Watches every directory beneath the root paths with inotify, and collects
+/// the paths which have changed, so that only these need to be scanned again.
+/// Directories excluded by the path filter, or on other devices, aren't
+/// watched, just as they aren't scanned, apart from other subvolumes when
+/// crossing them.
+
+pub struct FileWatcher <'a> {
+	root_paths: & 'a [PathRef],
+	path_filter: & 'a PathFilter,
+	cross_subvolumes: bool,
+	skip_readonly_subvolumes: bool,
+	inotify: Inotify,
+	watched_directories: HashMap <i32, PathBuf>,
+}
+
+impl <'a> FileWatcher <'a> {
+
+	pub fn new (
+		root_paths: & 'a [PathRef],
+		path_filter: & 'a PathFilter,
+		cross_subvolumes: bool,
+		skip_readonly_subvolumes: bool,
+	) -> Result <FileWatcher <'a>, String> {
+
+		Ok (FileWatcher {
+			root_paths: root_paths,
+			path_filter: path_filter,
+			cross_subvolumes: cross_subvolumes,
+			skip_readonly_subvolumes: skip_readonly_subvolumes,
+			inotify: try! (Inotify::new ()),
+			watched_directories: HashMap::new (),
+		})
+
+	}
+
+	pub fn num_watched_directories (
+		& self,
+	) -> usize {
+		self.watched_directories.len ()
+	}
+
+	/// Watches every directory beneath the root paths. Directories which are
+	/// already watched keep their watches.
+
+	pub fn watch_root_paths (
+		& mut self,
+	) -> Result <(), String> {
+
+		for root_path in self.root_paths.iter () {
+
+			let metadata =
+				try! (
+
+				fs::symlink_metadata (
+					root_path.as_ref (),
+				).map_err (
+					|error|
+
+					format! (
+						"Error reading metadata for: {}: {}",
+						root_path.to_string_lossy (),
+						error)
+
+				)
+
+			);
+
+			try! (
+				self.watch_directory (
+					root_path,
+					metadata.dev ()));
+
+		}
+
+		Ok (())
+
+	}
+
+	/// Waits for something to change, then keeps collecting changes until
+	/// there have been none for the debounce time, and returns the paths which
+	/// changed, in order. If the kernel dropped any events, the root paths are
+	/// returned, so that everything is scanned again.
+
+	pub fn wait_for_changes (
+		& mut self,
+		debounce_time: Duration,
+	) -> Result <Vec <PathBuf>, String> {
+
+		let mut changed_paths: BTreeSet <PathBuf> =
+			BTreeSet::new ();
+
+		let mut events =
+			try! (
+				self.inotify.read_events (
+					None));
+
+		let batch_deadline =
+			Instant::now () + debounce_time * MAXIMUM_BATCH_DEBOUNCES;
+
+		while ! events.is_empty () {
+
+			for event in events {
+
+				try! (
+					self.handle_event (
+						event,
+						& mut changed_paths));
+
+			}
+
+			if Instant::now () >= batch_deadline {
+				break;
+			}
+
+			events =
+				try! (
+					self.inotify.read_events (
+						Some (debounce_time)));
+
+		}
+
+		Ok (changed_paths.into_iter ().collect ())
+
+	}
+
+	fn handle_event (
+		& mut self,
+		event: InotifyEvent,
+		changed_paths: & mut BTreeSet <PathBuf>,
+	) -> Result <(), String> {
+
+		if event.mask & IN_Q_OVERFLOW != 0 {
+
+			try! (
+				self.watch_root_paths ());
+
+			changed_paths.extend (
+				self.root_paths.iter ().map (
+					|root_path|
+					root_path.as_ref ().clone ()
+				));
+
+			return Ok (());
+
+		}
+
+		if event.mask & IN_IGNORED != 0 {
+
+			self.watched_directories.remove (
+				& event.watch_descriptor);
+
+			return Ok (());
+
+		}
+
+		let path =
+			match self.watched_directories.get (
+				& event.watch_descriptor) {
+
+			Some (directory) =>
+				match event.name () {
+					Some (name) => directory.join (name),
+					None => directory.clone (),
+				},
+
+			None =>
+				return Ok (()),
+
+		};
+
+		if event.mask & IN_ISDIR != 0 {
+
+			if event.mask & (IN_MOVED_FROM | IN_DELETE) != 0 {
+
+				self.unwatch_directory (
+					& path);
+
+			}
+
+			if event.mask & (IN_CREATE | IN_MOVED_TO) != 0 {
+
+				// anything created in it before it was watched is found when
+				// it is scanned, which happens afterwards
+
+				if let Ok (metadata) =
+					fs::symlink_metadata (
+						& path) {
+
+					try! (
+						self.watch_directory (
+							& path,
+							metadata.dev ()));
+
+				}
+
+			}
+
+		}
+
+		changed_paths.insert (
+			path);
+
+		Ok (())
+
+	}
+
+	/// Watches a directory and everything beneath it on the same device, or
+	/// in nested subvolumes when crossing them. Directories which disappear
+	/// while this is happening are skipped.
+
+	fn watch_directory (
+		& mut self,
+		directory: & Path,
+		device_id: u64,
+	) -> Result <(), String> {
+
+		let watch_descriptor =
+			match self.inotify.add_watch (
+				directory,
+				WATCH_MASK) {
+
+			Ok (watch_descriptor) =>
+				watch_descriptor,
+
+			Err (_) if ! directory.exists () =>
+				return Ok (()),
+
+			Err (error) =>
+				return Err (error),
+
+		};
+
+		self.watched_directories.insert (
+			watch_descriptor,
+			directory.to_owned ());
+
+		let entries =
+			match fs::read_dir (
+				directory) {
+
+			Ok (entries) =>
+				entries,
+
+			Err (ref error)
+			if error.kind () == io::ErrorKind::NotFound =>
+				return Ok (()),
+
+			Err (error) =>
+				return Err (
+					format! (
+						"Error reading directory: {}: {}",
+						directory.to_string_lossy (),
+						error)),
+
+		};
+
+		for entry_result in entries {
+
+			let entry_path =
+				match entry_result {
+					Ok (entry) => entry.path (),
+					Err (_) => continue,
+				};
+
+			let entry_metadata =
+				match fs::symlink_metadata (
+					& entry_path) {
+					Ok (metadata) => metadata,
+					Err (_) => continue,
+				};
+
+			if (
+				! entry_metadata.is_dir ()
+				|| self.path_filter.is_excluded (
+					& entry_path,
+					true)
+			) {
+				continue;
+			}
+
+			if entry_metadata.dev () != device_id && (
+				! self.cross_subvolumes
+				|| ! is_nested_subvolume (
+					directory,
+					& entry_path)
+				|| (
+					self.skip_readonly_subvolumes
+					&& get_subvolume_read_only (
+						& entry_path,
+					).unwrap_or (false)
+				)
+			) {
+				continue;
+			}
+
+			try! (
+				self.watch_directory (
+					& entry_path,
+					entry_metadata.dev ()));
+
+		}
+
+		Ok (())
+
+	}
+
+	/// Stops watching a directory which has gone, and everything beneath it.
+	/// Their watches would otherwise report events under the old paths if
+	/// they were moved.
+
+	fn unwatch_directory (
+		& mut self,
+		directory: & Path,
+	) {
+
+		let watch_descriptors: Vec <i32> =
+			self.watched_directories.iter ().filter (
+				|& (_, watched_directory)|
+
+				watched_directory.starts_with (
+					directory)
+
+			).map (
+				|(& watch_descriptor, _)|
+				watch_descriptor
+			).collect ();
+
+		for watch_descriptor in watch_descriptors {
+
+			self.inotify.remove_watch (
+				watch_descriptor);
+
+			self.watched_directories.remove (
+				& watch_descriptor);
+
+		}
+
+	}
+
+}
+
+// ex: noet ts=4 filetype=rust
@@ -0,0 +1,178 @@
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::mpsc;
+use std::sync::mpsc::Receiver;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::thread::JoinHandle;
+
+use hash_algorithm::*;
+use types::*;
+
+pub type HashJobFunction =
+	fn (& Path, u64, HashAlgorithm) -> Result <Hash, String>;
+
+/// A file to be hashed by a worker thread. Only plain paths are sent to the
+/// workers, since the shared path types used by the database can't leave the
+/// main thread.
+
+pub struct HashJob {
+	pub index: usize,
+	pub path: PathBuf,
+	pub size: u64,
+	pub hash_algorithm: HashAlgorithm,
+	pub hash_function: HashJobFunction,
+}
+
+pub struct HashJobResult {
+	pub index: usize,
+	pub path: PathBuf,
+	pub result: Result <Hash, String>,
+}
+
+pub struct HashWorkerPool {
+	job_sender: Option <Sender <HashJob>>,
+	result_receiver: Receiver <HashJobResult>,
+	worker_threads: Vec <JoinHandle <()>>,
+}
+
+impl HashWorkerPool {
+
+	pub fn new (
+		num_threads: usize,
+	) -> HashWorkerPool {
+
+		let (job_sender, job_receiver) =
+			mpsc::channel::<HashJob> ();
+
+		let (result_sender, result_receiver) =
+			mpsc::channel::<HashJobResult> ();
+
+		let job_receiver =
+			Arc::new (
+				Mutex::new (
+					job_receiver));
+
+		let worker_threads: Vec <JoinHandle <()>> =
+			(0 .. num_threads).map (
+				|_|
+
+			{
+
+				let job_receiver =
+					job_receiver.clone ();
+
+				let result_sender =
+					result_sender.clone ();
+
+				thread::spawn (
+					move ||
+
+					hash_worker (
+						job_receiver,
+						result_sender)
+
+				)
+
+			}
+
+		).collect ();
+
+		HashWorkerPool {
+			job_sender: Some (job_sender),
+			result_receiver: result_receiver,
+			worker_threads: worker_threads,
+		}
+
+	}
+
+	/// Hashes all of the provided files, calling the provided function with
+	/// each result as it arrives, in whatever order the workers finish them.
+
+	pub fn hash_files <ResultFunction: FnMut (HashJobResult)> (
+		& self,
+		jobs: Vec <HashJob>,
+		mut result_function: ResultFunction,
+	) {
+
+		let num_jobs =
+			jobs.len ();
+
+		for job in jobs.into_iter () {
+
+			self.job_sender.as_ref ().unwrap ().send (
+				job,
+			).unwrap ();
+
+		}
+
+		for _ in 0 .. num_jobs {
+
+			result_function (
+				self.result_receiver.recv ().unwrap ());
+
+		}
+
+	}
+
+}
+
+impl Drop for HashWorkerPool {
+
+	fn drop (
+		& mut self,
+	) {
+
+		// closing the channel tells the workers to stop
+
+		self.job_sender = None;
+
+		for worker_thread in self.worker_threads.drain (..) {
+
+			worker_thread.join ().unwrap ();
+
+		}
+
+	}
+
+}
+
+fn hash_worker (
+	job_receiver: Arc <Mutex <Receiver <HashJob>>>,
+	result_sender: Sender <HashJobResult>,
+) {
+
+	loop {
+
+		let job_result =
+			job_receiver.lock ().unwrap ().recv ();
+
+		let job =
+			match job_result {
+				Ok (job) => job,
+				Err (_) => break,
+			};
+
+		let result =
+			(job.hash_function) (
+				& job.path,
+				job.size,
+				job.hash_algorithm);
+
+		if result_sender.send (
+			HashJobResult {
+				index: job.index,
+				path: job.path,
+				result: result,
+			},
+		).is_err () {
+			break;
+		}
+
+	}
+
+}
+
+// ex: noet ts=4 filetype=rust
@@ -0,0 +1,407 @@
+use std::fs::File;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::path::Path;
+use std::path::PathBuf;
+
+use glob::MatchOptions;
+use glob::Pattern;
+
+pub const IGNORE_FILE_NAME: & 'static str = ".btrfs-dedupe-ignore";
+
+/// The rules from a single ignore file, which apply to the directory it is in
+/// and everything below it. The syntax follows gitignore: blank lines and lines
+/// starting with `#` are skipped, a leading `!` re-includes a path, a trailing
+/// `/` only matches directories, and a pattern containing any other `/` is
+/// matched against the path relative to the directory, rather than against
+/// the file name.
+
+pub struct IgnoreFile {
+	directory: PathBuf,
+	rules: Vec <IgnoreRule>,
+}
+
+struct IgnoreRule {
+	pattern: Pattern,
+	negated: bool,
+	directory_only: bool,
+	anchored: bool,
+}
+
+impl IgnoreFile {
+
+	pub fn read (
+		directory: & Path,
+	) -> Result <Option <IgnoreFile>, String> {
+
+		let ignore_file_path =
+			directory.join (
+				IGNORE_FILE_NAME);
+
+		let ignore_file =
+			match File::open (
+				& ignore_file_path) {
+
+			Ok (ignore_file) =>
+				ignore_file,
+
+			Err (ref error)
+			if error.kind () == io::ErrorKind::NotFound =>
+				return Ok (None),
+
+			Err (error) =>
+				return Err (
+					format! (
+						"Error reading ignore file: {}: {}",
+						ignore_file_path.to_string_lossy (),
+						error)),
+
+		};
+
+		let mut rules: Vec <IgnoreRule> =
+			Vec::new ();
+
+		for line_result in BufReader::new (ignore_file).lines () {
+
+			let line =
+				try! (
+					line_result.map_err (
+						|error|
+
+						format! (
+							"Error reading ignore file: {}: {}",
+							ignore_file_path.to_string_lossy (),
+							error)
+
+					));
+
+			if let Some (rule) =
+				try! (
+					parse_rule (
+						& line,
+					).map_err (
+						|error|
+
+						format! (
+							"Error in ignore file: {}: {}",
+							ignore_file_path.to_string_lossy (),
+							error)
+
+					)) {
+
+				rules.push (
+					rule);
+
+			}
+
+		}
+
+		Ok (Some (IgnoreFile {
+			directory: directory.to_owned (),
+			rules: rules,
+		}))
+
+	}
+
+	/// Returns `Some (true)` if the last matching rule ignores the path,
+	/// `Some (false)` if it re-includes it, or `None` if no rule matches.
+
+	pub fn matches (
+		& self,
+		path: & Path,
+		is_directory: bool,
+	) -> Option <bool> {
+
+		let relative_path =
+			match path.strip_prefix (
+				& self.directory) {
+
+			Ok (relative_path) =>
+				relative_path,
+
+			Err (_) =>
+				return None,
+
+		};
+
+		self.rules.iter ().rev ().filter (
+			|rule|
+
+			is_directory || ! rule.directory_only
+
+		).find (
+			|rule|
+
+			if rule.anchored {
+
+				rule.pattern.matches_path_with (
+					relative_path,
+					& MatchOptions {
+						case_sensitive: true,
+						require_literal_separator: true,
+						require_literal_leading_dot: false,
+					})
+
+			} else {
+
+				path.file_name ().map (
+					|file_name|
+
+					rule.pattern.matches_path (
+						Path::new (
+							file_name))
+
+				).unwrap_or (false)
+
+			}
+
+		).map (
+			|rule|
+
+			! rule.negated
+
+		)
+
+	}
+
+}
+
+/// Checks a path against a stack of ignore files, ordered from the outermost
+/// directory to the innermost. Rules in deeper directories take precedence.
+
+pub fn is_ignored (
+	ignore_files: & [IgnoreFile],
+	path: & Path,
+	is_directory: bool,
+) -> bool {
+
+	ignore_files.iter ().rev ().filter_map (
+		|ignore_file|
+
+		ignore_file.matches (
+			path,
+			is_directory)
+
+	).next ().unwrap_or (false)
+
+}
+
+fn parse_rule (
+	line: & str,
+) -> Result <Option <IgnoreRule>, String> {
+
+	let mut line =
+		line.trim_right ();
+
+	if line.is_empty () || line.starts_with ('#') {
+		return Ok (None);
+	}
+
+	let negated =
+		line.starts_with ('!');
+
+	// a leading backslash escapes a literal "#" or "!"
+
+	if (
+		negated
+		|| line.starts_with ("\\#")
+		|| line.starts_with ("\\!")
+	) {
+		line = & line [1 .. ];
+	}
+
+	let directory_only =
+		line.ends_with ('/');
+
+	if directory_only {
+		line = & line [0 .. line.len () - 1];
+	}
+
+	let anchored =
+		line.contains ('/');
+
+	if line.starts_with ('/') {
+		line = & line [1 .. ];
+	}
+
+	if line.is_empty () {
+		return Ok (None);
+	}
+
+	let pattern =
+		try! (
+			Pattern::new (
+				line,
+			).map_err (
+				|pattern_error|
+
+				format! (
+					"Invalid pattern: {}: {}",
+					line,
+					pattern_error.msg)
+
+			));
+
+	Ok (Some (IgnoreRule {
+		pattern: pattern,
+		negated: negated,
+		directory_only: directory_only,
+		anchored: anchored,
+	}))
+
+}
+
+#[ cfg (test) ]
+mod tests {
+
+	use std::path::Path;
+
+	use super::*;
+
+	fn parse_ignore_file (
+		directory: & str,
+		lines: & [& str],
+	) -> IgnoreFile {
+
+		IgnoreFile {
+
+			directory: PathBuf::from (directory),
+
+			rules: lines.iter ().filter_map (
+				|line|
+				parse_rule (line).unwrap ()
+			).collect (),
+
+		}
+
+	}
+
+	#[ test ]
+	fn comments_and_blank_lines () {
+
+		let ignore_file =
+			parse_ignore_file ("/data", & [ "", "# comment", "   ", "\\#hash" ]);
+
+		assert_eq! (ignore_file.rules.len (), 1);
+
+		assert_eq! (
+			ignore_file.matches (Path::new ("/data/#hash"), false),
+			Some (true));
+
+		assert_eq! (
+			ignore_file.matches (Path::new ("/data/comment"), false),
+			None);
+
+	}
+
+	#[ test ]
+	fn last_matching_rule_wins () {
+
+		let ignore_file =
+			parse_ignore_file ("/data", & [ "*.log", "!keep.log" ]);
+
+		assert_eq! (
+			ignore_file.matches (Path::new ("/data/a/debug.log"), false),
+			Some (true));
+
+		assert_eq! (
+			ignore_file.matches (Path::new ("/data/a/keep.log"), false),
+			Some (false));
+
+		let ignore_file =
+			parse_ignore_file ("/data", & [ "!keep.log", "*.log" ]);
+
+		assert_eq! (
+			ignore_file.matches (Path::new ("/data/a/keep.log"), false),
+			Some (true));
+
+	}
+
+	#[ test ]
+	fn trailing_slash_only_matches_directories () {
+
+		let ignore_file =
+			parse_ignore_file ("/data", & [ "build/" ]);
+
+		assert_eq! (
+			ignore_file.matches (Path::new ("/data/a/build"), true),
+			Some (true));
+
+		assert_eq! (
+			ignore_file.matches (Path::new ("/data/a/build"), false),
+			None);
+
+	}
+
+	#[ test ]
+	fn slash_anchors_to_directory () {
+
+		let ignore_file =
+			parse_ignore_file ("/data", & [ "/top.txt", "sub/*.txt" ]);
+
+		assert_eq! (
+			ignore_file.matches (Path::new ("/data/top.txt"), false),
+			Some (true));
+
+		assert_eq! (
+			ignore_file.matches (Path::new ("/data/a/top.txt"), false),
+			None);
+
+		assert_eq! (
+			ignore_file.matches (Path::new ("/data/sub/file.txt"), false),
+			Some (true));
+
+		assert_eq! (
+			ignore_file.matches (Path::new ("/data/a/sub/file.txt"), false),
+			None);
+
+		assert_eq! (
+			ignore_file.matches (Path::new ("/data/sub/a/file.txt"), false),
+			None);
+
+	}
+
+	#[ test ]
+	fn paths_outside_directory () {
+
+		let ignore_file =
+			parse_ignore_file ("/data/a", & [ "*" ]);
+
+		assert_eq! (
+			ignore_file.matches (Path::new ("/data/b/file"), false),
+			None);
+
+	}
+
+	#[ test ]
+	fn deeper_ignore_files_take_precedence () {
+
+		let ignore_files = vec! [
+			parse_ignore_file ("/data", & [ "*.iso" ]),
+			parse_ignore_file ("/data/keep", & [ "!*.iso" ]),
+		];
+
+		assert! (
+			is_ignored (
+				& ignore_files,
+				Path::new ("/data/other/disc.iso"),
+				false));
+
+		assert! (
+			! is_ignored (
+				& ignore_files,
+				Path::new ("/data/keep/disc.iso"),
+				false));
+
+		assert! (
+			! is_ignored (
+				& ignore_files,
+				Path::new ("/data/keep/notes.txt"),
+				false));
+
+	}
+
+}
+
+// ex: noet ts=4 filetype=rust
@@ -1,11 +1,33 @@
+mod block_deduper;
+mod chunk_hasher;
+mod content_defined_chunker;
 mod content_hasher;
+mod database_checker;
 mod directory_scanner;
 mod extent_hasher;
+mod extent_sharing;
 mod file_deduper;
+mod file_watcher;
+mod hash_worker_pool;
+mod ignore_file;
+mod path_filter;
+mod source_selector;
+mod subvolume_locator;
 
+pub use self::block_deduper::*;
+pub use self::chunk_hasher::*;
+pub use self::content_defined_chunker::*;
 pub use self::content_hasher::*;
+pub use self::database_checker::*;
 pub use self::directory_scanner::*;
 pub use self::extent_hasher::*;
+pub use self::extent_sharing::*;
 pub use self::file_deduper::*;
+pub use self::file_watcher::*;
+pub use self::hash_worker_pool::*;
+pub use self::ignore_file::*;
+pub use self::path_filter::*;
+pub use self::source_selector::*;
+pub use self::subvolume_locator::*;
 
 // ex: noet ts=4 filetype=rust
@@ -0,0 +1,305 @@
+use std::path::Path;
+
+use glob::MatchOptions;
+use glob::Pattern;
+
+use hash_algorithm::*;
+
+/// Decides which paths are considered during a scan, based on the patterns
+/// given with `--include` and `--exclude`.
+///
+/// A pattern containing a slash is matched against the full path, where `*`
+/// does not match a slash but `**` does. Any other pattern is matched against
+/// the file name alone.
+
+pub struct PathFilter {
+	include_patterns: Vec <Pattern>,
+	exclude_patterns: Vec <Pattern>,
+}
+
+impl PathFilter {
+
+	pub fn new (
+		include_patterns: & [String],
+		exclude_patterns: & [String],
+	) -> Result <PathFilter, String> {
+
+		Ok (PathFilter {
+
+			include_patterns:
+				try! (
+					compile_patterns (
+						include_patterns)),
+
+			exclude_patterns:
+				try! (
+					compile_patterns (
+						exclude_patterns)),
+
+		})
+
+	}
+
+	/// Excluded directories are not scanned at all. Files are excluded if they
+	/// match an exclude pattern, or if include patterns are present and they
+	/// don't match any of them.
+
+	pub fn is_excluded (
+		& self,
+		path: & Path,
+		is_directory: bool,
+	) -> bool {
+
+		if self.exclude_patterns.iter ().any (
+			|pattern|
+			pattern_matches (pattern, path)
+		) {
+			return true;
+		}
+
+		! is_directory
+
+		&& ! self.include_patterns.is_empty ()
+
+		&& ! self.include_patterns.iter ().any (
+			|pattern|
+			pattern_matches (pattern, path)
+		)
+
+	}
+
+	/// Summarises the patterns, along with whether ignore files are read, so
+	/// that a scan can tell whether an earlier one left out the same paths. It
+	/// is stored in the database, so it uses a hash which is the same from one
+	/// build to the next.
+
+	pub fn fingerprint (
+		& self,
+		use_ignore_files: bool,
+	) -> u64 {
+
+		let mut hash_function =
+			HashAlgorithm::Xxh3_128.new_hash_function ();
+
+		for (prefix, patterns) in [
+			(b"+", & self.include_patterns),
+			(b"-", & self.exclude_patterns),
+		] {
+
+			for pattern in patterns.iter () {
+
+				hash_function.input (prefix);
+				hash_function.input (pattern.as_str ().as_bytes ());
+				hash_function.input (b"\0");
+
+			}
+
+		}
+
+		hash_function.input (
+			if use_ignore_files { b"i" } else { b"n" });
+
+		let hash =
+			hash_function.result ();
+
+		hash [0 .. 8].iter ().rev ().fold (
+			0,
+			|fingerprint, & byte|
+			fingerprint << 8 | byte as u64)
+
+	}
+
+}
+
+fn compile_patterns (
+	pattern_strings: & [String],
+) -> Result <Vec <Pattern>, String> {
+
+	pattern_strings.iter ().map (
+		|pattern_string|
+
+		Pattern::new (
+			pattern_string,
+		).map_err (
+			|pattern_error|
+
+			format! (
+				"Invalid pattern: {}: {}",
+				pattern_string,
+				pattern_error.msg)
+
+		)
+
+	).collect ()
+
+}
+
+fn pattern_matches (
+	pattern: & Pattern,
+	path: & Path,
+) -> bool {
+
+	if pattern.as_str ().contains ('/') {
+
+		pattern.matches_path_with (
+			path,
+			& MatchOptions {
+				case_sensitive: true,
+				require_literal_separator: true,
+				require_literal_leading_dot: false,
+			})
+
+	} else {
+
+		path.file_name ().map (
+			|file_name|
+
+			pattern.matches_path (
+				Path::new (
+					file_name))
+
+		).unwrap_or (false)
+
+	}
+
+}
+
+#[ cfg (test) ]
+mod tests {
+
+	use std::path::Path;
+
+	use super::*;
+
+	fn new_path_filter (
+		include_patterns: & [& str],
+		exclude_patterns: & [& str],
+	) -> PathFilter {
+
+		PathFilter::new (
+			& include_patterns.iter ().map (
+				|pattern| pattern.to_string (),
+			).collect::<Vec <String>> (),
+			& exclude_patterns.iter ().map (
+				|pattern| pattern.to_string (),
+			).collect::<Vec <String>> (),
+		).unwrap ()
+
+	}
+
+	#[ test ]
+	fn name_patterns_match_any_directory () {
+
+		let path_filter =
+			new_path_filter (& [], & [ "*.tmp" ]);
+
+		assert! (
+			path_filter.is_excluded (
+				Path::new ("/data/a/b/file.tmp"),
+				false));
+
+		assert! (
+			! path_filter.is_excluded (
+				Path::new ("/data/a.tmp/file"),
+				false));
+
+	}
+
+	#[ test ]
+	fn path_patterns_match_whole_path () {
+
+		let path_filter =
+			new_path_filter (& [], & [ "/data/*/cache" ]);
+
+		assert! (
+			path_filter.is_excluded (
+				Path::new ("/data/a/cache"),
+				true));
+
+		assert! (
+			! path_filter.is_excluded (
+				Path::new ("/data/a/b/cache"),
+				true));
+
+		let path_filter =
+			new_path_filter (& [], & [ "/data/**/cache" ]);
+
+		assert! (
+			path_filter.is_excluded (
+				Path::new ("/data/a/b/cache"),
+				true));
+
+	}
+
+	#[ test ]
+	fn include_patterns_only_apply_to_files () {
+
+		let path_filter =
+			new_path_filter (& [ "*.iso" ], & []);
+
+		assert! (
+			! path_filter.is_excluded (
+				Path::new ("/data/images"),
+				true));
+
+		assert! (
+			! path_filter.is_excluded (
+				Path::new ("/data/images/disc.iso"),
+				false));
+
+		assert! (
+			path_filter.is_excluded (
+				Path::new ("/data/images/notes.txt"),
+				false));
+
+	}
+
+	#[ test ]
+	fn exclude_beats_include () {
+
+		let path_filter =
+			new_path_filter (& [ "*.iso" ], & [ "old-*" ]);
+
+		assert! (
+			path_filter.is_excluded (
+				Path::new ("/data/old-disc.iso"),
+				false));
+
+	}
+
+	#[ test ]
+	fn fingerprint () {
+
+		let path_filter =
+			new_path_filter (& [ "*.iso" ], & [ "old-*" ]);
+
+		assert_eq! (
+			path_filter.fingerprint (true),
+			new_path_filter (& [ "*.iso" ], & [ "old-*" ]).fingerprint (true));
+
+		// the same pattern means something else as an include
+
+		assert! (
+			path_filter.fingerprint (true)
+				!= new_path_filter (& [ "*.iso", "old-*" ], & []).fingerprint (
+					true));
+
+		assert! (
+			path_filter.fingerprint (true)
+				!= path_filter.fingerprint (false));
+
+	}
+
+	#[ test ]
+	fn invalid_pattern () {
+
+		assert! (
+			PathFilter::new (
+				& [ "[".to_string () ],
+				& []).is_err ());
+
+	}
+
+}
+
+// ex: noet ts=4 filetype=rust
@@ -0,0 +1,183 @@
+use std::path::PathBuf;
+
+use database::*;
+use linux;
+
+/// How to choose which file in a group of identical files keeps its extents,
+/// with every other file in the group deduplicated against it.
+
+#[ derive (Clone, Copy, Debug, Eq, PartialEq) ]
+pub enum SourcePolicy {
+	First,
+	FewestExtents,
+	Oldest,
+	MostShared,
+	Uncompressed,
+	Compressed,
+}
+
+impl SourcePolicy {
+
+	pub fn names (
+	) -> & 'static [& 'static str] {
+		& [
+			"first",
+			"fewest-extents",
+			"oldest",
+			"most-shared",
+			"uncompressed",
+			"compressed",
+		]
+	}
+
+	pub fn from_name (
+		name: & str,
+	) -> Option <SourcePolicy> {
+
+		match name {
+			"first" => Some (SourcePolicy::First),
+			"fewest-extents" => Some (SourcePolicy::FewestExtents),
+			"oldest" => Some (SourcePolicy::Oldest),
+			"most-shared" => Some (SourcePolicy::MostShared),
+			"uncompressed" => Some (SourcePolicy::Uncompressed),
+			"compressed" => Some (SourcePolicy::Compressed),
+			_ => None,
+		}
+
+	}
+
+	/// Whether this policy needs to read each file's extents, which takes an
+	/// ioctl per file.
+
+	pub fn reads_extents (
+		& self,
+	) -> bool {
+
+		* self != SourcePolicy::First
+			&& * self != SourcePolicy::Oldest
+
+	}
+
+}
+
+/// Chooses the source for a group of identical files. Files in read only
+/// subvolumes always win, since they can't be deduplicated against anything
+/// else, then files beneath one of the preferred paths, then the policy
+/// decides, and any remaining tie goes to the first file in path order. If
+/// extents may not be read, a policy which needs them leaves every file tied.
+
+pub fn choose_source (
+	policy: SourcePolicy,
+	read_extents: bool,
+	preferred_paths: & [PathBuf],
+	file_database: & FileDatabase,
+	file_data_indices: & [usize],
+) -> usize {
+
+	* file_data_indices.iter ().min_by_key (
+		|& & file_data_index| {
+
+		let ref file_data =
+			file_database [file_data_index];
+
+		let file_path =
+			file_data.path.to_path ();
+
+		let preferred =
+			preferred_paths.iter ().any (
+				|preferred_path|
+
+				file_path.starts_with (
+					preferred_path)
+
+			);
+
+		(
+			! file_data.is_read_only (),
+			! preferred,
+			source_score (
+				policy,
+				read_extents,
+				file_data),
+			file_data_index,
+		)
+
+	}).unwrap ()
+
+}
+
+/// Lower scores are better. Files whose extents can't be read score worst.
+
+fn source_score (
+	policy: SourcePolicy,
+	read_extents: bool,
+	file_data: & FileData,
+) -> i64 {
+
+	match policy {
+
+		SourcePolicy::First =>
+			return 0,
+
+		SourcePolicy::Oldest =>
+			return file_data.ctime,
+
+		_ if ! read_extents =>
+			return 0,
+
+		_ => (),
+
+	}
+
+	let file_extents =
+		match linux::get_file_extent_map (
+			& file_data.path.to_path ()) {
+
+		Ok (file_extents) =>
+			file_extents,
+
+		Err (_) =>
+			return i64::MAX,
+
+	};
+
+	let shared_size: u64 =
+		file_extents.iter ().filter (
+			|file_extent|
+			file_extent.is_shared ()
+		).map (
+			|file_extent|
+			file_extent.length
+		).sum ();
+
+	let encoded_size: u64 =
+		file_extents.iter ().filter (
+			|file_extent|
+			file_extent.is_encoded ()
+		).map (
+			|file_extent|
+			file_extent.length
+		).sum ();
+
+	match policy {
+
+		SourcePolicy::FewestExtents =>
+			file_extents.len () as i64,
+
+		SourcePolicy::MostShared =>
+			- (shared_size as i64),
+
+		SourcePolicy::Uncompressed =>
+			encoded_size as i64,
+
+		SourcePolicy::Compressed =>
+			- (encoded_size as i64),
+
+		SourcePolicy::First | SourcePolicy::Oldest =>
+			unreachable! (),
+
+	}
+
+}
+
+// ex: noet ts=4 filetype=rust
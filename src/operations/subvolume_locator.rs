@@ -0,0 +1,544 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs;
+use std::fs::File;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::os::unix::ffi::OsStringExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::str;
+
+use database::*;
+use linux;
+use types::*;
+
+/// Works out which subvolume a path is in, by looking for the top directory of
+/// the subvolume above it. Returns None for paths which aren't on btrfs, or
+/// where the top of the subvolume isn't visible, for example beneath a bind
+/// mount of a directory inside it.
+
+pub fn find_subvolume (
+	recursive_path_database: & mut RecursivePathDatabase,
+	path: & Path,
+) -> Option <Subvolume> {
+
+	let device_id =
+		match fs::symlink_metadata (path) {
+			Ok (metadata) => metadata.dev (),
+			Err (_) => return None,
+		};
+
+	let mut root_path =
+		path;
+
+	loop {
+
+		let metadata =
+			match fs::symlink_metadata (root_path) {
+				Ok (metadata) => metadata,
+				Err (_) => return None,
+			};
+
+		if metadata.dev () != device_id {
+			return None;
+		}
+
+		if (
+			metadata.is_dir ()
+			&& metadata.ino () == linux::BTRFS_FIRST_FREE_OBJECTID
+		) {
+			break;
+		}
+
+		root_path =
+			root_path.parent () ?;
+
+	}
+
+	subvolume_at (
+		recursive_path_database,
+		root_path)
+
+}
+
+/// Looks up the device each root path is on, which identifies the filesystem
+/// of files beneath it which aren't known to be in any subvolume, since scans
+/// never carry on into other filesystems. Roots which can't be read are left
+/// out.
+
+pub fn root_devices (
+	root_paths: & [PathRef],
+) -> HashMap <PathRef, u64> {
+
+	root_paths.iter ().filter_map (
+		|root_path|
+
+		fs::symlink_metadata (
+			root_path.as_ref (),
+		).ok ().map (
+			|metadata|
+
+			(
+				root_path.clone (),
+				metadata.dev (),
+			)
+
+		)
+
+	).collect ()
+
+}
+
+/// Whether a directory is the top of another subvolume on the same btrfs
+/// filesystem as the directory containing it. Scans which cross subvolumes
+/// carry on into these, but never into other filesystems.
+
+pub fn is_nested_subvolume (
+	parent_path: & Path,
+	path: & Path,
+) -> bool {
+
+	match fs::symlink_metadata (path) {
+
+		Ok (ref metadata)
+		if (
+			metadata.is_dir ()
+			&& metadata.ino () == linux::BTRFS_FIRST_FREE_OBJECTID
+		) =>
+			(),
+
+		_ =>
+			return false,
+
+	}
+
+	match (
+		linux::get_filesystem_uuid (parent_path),
+		linux::get_filesystem_uuid (path),
+	) {
+
+		(Ok (parent_filesystem_uuid), Ok (filesystem_uuid)) =>
+			parent_filesystem_uuid == filesystem_uuid,
+
+		_ =>
+			false,
+
+	}
+
+}
+
+/// Finds where subvolumes are now, so that files recorded along with their
+/// subvolume can be found again after it has been mounted somewhere else. The
+/// results are remembered, and the mount table is only read if a subvolume
+/// isn't where it was last seen.
+
+pub struct SubvolumeLocator {
+	mounted_subvolumes: Option <HashMap <(Uuid, u64), PathBuf>>,
+	subvolume_roots: HashMap <Subvolume, Option <RecursivePathRef>>,
+}
+
+impl SubvolumeLocator {
+
+	pub fn new (
+	) -> SubvolumeLocator {
+
+		SubvolumeLocator {
+			mounted_subvolumes: None,
+			subvolume_roots: HashMap::new (),
+		}
+
+	}
+
+	/// Works out where the top of a subvolume is now. This is where it was
+	/// last seen if it's still there, or otherwise wherever it is mounted.
+	/// Returns None if it can't be found at all.
+
+	pub fn locate (
+		& mut self,
+		recursive_path_database: & mut RecursivePathDatabase,
+		subvolume: & Subvolume,
+	) -> Option <RecursivePathRef> {
+
+		if let Some (root_path) =
+			self.subvolume_roots.get (
+				subvolume) {
+
+			return root_path.clone ();
+
+		}
+
+		let root_path =
+			self.locate_real (
+				recursive_path_database,
+				subvolume);
+
+		self.subvolume_roots.insert (
+			subvolume.clone (),
+			root_path.clone ());
+
+		root_path
+
+	}
+
+	/// Works out where a file is now. Files which aren't in a known subvolume
+	/// are assumed not to have moved. Returns None if the file's subvolume
+	/// can't be found, for example because it isn't mounted, in which case
+	/// nothing can be said about whether the file still exists.
+
+	pub fn current_path (
+		& mut self,
+		recursive_path_database: & mut RecursivePathDatabase,
+		file_data: & FileData,
+	) -> Option <RecursivePathRef> {
+
+		let subvolume =
+			match file_data.subvolume {
+				Some (ref subvolume) => subvolume,
+				None => return Some (file_data.path.clone ()),
+			};
+
+		match self.locate (
+			recursive_path_database,
+			subvolume) {
+
+			Some (ref root_path)
+			if * root_path != subvolume.root_path =>
+				subvolume.relocate (
+					recursive_path_database,
+					& file_data.path,
+					root_path,
+				).map (
+					|(path, _)|
+					path
+				),
+
+			Some (_) =>
+				Some (file_data.path.clone ()),
+
+			None =>
+				None,
+
+		}
+
+	}
+
+	fn relocate (
+		& mut self,
+		recursive_path_database: & mut RecursivePathDatabase,
+		file_data: & FileData,
+	) -> Option <(RecursivePathRef, Subvolume)> {
+
+		let subvolume =
+			match file_data.subvolume {
+				Some (ref subvolume) => subvolume,
+				None => return None,
+			};
+
+		match self.locate (
+			recursive_path_database,
+			subvolume) {
+
+			Some (ref root_path)
+			if * root_path != subvolume.root_path =>
+				subvolume.relocate (
+					recursive_path_database,
+					& file_data.path,
+					root_path),
+
+			_ =>
+				None,
+
+		}
+
+	}
+
+	fn locate_real (
+		& mut self,
+		recursive_path_database: & mut RecursivePathDatabase,
+		subvolume: & Subvolume,
+	) -> Option <RecursivePathRef> {
+
+		if subvolume_at (
+			recursive_path_database,
+			& subvolume.root_path.to_path (),
+		).map (
+			|found_subvolume|
+			found_subvolume.is_same_subvolume (subvolume)
+		).unwrap_or (false) {
+
+			return Some (
+				subvolume.root_path.clone ());
+
+		}
+
+		if self.mounted_subvolumes.is_none () {
+
+			self.mounted_subvolumes =
+				Some (
+					read_mounted_subvolumes (
+						recursive_path_database));
+
+		}
+
+		self.mounted_subvolumes.as_ref ().unwrap ().get (
+			& (subvolume.filesystem_uuid, subvolume.subvolume_id),
+		).and_then (
+			|mount_path|
+
+			recursive_path_database.for_path (
+				mount_path)
+
+		)
+
+	}
+
+}
+
+/// Moves the files in subvolumes which are now mounted somewhere else to their
+/// new paths. Returns the database along with the number of files moved.
+
+pub fn relocate_subvolumes (
+	recursive_path_database: & mut RecursivePathDatabase,
+	root_paths: & [PathRef],
+	subvolume_locator: & mut SubvolumeLocator,
+	file_database: FileDatabase,
+) -> (FileDatabase, u64) {
+
+	let mut relocations: HashMap <RecursivePathRef, (RecursivePathRef, Subvolume)> =
+		HashMap::new ();
+
+	for file_data in file_database.iter () {
+
+		if let Some (relocation) =
+			subvolume_locator.relocate (
+				recursive_path_database,
+				file_data) {
+
+			relocations.insert (
+				file_data.path.clone (),
+				relocation);
+
+		}
+
+	}
+
+	if relocations.is_empty () {
+		return (file_database, 0);
+	}
+
+	let num_relocated =
+		relocations.len () as u64;
+
+	// scan generations are relative to their subvolume, so they still apply
+
+	let scan_generations =
+		file_database.scan_generations ().to_vec ();
+
+	let file_data_list: Vec <FileData> =
+		file_database.into_iter ().map (
+			|mut file_data| {
+
+			if let Some ((path, subvolume)) =
+				relocations.remove (
+					& file_data.path) {
+
+				file_data.path = path;
+				file_data.subvolume = Some (subvolume);
+
+			}
+
+			file_data
+
+		}).collect ();
+
+	let mut file_database =
+		FileDatabase::from_unordered (
+			recursive_path_database,
+			root_paths,
+			file_data_list);
+
+	file_database.set_scan_generations (
+		scan_generations);
+
+	(file_database, num_relocated)
+
+}
+
+fn subvolume_at (
+	recursive_path_database: & mut RecursivePathDatabase,
+	root_path: & Path,
+) -> Option <Subvolume> {
+
+	match fs::symlink_metadata (root_path) {
+
+		Ok (ref metadata)
+		if (
+			metadata.is_dir ()
+			&& metadata.ino () == linux::BTRFS_FIRST_FREE_OBJECTID
+		) =>
+			(),
+
+		_ =>
+			return None,
+
+	}
+
+	let filesystem_uuid =
+		match linux::get_filesystem_uuid (root_path) {
+			Ok (filesystem_uuid) => filesystem_uuid,
+			Err (_) => return None,
+		};
+
+	let subvolume_id =
+		match linux::get_subvolume_id (root_path) {
+			Ok (subvolume_id) => subvolume_id,
+			Err (_) => return None,
+		};
+
+	let read_only =
+		linux::get_subvolume_read_only (
+			root_path,
+		).unwrap_or (false);
+
+	recursive_path_database.for_path (
+		root_path,
+	).map (
+		|root_path|
+
+		Subvolume {
+			filesystem_uuid: filesystem_uuid,
+			subvolume_id: subvolume_id,
+			root_path: root_path,
+			read_only: read_only,
+		}
+
+	)
+
+}
+
+/// Lists the btrfs subvolumes which are mounted at the top, by filesystem UUID
+/// and subvolume ID. Where a subvolume is mounted more than once, the first
+/// mount is used.
+
+fn read_mounted_subvolumes (
+	recursive_path_database: & mut RecursivePathDatabase,
+) -> HashMap <(Uuid, u64), PathBuf> {
+
+	let mut mounted_subvolumes: HashMap <(Uuid, u64), PathBuf> =
+		HashMap::new ();
+
+	let mountinfo_file =
+		match File::open ("/proc/self/mountinfo") {
+			Ok (file) => file,
+			Err (_) => return mounted_subvolumes,
+		};
+
+	for line in BufReader::new (mountinfo_file).lines () {
+
+		let line =
+			match line {
+				Ok (line) => line,
+				Err (_) => break,
+			};
+
+		// the filesystem type follows a separator after the optional fields
+
+		let fields: Vec <& str> =
+			line.split (' ').collect ();
+
+		let filesystem_type =
+			fields.iter ().position (
+				|field|
+				* field == "-"
+			).and_then (
+				|separator_index|
+				fields.get (separator_index + 1)
+			);
+
+		if filesystem_type != Some (& "btrfs") || fields.len () < 5 {
+			continue;
+		}
+
+		let mount_path =
+			unescape_mount_path (
+				fields [4]);
+
+		if let Some (subvolume) =
+			subvolume_at (
+				recursive_path_database,
+				& mount_path) {
+
+			mounted_subvolumes.entry (
+				(subvolume.filesystem_uuid, subvolume.subvolume_id),
+			).or_insert (
+				mount_path);
+
+		}
+
+	}
+
+	mounted_subvolumes
+
+}
+
+/// Mount points in the mount table have spaces and some other characters
+/// written as a backslash followed by three octal digits.
+
+fn unescape_mount_path (
+	escaped_path: & str,
+) -> PathBuf {
+
+	let escaped_bytes =
+		escaped_path.as_bytes ();
+
+	let mut path_bytes: Vec <u8> =
+		Vec::new ();
+
+	let mut index = 0;
+
+	while index < escaped_bytes.len () {
+
+		let octal_value =
+			if escaped_bytes [index] == b'\\' && index + 4 <= escaped_bytes.len () {
+
+			str::from_utf8 (
+				& escaped_bytes [index + 1 .. index + 4],
+			).ok ().and_then (
+				|octal_digits|
+
+				u8::from_str_radix (
+					octal_digits,
+					8,
+				).ok ()
+
+			)
+
+		} else {
+			None
+		};
+
+		match octal_value {
+
+			Some (byte) => {
+				path_bytes.push (byte);
+				index += 4;
+			},
+
+			None => {
+				path_bytes.push (escaped_bytes [index]);
+				index += 1;
+			},
+
+		}
+
+	}
+
+	PathBuf::from (
+		OsString::from_vec (
+			path_bytes))
+
+}
+
+// ex: noet ts=4 filetype=rust
@@ -9,10 +9,26 @@ pub type Hash = [u8; HASH_SIZE];
 
 pub const ZERO_HASH: Hash = [0u8; HASH_SIZE];
 
-#[ derive (Eq, Hash, PartialEq) ]
-pub struct CompareFileMetadata {
-	pub filename: Option <PathBuf>,
-	pub size: u64,
+pub const UUID_SIZE: usize = 16;
+
+pub type Uuid = [u8; UUID_SIZE];
+
+pub const ZERO_UUID: Uuid = [0u8; UUID_SIZE];
+
+/// Identifies an inode by the UUID of its filesystem, the ID of its subvolume,
+/// and its inode number, which is only unique within the subvolume. Unlike the
+/// device number, these stay the same wherever the subvolume is mounted.
+
+pub type InodeId = (Uuid, u64, u64);
+
+/// Identifies the filesystem a file is on, by its UUID where the file is known
+/// to be in a btrfs subvolume, or otherwise by the device of the root path it
+/// was found beneath, which is only meaningful during a single run.
+
+#[ derive (Clone, Copy, Debug, Eq, Hash, PartialEq) ]
+pub enum FilesystemId {
+	Uuid (Uuid),
+	Device (u64),
 }
 
 // ex: noet ts=4 filetype=rust